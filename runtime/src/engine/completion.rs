@@ -0,0 +1,725 @@
+//! Knuth–Bendix completion, to turn a set of definitions into an equivalent confluent one.
+//!
+//! Definitions are read as equations, regardless of their order. Each equation is oriented with
+//! a reduction ordering into a rule that makes expressions smaller, and the critical pairs between
+//! rules are added as new equations, until every critical pair joins.
+//!
+//! While completing, the variables of an equation stand for themselves inside other patterns, so
+//! rewriting works on patterns rather than expressions.
+//!
+//! Reserved words belong to the patterns around variables, so variables don't stand for them,
+//! unless they are inside a group. That reads `if a then x... else y...` like a term, where `x...`
+//! can be `( if b then c else d )` but not `b else c`, which would otherwise make the two sides of
+//! almost any equation equal. For the same reason, a left side that starts inside another one at a
+//! run of its variables, like the middle `x` of `x = x = x`, only shares an argument with it, so
+//! that is not a critical pair.
+//!
+//! Definitions with a `where x != y` guard are equations that only hold while both sides of the
+//! guard differ. Rules only rewrite a pattern with such a guard when it is known to hold, and a
+//! critical pair keeps the guards of both rules, unless they can't hold at all.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use thiserror::Error;
+
+use crate::matching;
+
+use super::{
+    confluence::{bracket, overlaps, rename, stands_for},
+    cost::CostModel,
+    termination::{lpo_greater, optional_cases, Precedence},
+    Definition, Guard, PatternToken, Runtime, Sort, Structure, Token,
+};
+
+/// How many rewrites normalizing a single pattern can take, in case the ordering was not a
+/// reduction ordering after all.
+const MAX_NORMALIZATION_STEPS: usize = 10_000;
+
+/// The ordering that decides which side of an equation becomes the left side of a rule.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ReductionOrder {
+    /// Shorter is smaller, and expressions of the same length are compared token by token. This
+    /// is the same order evaluations use to pick the smallest expression.
+    #[default]
+    ShortLex,
+
+    /// The lexicographic path ordering, starting from this precedence and extending it as needed.
+    Lpo(Precedence),
+}
+
+/// Configuration of [`Runtime::complete`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionOptions {
+    pub order: ReductionOrder,
+
+    /// Give up when there are more rules than this.
+    pub max_rules: usize,
+
+    /// Give up after orienting this many equations.
+    pub max_steps: usize,
+}
+
+impl Default for CompletionOptions {
+    fn default() -> Self {
+        Self {
+            order: ReductionOrder::default(),
+            max_rules: 100,
+            max_steps: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CompletionError {
+    #[error("Can't orient `{0}` in either direction with the reduction order")]
+    Unorientable(String),
+
+    #[error("Gave up after reaching {0} rules")]
+    TooManyRules(usize),
+
+    #[error("Gave up after {0} steps")]
+    TooManySteps(usize),
+
+    #[error("Can't read `{0}` as an equation, since a host function builds its right side")]
+    HostDefinition(String),
+
+    #[error("Can't read `{0}` as an equation, since its guard has to be rewritten to hold")]
+    GuardedDefinition(String),
+
+    #[error("Can't write `{0}` as a definition, since it needs more than one guard")]
+    CombinedGuards(String),
+
+    #[error("Can't read `{0}` as an equation, since it has variables with a sort or wildcards")]
+    RestrictedVariables(String),
+}
+
+type Pattern = Vec<PatternToken>;
+
+/// Two sides that have to be different, like in a `where x != y` guard.
+type Constraint = (Pattern, Pattern);
+
+/// Two patterns that are equal, as long as the sides of every constraint differ.
+type Equation = (Pattern, Pattern, Vec<Constraint>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    lhs: Pattern,
+    rhs: Pattern,
+
+    /// The rule only applies when the sides of each of these differ.
+    constraints: Vec<Constraint>,
+
+    /// Both sides lifted, to rewrite patterns with.
+    lifted: (
+        Vec<PatternToken<PatternToken>>,
+        Vec<PatternToken<PatternToken>>,
+    ),
+}
+
+impl Rule {
+    fn new(lhs: Pattern, rhs: Pattern, constraints: Vec<Constraint>) -> Self {
+        Self {
+            lifted: (lift(&lhs), lift(&rhs)),
+            lhs,
+            rhs,
+            constraints,
+        }
+    }
+}
+
+/// A pattern where each token is matched literally, to rewrite patterns with other patterns.
+fn lift(pattern: &[PatternToken]) -> Vec<PatternToken<PatternToken>> {
+    pattern
+        .iter()
+        .map(|token| match token {
+            PatternToken::Concrete(_) => PatternToken::Concrete(token.clone()),
+            PatternToken::Variable(name) => PatternToken::Variable(name.clone()),
+            PatternToken::SpreadVariable(name) => PatternToken::SpreadVariable(name.clone()),
+            PatternToken::OptionalSpreadVariable(name) => {
+                PatternToken::OptionalSpreadVariable(name.clone())
+            }
+            PatternToken::ItemVariable(name) => PatternToken::ItemVariable(name.clone()),
+            // Definitions with these are rejected before completing
+            PatternToken::Sorted(name, _) => PatternToken::Variable(name.clone()),
+            PatternToken::Wildcard => PatternToken::Wildcard,
+        })
+        .collect()
+}
+
+/// Rewrites `window` with `rule`, unless that would need a single variable to stand for a spread
+/// variable, which can be more than one token. Also gives the constraints of the rule, with the
+/// variables replaced like in the result.
+fn rewrite_window(rule: &Rule, window: &[PatternToken]) -> Option<(Pattern, Vec<Constraint>)> {
+    // Cheaper than lifting both sides just to find out
+    let mismatch = |token: Option<&PatternToken>, other: Option<&PatternToken>| {
+        matches!(token, Some(PatternToken::Concrete(_))) && token != other
+    };
+
+    if mismatch(rule.lhs.first(), window.first()) || mismatch(rule.lhs.last(), window.last()) {
+        return None;
+    }
+
+    let (lhs, rhs) = &rule.lifted;
+    let bindings =
+        matching::get_match_bindings_where(lhs, window, &stands_for, &|_, _| false, &bracket)?;
+    let (result, bindings) = matching::build(rhs, bindings)?;
+
+    if bindings.0.values().any(|token| token.is_spread()) {
+        return None;
+    }
+
+    let mut bindings = Some(bindings);
+    let mut side = |side: &[PatternToken]| {
+        let (built, rest) = matching::build(&lift(side), bindings.take()?)?;
+        bindings = Some(rest);
+        Some(built)
+    };
+
+    let constraints = rule
+        .constraints
+        .iter()
+        .map(|(a, b)| Some((side(a)?, side(b)?)))
+        .collect::<Option<_>>()?;
+
+    Some((result, constraints))
+}
+
+/// Whether the sides of a constraint differ in every instance, or are the same in every one.
+fn decide((a, b): &Constraint) -> Option<bool> {
+    if a == b {
+        return Some(false);
+    }
+
+    if !a.iter().chain(b).any(PatternToken::is_spread) && a.len() != b.len() {
+        return Some(true);
+    }
+
+    // Tokens before the first spread variable are at the same place in every instance
+    let differ = a
+        .iter()
+        .zip(b)
+        .take_while(|(a, b)| !a.is_spread() && !b.is_spread())
+        .any(
+            |pair| matches!(pair, (PatternToken::Concrete(a), PatternToken::Concrete(b)) if a != b),
+        );
+
+    differ.then_some(true)
+}
+
+/// Whether a constraint holds in every instance that the assumptions hold in.
+fn holds(constraint: &Constraint, assumptions: &[Constraint]) -> bool {
+    let (a, b) = constraint;
+
+    decide(constraint) == Some(true)
+        || assumptions
+            .iter()
+            .any(|assumed| assumed == constraint || (&assumed.1, &assumed.0) == (a, b))
+}
+
+/// The window sizes the left side of `rule` can match, up to `max`.
+fn window_sizes(rule: &Rule, max: usize) -> std::ops::RangeInclusive<usize> {
+    let has_spread = rule.lhs.iter().any(PatternToken::is_spread);
+    let optional = rule
+        .lhs
+        .iter()
+        .filter(|token| matches!(token, PatternToken::OptionalSpreadVariable(_)))
+        .count();
+
+    match has_spread {
+        // Spread variables match at least one token, unless they are optional
+        true => (rule.lhs.len() - optional).max(1)..=max,
+        false => rule.lhs.len()..=rule.lhs.len().min(max),
+    }
+}
+
+/// Rewrites the leftmost window that any rule applies to, given that the assumptions hold.
+fn rewrite_once(
+    rules: &[Rule],
+    pattern: &[PatternToken],
+    assumptions: &[Constraint],
+) -> Option<Pattern> {
+    for start in 0..pattern.len() {
+        for rule in rules {
+            for size in window_sizes(rule, pattern.len() - start) {
+                let Some((result, constraints)) =
+                    rewrite_window(rule, &pattern[start..start + size])
+                else {
+                    continue;
+                };
+
+                if constraints
+                    .iter()
+                    .all(|constraint| holds(constraint, assumptions))
+                {
+                    let mut rewritten = pattern[..start].to_vec();
+                    rewritten.extend(result);
+                    rewritten.extend_from_slice(&pattern[start + size..]);
+                    return Some(rewritten);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn normalize(rules: &[Rule], mut pattern: Pattern, assumptions: &[Constraint]) -> Pattern {
+    for _ in 0..MAX_NORMALIZATION_STEPS {
+        match rewrite_once(rules, &pattern, assumptions) {
+            Some(rewritten) => pattern = rewritten,
+            None => break,
+        }
+    }
+
+    pattern
+}
+
+fn variable_counts(pattern: &[PatternToken]) -> BTreeMap<&String, usize> {
+    let mut counts = BTreeMap::new();
+
+    for token in pattern {
+        if let PatternToken::Variable(name)
+        | PatternToken::SpreadVariable(name)
+        | PatternToken::OptionalSpreadVariable(name)
+        | PatternToken::ItemVariable(name) = token
+        {
+            *counts.entry(name).or_default() += 1;
+        }
+    }
+
+    counts
+}
+
+/// Whether every instance of `high` is greater than the same instance of `low` in the
+/// [`ReductionOrder::ShortLex`] order.
+fn shortlex_greater(high: &[PatternToken], low: &[PatternToken]) -> bool {
+    if let Some(cases) = optional_cases(high, low) {
+        return cases.iter().all(|(high, low)| shortlex_greater(high, low));
+    }
+
+    // Otherwise a long enough binding would make `low` longer
+    let high_counts = variable_counts(high);
+    let variables_shrink = variable_counts(low)
+        .into_iter()
+        .all(|(name, count)| high_counts.get(name).is_some_and(|&high| count <= high));
+
+    if !variables_shrink {
+        return false;
+    }
+
+    if high.len() != low.len() {
+        return high.len() > low.len();
+    }
+
+    // Same prefixes give the same instances, so the first different tokens decide
+    match high.iter().zip(low).find(|(high, low)| high != low) {
+        Some((PatternToken::Concrete(high), PatternToken::Concrete(low))) => high > low,
+        _ => false,
+    }
+}
+
+/// Renames the variables of a rule to their names before they were renamed apart, adding a
+/// number where that would make two variables the same.
+fn canonical(rule: Rule) -> Rule {
+    let mut names: BTreeMap<String, String> = BTreeMap::new();
+    let mut taken = BTreeSet::new();
+
+    for token in rule.lhs.iter().chain(&rule.rhs) {
+        if let PatternToken::Variable(name)
+        | PatternToken::SpreadVariable(name)
+        | PatternToken::OptionalSpreadVariable(name)
+        | PatternToken::ItemVariable(name) = token
+        {
+            if names.contains_key(name) {
+                continue;
+            }
+
+            let base = name.trim_end_matches('\'');
+            let mut candidate = base.to_string();
+            let mut number = 2;
+
+            while taken.contains(&candidate) {
+                candidate = format!("{base}{number}");
+                number += 1;
+            }
+
+            taken.insert(candidate.clone());
+            names.insert(name.clone(), candidate);
+        }
+    }
+
+    let rename = |pattern: Pattern| -> Pattern {
+        pattern
+            .into_iter()
+            .map(|token| match token {
+                PatternToken::Variable(name) => PatternToken::Variable(names[&name].clone()),
+                PatternToken::SpreadVariable(name) => {
+                    PatternToken::SpreadVariable(names[&name].clone())
+                }
+                PatternToken::OptionalSpreadVariable(name) => {
+                    PatternToken::OptionalSpreadVariable(names[&name].clone())
+                }
+                PatternToken::ItemVariable(name) => {
+                    PatternToken::ItemVariable(names[&name].clone())
+                }
+                concrete => concrete,
+            })
+            .collect()
+    };
+
+    let constraints = rule
+        .constraints
+        .into_iter()
+        .map(|(a, b)| (rename(a), rename(b)))
+        .collect();
+
+    Rule::new(rename(rule.lhs), rename(rule.rhs), constraints)
+}
+
+/// Both ways of rewriting every overlap between the left sides of two rules, with the
+/// constraints of both. Overlaps where the constraints can't hold are left out.
+fn critical_pairs(first: &Rule, second: &Rule, same: bool) -> Vec<Equation> {
+    let constraints = second
+        .constraints
+        .iter()
+        .map(|(a, b)| (rename(a), rename(b)))
+        .collect();
+    let second = Rule::new(rename(&second.lhs), rename(&second.rhs), constraints);
+
+    overlaps(&first.lhs, &second.lhs, same, &stands_for)
+        .into_iter()
+        .filter(|overlap| !overlap.at_variables)
+        .filter_map(|overlap| {
+            let rewrite = |rule: &Rule, (start, size): (usize, usize)| {
+                let (result, constraints) =
+                    rewrite_window(rule, &overlap.expression[start..start + size])?;
+
+                let mut rewritten = overlap.expression[..start].to_vec();
+                rewritten.extend(result);
+                rewritten.extend_from_slice(&overlap.expression[start + size..]);
+                Some((rewritten, constraints))
+            };
+
+            let (a, first_constraints) = rewrite(first, overlap.windows.0)?;
+            let (b, second_constraints) = rewrite(&second, overlap.windows.1)?;
+
+            // Constraints that always hold don't need to be kept
+            let mut constraints = Vec::new();
+            for constraint in first_constraints.into_iter().chain(second_constraints) {
+                match decide(&constraint) {
+                    Some(false) => return None,
+                    Some(true) => (),
+                    None if constraints.contains(&constraint) => (),
+                    None => constraints.push(constraint),
+                }
+            }
+
+            Some((a, b, constraints))
+        })
+        .collect()
+}
+
+fn display(pattern: &[PatternToken]) -> String {
+    pattern
+        .iter()
+        .map(|token| match token {
+            PatternToken::Concrete(token) => token.as_str().to_string(),
+            PatternToken::Variable(name) => name.clone(),
+            PatternToken::SpreadVariable(name) => format!("{name}..."),
+            PatternToken::OptionalSpreadVariable(name) => format!("{name}...?"),
+            PatternToken::ItemVariable(name) => format!("{name}...: item"),
+            PatternToken::Sorted(name, Sort::Set(set)) => format!("{name}: {}", set.as_str()),
+            PatternToken::Sorted(name, sort) => format!("{name}: {sort}"),
+            PatternToken::Wildcard => "_".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes the constraints as a guard, like ` where x != y`.
+fn display_guard(constraints: &[Constraint]) -> String {
+    constraints
+        .iter()
+        .map(|(a, b)| format!(" where {} != {}", display(a), display(b)))
+        .collect()
+}
+
+struct Completer {
+    order: ReductionOrder,
+    rules: Vec<Rule>,
+    equations: VecDeque<Equation>,
+}
+
+impl Completer {
+    /// Turns an equation into a rule, extending the precedence if needed.
+    fn orient(&mut self, (a, b, constraints): Equation) -> Result<Rule, CompletionError> {
+        let a_is_greater = match &self.order {
+            ReductionOrder::ShortLex if shortlex_greater(&a, &b) => Some(true),
+            ReductionOrder::ShortLex if shortlex_greater(&b, &a) => Some(false),
+            ReductionOrder::ShortLex => None,
+
+            ReductionOrder::Lpo(precedence) => {
+                if let Some(extended) = lpo_greater(&a, &b, precedence) {
+                    self.order = ReductionOrder::Lpo(extended);
+                    Some(true)
+                } else if let Some(extended) = lpo_greater(&b, &a, precedence) {
+                    self.order = ReductionOrder::Lpo(extended);
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+        };
+
+        // The guard can only be checked if the left side binds its variables
+        let binds_guard = |lhs: &[PatternToken]| {
+            let bound = variable_counts(lhs);
+
+            constraints
+                .iter()
+                .flat_map(|(a, b)| {
+                    variable_counts(a)
+                        .into_keys()
+                        .chain(variable_counts(b).into_keys())
+                })
+                .all(|name| bound.contains_key(name))
+        };
+
+        match a_is_greater {
+            Some(true) if binds_guard(&a) => Ok(Rule::new(a, b, constraints)),
+            Some(false) if binds_guard(&b) => Ok(Rule::new(b, a, constraints)),
+            _ => Err(CompletionError::Unorientable(format!(
+                "{} = {}{}",
+                display(&a),
+                display(&b),
+                display_guard(&constraints)
+            ))),
+        }
+    }
+
+    fn add(&mut self, rule: Rule) {
+        let new = std::slice::from_ref(&rule);
+
+        // Rules the new one simplifies the left side of are equations again
+        let (simplified, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.rules)
+            .into_iter()
+            .partition(|old| rewrite_once(new, &old.lhs, &old.constraints).is_some());
+
+        self.equations.extend(
+            simplified
+                .into_iter()
+                .map(|old| (old.lhs, old.rhs, old.constraints)),
+        );
+
+        self.rules = kept;
+
+        for old in &self.rules {
+            self.equations.extend(critical_pairs(&rule, old, false));
+            self.equations.extend(critical_pairs(old, &rule, false));
+        }
+        self.equations.extend(critical_pairs(&rule, &rule, true));
+
+        self.rules.push(rule);
+
+        for index in 0..self.rules.len() {
+            let rule = &self.rules[index];
+            let rhs = normalize(&self.rules, rule.rhs.clone(), &rule.constraints);
+
+            if rhs != rule.rhs {
+                self.rules[index] = Rule::new(rule.lhs.clone(), rhs, rule.constraints.clone());
+            }
+        }
+    }
+}
+
+impl Runtime {
+    /// Tries to turn the definitions of every structure into an equivalent set of definitions
+    /// where the order in which they are applied doesn't matter, and rewriting always ends.
+    ///
+    /// Definitions are read as equations, so definitions that only work as a fallback because
+    /// of their order, like `p and q => false` after `true and true => true`, change meaning.
+    /// A `where p q != true true` guard keeps them apart instead.
+    ///
+    /// The result has the domain, reserved keywords, numerals and weights of every structure, so
+    /// that it works without any dependencies.
+    pub fn complete(&self, options: &CompletionOptions) -> Result<Structure, CompletionError> {
+        if let Some(definition) = self
+            .definitions()
+            .find(|definition| definition.host.is_some())
+        {
+            return Err(CompletionError::HostDefinition(definition.to_string()));
+        }
+
+        if let Some(definition) = self
+            .definitions()
+            .find(|definition| matches!(definition.guard, Some(Guard::Normalises(_))))
+        {
+            return Err(CompletionError::GuardedDefinition(definition.to_string()));
+        }
+
+        if let Some(definition) = self.definitions().find(|definition| {
+            definition
+                .high
+                .iter()
+                .chain(&definition.low)
+                .any(|token| matches!(token, PatternToken::Sorted(..) | PatternToken::Wildcard))
+        }) {
+            return Err(CompletionError::RestrictedVariables(definition.to_string()));
+        }
+
+        let mut completer = Completer {
+            order: options.order.clone(),
+            rules: Vec::new(),
+            equations: self
+                .definitions()
+                .map(|definition| {
+                    let constraints = match &definition.guard {
+                        Some(Guard::Differ(a, b)) => vec![(a.clone(), b.clone())],
+                        _ => Vec::new(),
+                    };
+
+                    (definition.high.clone(), definition.low.clone(), constraints)
+                })
+                .collect(),
+        };
+
+        let mut steps = 0;
+
+        while let Some((a, b, constraints)) = completer.equations.pop_front() {
+            let a = normalize(&completer.rules, a, &constraints);
+            let b = normalize(&completer.rules, b, &constraints);
+
+            if a == b {
+                continue;
+            }
+
+            steps += 1;
+            if steps > options.max_steps {
+                return Err(CompletionError::TooManySteps(options.max_steps));
+            }
+
+            let rule = completer.orient((a, b, constraints))?;
+            completer.add(canonical(rule));
+
+            if completer.rules.len() > options.max_rules {
+                return Err(CompletionError::TooManyRules(options.max_rules));
+            }
+        }
+
+        let definitions = completer
+            .rules
+            .into_iter()
+            .map(|mut rule| {
+                if rule.constraints.len() > 1 {
+                    return Err(CompletionError::CombinedGuards(format!(
+                        "{} => {}{}",
+                        display(&rule.lhs),
+                        display(&rule.rhs),
+                        display_guard(&rule.constraints)
+                    )));
+                }
+
+                let mut definition = Definition::new(rule.lhs, rule.rhs);
+                definition.set_guard(rule.constraints.pop().map(|(a, b)| Guard::Differ(a, b)));
+                Ok(definition)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The definitions can also use tokens of structures that aren't part of the runtime, like
+        // `true` when completing a structure that uses `std/core` on its own
+        let mut domain: BTreeSet<_> = self.domain().cloned().collect();
+        let mut reserved: BTreeSet<_> = self.reserved().cloned().collect();
+
+        let tokens = definitions.iter().flat_map(|definition| {
+            let guard = definition.guard.iter().flat_map(Guard::tokens);
+            definition.high.iter().chain(&definition.low).chain(guard)
+        });
+
+        for token in tokens {
+            match token {
+                PatternToken::Concrete(Token::Element(element)) => domain.insert(element.clone()),
+                PatternToken::Concrete(Token::Literal(literal)) => reserved.insert(literal.clone()),
+                _ => false,
+            };
+        }
+
+        let intrinsic = Structure::intrinsic();
+        reserved.retain(|literal| !intrinsic.get_reserved().contains(literal));
+
+        let mut structure = Structure::create(domain, reserved, definitions)
+            .expect("The structures of a runtime don't overlap");
+
+        structure.set_numerals(self.has_numerals());
+
+        if let CostModel::Weights(weights) = self.cost_model() {
+            structure.set_weights(weights);
+        }
+
+        Ok(structure)
+    }
+}
+
+impl Structure {
+    /// Tries to complete the definitions of this structure alone, see [`Runtime::complete`]. The
+    /// result also has the tokens of other structures that the definitions use.
+    pub fn complete(&self, options: &CompletionOptions) -> Result<Structure, CompletionError> {
+        Runtime::new(BTreeMap::from([(String::new(), self.clone())])).complete(options)
+    }
+
+    /// Writes the structure as a `.pink` file, without any dependencies.
+    pub fn to_pink(&self) -> String {
+        let set = |set: &BTreeSet<String>| match set.is_empty() {
+            true => "{ }".to_string(),
+            false => format!(
+                "{{ {} }}",
+                set.iter().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        };
+
+        let mut pink = format!(
+            "domain {}\nreserve {}\nuse {{ }}\n",
+            set(&self.domain),
+            set(&self.reserved)
+        );
+
+        if self.match_mode != super::options::MatchMode::default() {
+            pink.push_str(&format!("match {{ {} }}\n", self.match_mode));
+        }
+
+        if self.grouping != super::options::Grouping::default() {
+            pink.push_str(&format!("grouping {{ {} }}\n", self.grouping));
+        }
+
+        if self.numerals {
+            pink.push_str("numerals { integers }\n");
+        }
+
+        if !self.weights.is_empty() {
+            let weights: Vec<_> = self
+                .weights
+                .iter()
+                .map(|(token, weight)| format!("{token} = {weight}"))
+                .collect();
+            pink.push_str(&format!("cost {{ {} }}\n", weights.join(", ")));
+        }
+
+        pink.push('\n');
+
+        for definition in &self.definitions {
+            let low = match &definition.host {
+                Some(function) => format!("@{function}"),
+                None => display(&definition.low),
+            };
+
+            let guard = match &definition.guard {
+                Some(Guard::Differ(a, b)) => display_guard(&[(a.clone(), b.clone())]),
+                Some(Guard::Normalises(condition)) => format!(" where {}", display(condition)),
+                None => String::new(),
+            };
+
+            pink.push_str(&format!("{} => {low}{guard};\n", display(&definition.high)));
+        }
+
+        pink
+    }
+}
@@ -0,0 +1,576 @@
+//! Critical pair analysis, to find out whether the order in which windows are rewritten matters.
+//!
+//! Two definitions overlap when an expression can be rewritten by both at windows that share
+//! tokens, either one window inside the other or one starting before the other ends. The two
+//! results of such an overlap form a critical pair. If every critical pair can be rewritten into
+//! a common expression, the definitions are locally confluent.
+//!
+//! Overlaps are found by unifying the left sides of definitions. Spread variables are only
+//! unified with whole tokens of the other pattern, so overlaps that split the binding of a spread
+//! variable in two are not found. Variables only stand for runs of whole subterms without a
+//! reserved word outside of their groups, like while completing, so that brackets and reserved
+//! words aren't read as the arguments of a definition.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use crate::matching::{self, Bracket};
+
+use super::{
+    limits::{Completion, EvalLimits},
+    options::EvalOptions,
+    rules::RuleSet,
+    strategy::SearchStrategy,
+    termination::instantiate,
+    Definition, Expression, PatternToken, Runtime, Structure, Token,
+};
+
+/// How many expressions are visited from each side of a critical pair while trying to join it.
+const JOIN_SEARCH_VISITS: usize = 1000;
+
+/// How much longer than the overlap expressions can get while trying to join a critical pair.
+const JOIN_SEARCH_GROWTH: usize = 4;
+
+/// Two different results of rewriting the same expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CriticalPair {
+    /// Indices of the definitions in [`Runtime::definitions`].
+    pub definitions: (usize, usize),
+
+    /// The expression both definitions apply to, with variables standing for themselves.
+    pub overlap: Expression,
+
+    pub results: (Expression, Expression),
+}
+
+/// Whether both results of a critical pair can be rewritten into the same expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Joinability {
+    /// Both results rewrite to this expression.
+    Joinable(Expression),
+
+    /// Every expression either result rewrites to was found, and none is shared.
+    NotJoinable,
+
+    /// The search stopped before finding out.
+    Unknown(Completion),
+}
+
+/// Everything found out by [`Runtime::check_confluence`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfluenceReport {
+    /// The definitions of the analysed runtime, which critical pairs refer to by index.
+    pub definitions: Vec<Definition>,
+
+    pub pairs: Vec<(CriticalPair, Joinability)>,
+}
+
+impl ConfluenceReport {
+    /// Critical pairs that were not shown to join.
+    pub fn unjoined(&self) -> impl Iterator<Item = &(CriticalPair, Joinability)> + '_ {
+        self.pairs
+            .iter()
+            .filter(|(_, joinability)| !matches!(joinability, Joinability::Joinable(_)))
+    }
+
+    /// Whether every critical pair joins.
+    pub fn is_locally_confluent(&self) -> bool {
+        self.unjoined().next().is_none()
+    }
+}
+
+impl Display for ConfluenceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_locally_confluent() {
+            return writeln!(
+                f,
+                "Locally confluent: all {} critical pairs join.",
+                self.pairs.len()
+            );
+        }
+
+        writeln!(
+            f,
+            "Not shown to be confluent, {} of {} critical pairs don't join:",
+            self.unjoined().count(),
+            self.pairs.len()
+        )?;
+
+        for (pair, joinability) in self.unjoined() {
+            let (first, second) = pair.definitions;
+
+            writeln!(
+                f,
+                "- `{}` and `{}` both rewrite `{}`",
+                self.definitions[first], self.definitions[second], pair.overlap
+            )?;
+            writeln!(f, "     into `{}`", pair.results.0)?;
+            writeln!(f, "      and `{}`", pair.results.1)?;
+
+            match joinability {
+                Joinability::Joinable(_) => (),
+                Joinability::NotJoinable => writeln!(f, "  which never meet.")?,
+                Joinability::Unknown(completion) => {
+                    writeln!(f, "  which didn't meet before the {completion}.")?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Runtime {
+    /// Finds every critical pair between the definitions of every structure, and whether they
+    /// join.
+    pub fn check_confluence(&self) -> ConfluenceReport {
+        let pairs = self
+            .critical_pairs()
+            .into_iter()
+            .map(|pair| {
+                let joinability = self.join(&pair);
+                (pair, joinability)
+            })
+            .collect();
+
+        ConfluenceReport {
+            definitions: self.definitions().cloned().collect(),
+            pairs,
+        }
+    }
+
+    /// Every critical pair between two definitions, in the order of the definitions.
+    pub fn critical_pairs(&self) -> Vec<CriticalPair> {
+        let definitions: Vec<_> = self.definitions().collect();
+        let rules = RuleSet::compile(self, None, None);
+
+        let mut seen = BTreeSet::new();
+        let mut pairs = Vec::new();
+
+        for (i, first) in definitions.iter().enumerate() {
+            for (j, second) in definitions.iter().enumerate() {
+                let renamed = rename(&unsorted(&second.high));
+
+                for overlap in overlaps(&unsorted(&first.high), &renamed, i == j, &stands_for) {
+                    let Some(pair) = self.critical_pair(&rules, (i, j), &overlap) else {
+                        continue;
+                    };
+
+                    // The same pair is found again with the definitions swapped
+                    let (first, second) = &pair.results;
+                    let key = (
+                        pair.overlap.clone(),
+                        first.min(second).clone(),
+                        first.max(second).clone(),
+                    );
+                    if seen.insert(key) {
+                        pairs.push(pair);
+                    }
+                }
+            }
+        }
+
+        pairs
+    }
+
+    /// Rewrites the overlap with both definitions, if the search would actually do it.
+    fn critical_pair(
+        &self,
+        rules: &RuleSet,
+        definitions: (usize, usize),
+        overlap: &Overlap,
+    ) -> Option<CriticalPair> {
+        let expression = instantiate(&original_names(&overlap.expression));
+
+        // Earlier definitions might take precedence over either one on its window
+        let interner = self.interner();
+        let neighbours = rules.lower_neighbours(&interner.compact(&expression));
+
+        let result = |definition: usize, (start, size): (usize, usize)| {
+            neighbours
+                .iter()
+                .find(|(_, step)| {
+                    step.definition == definition
+                        && step.window_start == start
+                        && step.window_size == size
+                })
+                .map(|(neighbour, _)| interner.expand(neighbour))
+        };
+
+        let first = result(definitions.0, overlap.windows.0)?;
+        let second = result(definitions.1, overlap.windows.1)?;
+
+        (first != second).then_some(CriticalPair {
+            definitions,
+            overlap: expression,
+            results: (first, second),
+        })
+    }
+
+    /// Searches from both results of a critical pair for an expression they both reach.
+    fn join(&self, pair: &CriticalPair) -> Joinability {
+        let limits = EvalLimits {
+            max_visited: Some(JOIN_SEARCH_VISITS),
+            max_length: Some(pair.overlap.tokens.len() * JOIN_SEARCH_GROWTH),
+            ..EvalLimits::none()
+        };
+        let options = EvalOptions::new(limits, SearchStrategy::BreadthFirst);
+
+        let first = self.evaluations(pair.results.0.clone(), &options, &mut |_| ());
+        let second = self.evaluations(pair.results.1.clone(), &options, &mut |_| ());
+
+        let common = first
+            .evaluations
+            .intersection(&second.evaluations)
+            .next()
+            .cloned();
+
+        match (common, first.completion, second.completion) {
+            (Some(common), _, _) => Joinability::Joinable(common),
+            (None, Completion::Exhaustive, Completion::Exhaustive) => Joinability::NotJoinable,
+            (None, Completion::Exhaustive, completion) | (None, completion, _) => {
+                Joinability::Unknown(completion)
+            }
+        }
+    }
+}
+
+impl Structure {
+    /// Finds every critical pair between the definitions of this structure alone, and whether
+    /// they join.
+    pub fn check_confluence(&self) -> ConfluenceReport {
+        Runtime::new(BTreeMap::from([(String::new(), self.clone())])).check_confluence()
+    }
+}
+
+/// Renames every variable of a pattern, so that it doesn't share any with another pattern.
+pub(super) fn rename(pattern: &[PatternToken]) -> Vec<PatternToken> {
+    rename_with(pattern, |name| format!("{name}'"))
+}
+
+/// Undoes [`rename`] in an overlap, for every variable whose original name isn't taken by a
+/// variable of the other pattern.
+fn original_names(pattern: &[PatternToken]) -> Vec<PatternToken> {
+    let names: BTreeSet<_> = pattern.iter().filter_map(variable_name).collect();
+
+    rename_with(pattern, |name| match name.strip_suffix('\'') {
+        Some(original) if !names.contains(&original.to_string()) => original.to_string(),
+        _ => name.to_string(),
+    })
+}
+
+fn rename_with(pattern: &[PatternToken], name: impl Fn(&str) -> String) -> Vec<PatternToken> {
+    pattern
+        .iter()
+        .map(|token| match token {
+            PatternToken::Concrete(token) => PatternToken::Concrete(token.clone()),
+            PatternToken::Variable(variable) => PatternToken::Variable(name(variable)),
+            PatternToken::SpreadVariable(variable) => PatternToken::SpreadVariable(name(variable)),
+            PatternToken::OptionalSpreadVariable(variable) => {
+                PatternToken::OptionalSpreadVariable(name(variable))
+            }
+            PatternToken::ItemVariable(variable) => PatternToken::ItemVariable(name(variable)),
+            PatternToken::Sorted(variable, sort) => {
+                PatternToken::Sorted(name(variable), sort.clone())
+            }
+            PatternToken::Wildcard => PatternToken::Wildcard,
+        })
+        .collect()
+}
+
+/// Forgets the sorts of the variables of a pattern and names its wildcards after where they are,
+/// so that it can be unified. Overlaps are checked against the definitions themselves afterwards.
+fn unsorted(pattern: &[PatternToken]) -> Vec<PatternToken> {
+    pattern
+        .iter()
+        .enumerate()
+        .map(|(i, token)| match token {
+            PatternToken::Sorted(name, _) => PatternToken::Variable(name.clone()),
+            PatternToken::Wildcard => PatternToken::Variable(format!("_{i}")),
+            token => token.clone(),
+        })
+        .collect()
+}
+
+pub(super) fn bracket(token: &PatternToken) -> Option<Bracket> {
+    match token {
+        PatternToken::Concrete(token) => Bracket::of(token),
+        _ => None,
+    }
+}
+
+/// Whether a variable can stand for `run`, which has to be a run of whole subterms without a
+/// reserved word outside of its groups.
+pub(super) fn stands_for(run: &[PatternToken]) -> bool {
+    let mut depth = 0;
+
+    let words_grouped = run.iter().all(|token| match (token, bracket(token)) {
+        (_, Some(Bracket::Open(_))) => {
+            depth += 1;
+            true
+        }
+        (_, Some(Bracket::Close(_))) => {
+            depth -= 1;
+            true
+        }
+        (PatternToken::Concrete(Token::Literal(_)), None) => depth > 0,
+        _ => true,
+    });
+
+    words_grouped && matching::is_grouped(run, bracket)
+}
+
+/// A pattern that both left sides match, at the given `(start, size)` windows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Overlap {
+    pub expression: Vec<PatternToken>,
+    pub windows: ((usize, usize), (usize, usize)),
+
+    /// Whether `second` starts inside `first` at a run of variables, like in `x = x = x`. Read as
+    /// terms, both left sides only share an argument there.
+    pub at_variables: bool,
+}
+
+/// Every way `second` can match a window that shares tokens with a match of `first`, where
+/// variables only stand for runs that `accepts`.
+///
+/// If `same` is set, both patterns come from the same definition, so matching the exact same
+/// window is not an overlap.
+pub(super) fn overlaps(
+    first: &[PatternToken],
+    second: &[PatternToken],
+    same: bool,
+    accepts: &impl Fn(&[PatternToken]) -> bool,
+) -> Vec<Overlap> {
+    let mut overlaps = Vec::new();
+
+    // Without spread variables, both sides of a unification have to be as long
+    let has_spread = |pattern: &[PatternToken]| pattern.iter().any(PatternToken::is_spread);
+    let fixed_length = !has_spread(first) && !has_spread(second);
+
+    // `second` inside `first`
+    for start in 0..first.len() {
+        for end in start + 1..=first.len() {
+            if same && start == 0 && end == first.len() {
+                continue;
+            }
+
+            if fixed_length && end - start != second.len() {
+                continue;
+            }
+
+            for substitution in unify(&first[start..end], second, accepts) {
+                let before = apply(&first[..start], &substitution).len();
+
+                overlaps.push(Overlap {
+                    expression: apply(first, &substitution),
+                    windows: (
+                        (0, apply(first, &substitution).len()),
+                        (before, apply(second, &substitution).len()),
+                    ),
+                    at_variables: false,
+                });
+            }
+        }
+    }
+
+    // `second` starting inside `first` and ending after it
+    for start in 1..first.len() {
+        let at_variables = first[start..]
+            .iter()
+            .all(|token| variable_name(token).is_some());
+
+        for end in 1..second.len() {
+            if fixed_length && first.len() - start != end {
+                continue;
+            }
+
+            for substitution in unify(&first[start..], &second[..end], accepts) {
+                let first_instance = apply(first, &substitution);
+                let before = apply(&first[..start], &substitution).len();
+
+                let mut expression = first_instance.clone();
+                expression.extend(apply(&second[end..], &substitution));
+
+                overlaps.push(Overlap {
+                    expression,
+                    windows: (
+                        (0, first_instance.len()),
+                        (before, apply(second, &substitution).len()),
+                    ),
+                    at_variables,
+                });
+            }
+        }
+    }
+
+    overlaps
+}
+
+/// What each variable stands for. Single variables always stand for exactly one token.
+type Substitution = BTreeMap<String, Vec<PatternToken>>;
+
+fn variable_name(token: &PatternToken) -> Option<&String> {
+    match token {
+        PatternToken::Concrete(_) | PatternToken::Wildcard => None,
+        PatternToken::Variable(name)
+        | PatternToken::SpreadVariable(name)
+        | PatternToken::OptionalSpreadVariable(name)
+        | PatternToken::ItemVariable(name)
+        | PatternToken::Sorted(name, _) => Some(name),
+    }
+}
+
+fn apply(pattern: &[PatternToken], substitution: &Substitution) -> Vec<PatternToken> {
+    pattern
+        .iter()
+        .flat_map(
+            |token| match variable_name(token).and_then(|name| substitution.get(name)) {
+                Some(value) => value.clone(),
+                None => vec![token.clone()],
+            },
+        )
+        .collect()
+}
+
+/// Adds `variable = value` to the substitution, keeping every binding fully substituted.
+fn bind(
+    substitution: &Substitution,
+    variable: &String,
+    value: &[PatternToken],
+) -> Option<Substitution> {
+    let value = apply(value, substitution);
+
+    if value
+        .iter()
+        .any(|token| variable_name(token) == Some(variable))
+    {
+        return None;
+    }
+
+    let single = BTreeMap::from([(variable.clone(), value.clone())]);
+
+    let mut substitution: Substitution = substitution
+        .iter()
+        .map(|(name, bound)| (name.clone(), apply(bound, &single)))
+        .collect();
+    substitution.insert(variable.clone(), value);
+
+    Some(substitution)
+}
+
+/// Every substitution that makes both patterns equal, binding variables to runs that `accepts`.
+fn unify(
+    a: &[PatternToken],
+    b: &[PatternToken],
+    accepts: &impl Fn(&[PatternToken]) -> bool,
+) -> Vec<Substitution> {
+    let is_concrete = |pattern: &[PatternToken]| {
+        pattern
+            .iter()
+            .all(|token| matches!(token, PatternToken::Concrete(_)))
+    };
+
+    if is_concrete(a) && is_concrete(b) {
+        return match a == b {
+            true => vec![Substitution::new()],
+            false => Vec::new(),
+        };
+    }
+
+    let mut solutions = Vec::new();
+    unify_recurse(
+        a.to_vec(),
+        b.to_vec(),
+        Substitution::new(),
+        accepts,
+        &mut solutions,
+    );
+    solutions
+}
+
+fn unify_recurse(
+    a: Vec<PatternToken>,
+    b: Vec<PatternToken>,
+    substitution: Substitution,
+    accepts: &impl Fn(&[PatternToken]) -> bool,
+    solutions: &mut Vec<Substitution>,
+) {
+    let a = apply(&a, &substitution);
+    let b = apply(&b, &substitution);
+
+    // Optional spread variables can also stand for nothing, even at the end of the other side
+    if let Some(PatternToken::OptionalSpreadVariable(name)) = a.first() {
+        if let Some(substitution) = bind(&substitution, name, &[]) {
+            unify_recurse(a[1..].to_vec(), b.clone(), substitution, accepts, solutions);
+        }
+    }
+
+    if let Some(PatternToken::OptionalSpreadVariable(name)) = b.first() {
+        if let Some(substitution) = bind(&substitution, name, &[]) {
+            unify_recurse(a.clone(), b[1..].to_vec(), substitution, accepts, solutions);
+        }
+    }
+
+    let (x, y) = match (a.first(), b.first()) {
+        (None, None) => return solutions.push(substitution),
+        (Some(x), Some(y)) => (x, y),
+        _ => return,
+    };
+
+    if x == y {
+        return unify_recurse(
+            a[1..].to_vec(),
+            b[1..].to_vec(),
+            substitution,
+            accepts,
+            solutions,
+        );
+    }
+
+    let mut try_binding = |variable: &String, value: &[PatternToken], rest_a, rest_b| {
+        if !accepts(value) {
+            return;
+        }
+
+        if let Some(substitution) = bind(&substitution, variable, value) {
+            unify_recurse(rest_a, rest_b, substitution, accepts, solutions);
+        }
+    };
+
+    match (x, y) {
+        (PatternToken::Concrete(_), PatternToken::Concrete(_)) => (),
+
+        // A single variable takes exactly one token
+        (PatternToken::Variable(name), other) | (other, PatternToken::Variable(name))
+            if !other.is_spread() =>
+        {
+            try_binding(
+                name,
+                std::slice::from_ref(other),
+                a[1..].to_vec(),
+                b[1..].to_vec(),
+            )
+        }
+
+        _ => {
+            // A spread variable takes one or more whole tokens of the other side
+            if let Some(name) = variable_name(x).filter(|_| x.is_spread()) {
+                for taken in 1..=b.len() {
+                    try_binding(name, &b[..taken], a[1..].to_vec(), b[taken..].to_vec());
+                }
+            }
+
+            if let Some(name) = variable_name(y).filter(|_| y.is_spread()) {
+                // Taking a single token was already covered above when both are spreads
+                let min = if x.is_spread() { 2 } else { 1 };
+
+                for taken in min..=a.len() {
+                    try_binding(name, &a[..taken], a[taken..].to_vec(), b[1..].to_vec());
+                }
+            }
+        }
+    }
+}
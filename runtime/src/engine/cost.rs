@@ -0,0 +1,151 @@
+//! How much an expression costs, to decide which evaluation is the best one.
+//!
+//! By default every token costs the same, so the best evaluation is the shortest one. Structures
+//! can give tokens different weights in their head, with `cost { successor = 3 }`, and the library
+//! API can use any function of the expression instead. Expressions that cost the same are still
+//! ordered like [`Expression`]s, so that the best one is always well defined.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    sync::Arc,
+};
+
+use super::{
+    intern::{CompactExpression, Interner, Symbol},
+    Expression, Runtime, Token,
+};
+
+/// A function of the host program that gives the cost of an expression.
+pub type CostFunction = dyn Fn(&Expression) -> u64 + Send + Sync;
+
+/// How the cost of an expression is computed.
+#[derive(Clone, Default)]
+pub enum CostModel {
+    /// Every token costs 1.
+    #[default]
+    TokenCount,
+
+    /// Each token costs its weight, looked up by its text. Tokens without a weight cost 1.
+    Weights(BTreeMap<String, u64>),
+
+    /// Any function of the expression.
+    Function(Arc<CostFunction>),
+}
+
+impl CostModel {
+    pub fn function(function: impl Fn(&Expression) -> u64 + Send + Sync + 'static) -> Self {
+        CostModel::Function(Arc::new(function))
+    }
+
+    pub fn cost(&self, expression: &Expression) -> u64 {
+        match self {
+            CostModel::TokenCount => expression.tokens.len() as u64,
+            CostModel::Weights(weights) => expression
+                .tokens
+                .iter()
+                .map(|token| weights.get(token.as_str()).copied().unwrap_or(1))
+                .sum(),
+            CostModel::Function(function) => function(expression),
+        }
+    }
+
+    /// Orders expressions by cost, and expressions that cost the same like [`Expression`]s.
+    pub fn compare(&self, a: &Expression, b: &Expression) -> Ordering {
+        self.cost(a).cmp(&self.cost(b)).then_with(|| a.cmp(b))
+    }
+}
+
+impl Debug for CostModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CostModel::TokenCount => write!(f, "TokenCount"),
+            CostModel::Weights(weights) => f.debug_tuple("Weights").field(weights).finish(),
+            CostModel::Function(_) => write!(f, "Function(..)"),
+        }
+    }
+}
+
+/// Functions are only equal to themselves.
+impl PartialEq for CostModel {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (CostModel::TokenCount, CostModel::TokenCount) => true,
+            (CostModel::Weights(a), CostModel::Weights(b)) => a == b,
+            (CostModel::Function(a), CostModel::Function(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for CostModel {}
+
+impl Runtime {
+    /// The weights declared by every structure, or [`CostModel::TokenCount`] if none of them
+    /// declares any.
+    pub fn cost_model(&self) -> CostModel {
+        let weights: BTreeMap<_, _> = self
+            .structures
+            .values()
+            .flat_map(|structure| structure.get_weights())
+            .map(|(token, weight)| (token.clone(), *weight))
+            .collect();
+
+        match weights.is_empty() {
+            true => CostModel::TokenCount,
+            false => CostModel::Weights(weights),
+        }
+    }
+}
+
+/// A [`CostModel`] that works on compact expressions.
+#[derive(Clone)]
+pub(crate) struct Cost {
+    model: CostModel,
+    weights: HashMap<Symbol, u64>,
+    interner: Arc<Interner>,
+}
+
+impl Cost {
+    pub fn new(model: CostModel, interner: Arc<Interner>) -> Self {
+        let weights = match &model {
+            // Interning both kinds of token is harmless, they just never show up
+            CostModel::Weights(weights) => weights
+                .iter()
+                .flat_map(|(text, weight)| {
+                    [Token::Element(text.clone()), Token::Literal(text.clone())]
+                        .map(|token| (interner.intern(&token), *weight))
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        Self {
+            model,
+            weights,
+            interner,
+        }
+    }
+
+    pub fn interner(&self) -> &Arc<Interner> {
+        &self.interner
+    }
+
+    /// Orders expressions that cost the same like [`Expression`]s.
+    pub fn tie_break(&self, a: &CompactExpression, b: &CompactExpression) -> Ordering {
+        self.interner.compare(a, b)
+    }
+
+    pub fn of(&self, expression: &CompactExpression) -> u64 {
+        match &self.model {
+            CostModel::TokenCount => expression.len() as u64,
+            CostModel::Weights(_) => expression
+                .symbols()
+                .iter()
+                .map(|symbol| self.weights.get(symbol).copied().unwrap_or(1))
+                .sum(),
+            CostModel::Function(function) => function(&self.interner.expand(expression)),
+        }
+    }
+}
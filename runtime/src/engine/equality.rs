@@ -0,0 +1,210 @@
+//! Proving that two expressions are equal.
+//!
+//! Every definition is an equation, so two expressions are equal if one can be rewritten into
+//! the other by lowering and raising windows. Both expressions are explored at the same time,
+//! smallest expressions first, until an expression is reached from both sides.
+
+use std::{cmp::Reverse, collections::BinaryHeap, fmt::Display};
+
+use super::{
+    intern::{CompactExpression, CompactMap},
+    limits::{Budget, Completion, EvalLimits},
+    rules::{RuleSet, Step},
+    trace::{Derivation, DerivationStep, Rewrite},
+    Expression, Runtime,
+};
+
+/// The result of [`Runtime::prove_equal`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Equivalence {
+    /// The expressions are equal, as shown by the derivation from one to the other.
+    Equal(Derivation),
+
+    /// Every expression equal to one of them was found, and the other one wasn't among them.
+    Distinct,
+
+    /// The search stopped before finding out.
+    Unknown(Completion),
+}
+
+impl Equivalence {
+    pub fn is_equal(&self) -> bool {
+        matches!(self, Equivalence::Equal(_))
+    }
+}
+
+impl Display for Equivalence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Equivalence::Equal(derivation) => write!(f, "Equal:\n{derivation}"),
+            Equivalence::Distinct => write!(f, "Not equal"),
+            Equivalence::Unknown(completion) => write!(f, "Unknown ({completion})"),
+        }
+    }
+}
+
+/// Everything reached from one of the two expressions.
+struct Side {
+    /// How each expression was first reached, `None` for the starting one.
+    parents: CompactMap<Option<(CompactExpression, Step)>>,
+
+    /// Expressions found but not expanded yet, smallest first.
+    frontier: BinaryHeap<Reverse<CompactExpression>>,
+}
+
+impl Side {
+    fn new(start: CompactExpression) -> Self {
+        Self {
+            parents: CompactMap::from_iter([(start.clone(), None)]),
+            frontier: BinaryHeap::from([Reverse(start)]),
+        }
+    }
+
+    /// Steps from the start of this side to `expression`, as `(parent, step, child)` triples.
+    fn path_to(
+        &self,
+        expression: &CompactExpression,
+    ) -> Vec<(CompactExpression, Step, CompactExpression)> {
+        let mut path = Vec::new();
+        let mut current = expression;
+
+        while let Some((parent, step)) = &self.parents[current] {
+            path.push((parent.clone(), *step, current.clone()));
+            current = parent;
+        }
+
+        path.reverse();
+        path
+    }
+}
+
+impl Runtime {
+    /// Tries to prove that two expressions are equal, by rewriting both of them (in either
+    /// direction) until they meet.
+    ///
+    /// Unlike comparing the smallest evaluations of each, this can prove identities where both
+    /// sides are already as small as they get, like `p or q = q or p`.
+    pub fn prove_equal(&self, a: Expression, b: Expression, limits: &EvalLimits) -> Equivalence {
+        let rules = RuleSet::compile(self, None, None);
+        let mut budget = Budget::new(limits);
+
+        let interner = self.interner();
+        let (start, end) = (interner.compact(&a), interner.compact(&b));
+
+        if start == end {
+            return Equivalence::Equal(Derivation {
+                start: a,
+                steps: Vec::new(),
+            });
+        }
+
+        let mut sides = [Side::new(start), Side::new(end)];
+
+        let meeting = loop {
+            // Expand the side with the smallest expression left, since lowering is what usually
+            // brings both sides together. If either side has nothing left, everything equal to
+            // it has been found.
+            let current = match (sides[0].frontier.peek(), sides[1].frontier.peek()) {
+                (Some(first), Some(second)) if first >= second => 0,
+                (Some(_), Some(_)) => 1,
+                _ => break Meeting::Never(budget.finished(None)),
+            };
+
+            let visited = sides.iter().map(|side| side.parents.len()).sum();
+            if let Some(completion) = budget.exceeded(visited) {
+                break Meeting::Never(completion);
+            }
+
+            if let Some(meeting) = expand(&mut sides, current, &rules, &mut budget) {
+                break Meeting::At(meeting);
+            }
+        };
+
+        match meeting {
+            Meeting::At(meeting) => Equivalence::Equal(self.connect(a, &sides, &meeting)),
+            Meeting::Never(Completion::Exhaustive) => Equivalence::Distinct,
+            Meeting::Never(completion) => Equivalence::Unknown(completion),
+        }
+    }
+
+    /// Builds the derivation that goes from the start of the first side to the meeting point,
+    /// and then backwards to the start of the second side.
+    fn connect(&self, a: Expression, sides: &[Side; 2], meeting: &CompactExpression) -> Derivation {
+        let definitions: Vec<_> = self.definitions().collect();
+        let interner = self.interner();
+
+        let mut steps: Vec<_> = sides[0]
+            .path_to(meeting)
+            .into_iter()
+            .map(|(parent, step, child)| {
+                let parent = interner.expand(&parent);
+                let rewrite = Rewrite::from_step(definitions[step.definition], &parent, step);
+                (rewrite, interner.expand(&child))
+            })
+            .collect();
+
+        // Undo the steps of the second side, from the meeting point back to its start
+        for (parent, step, child) in sides[1].path_to(meeting).into_iter().rev() {
+            let (parent, child) = (interner.expand(&parent), interner.expand(&child));
+
+            // The bindings are the same either way, but only the original direction is sure to
+            // bind every variable
+            let mut rewrite = Rewrite::from_step(definitions[step.definition], &parent, step);
+            rewrite.direction = step.direction.reversed();
+            rewrite.window_size = step.window_size + child.tokens.len() - parent.tokens.len();
+
+            steps.push((rewrite, parent));
+        }
+
+        Derivation {
+            start: a,
+            steps: steps
+                .into_iter()
+                .map(|(rewrite, result)| DerivationStep {
+                    definition: definitions[rewrite.definition].clone(),
+                    rewrite,
+                    result,
+                })
+                .collect(),
+        }
+    }
+}
+
+enum Meeting {
+    At(CompactExpression),
+    Never(Completion),
+}
+
+/// Expands the smallest expression of the frontier of one side. Returns the expression where both
+/// sides met, if they did.
+fn expand(
+    sides: &mut [Side; 2],
+    current: usize,
+    rules: &RuleSet,
+    budget: &mut Budget,
+) -> Option<CompactExpression> {
+    let Reverse(expression) = sides[current].frontier.pop()?;
+
+    let neighbours = rules
+        .lower_neighbours(&expression)
+        .into_iter()
+        .chain(rules.raise_neighbours(&expression));
+
+    for (neighbour, step) in neighbours {
+        if !budget.admits(neighbour.len()) || sides[current].parents.contains_key(&neighbour) {
+            continue;
+        }
+
+        sides[current]
+            .parents
+            .insert(neighbour.clone(), Some((expression.clone(), step)));
+
+        if sides[1 - current].parents.contains_key(&neighbour) {
+            return Some(neighbour);
+        }
+
+        sides[current].frontier.push(Reverse(neighbour));
+    }
+
+    None
+}
@@ -1,68 +1,565 @@
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, ops::ControlFlow, sync::Arc};
 
-use super::{Expression, Runtime};
+use super::{
+    cost::Cost,
+    intern::{CompactExpression, CompactMap, CompactSet, Interner},
+    limits::{Budget, Completion, EvalOutcome, Limit, Stopwatch},
+    normal::NormalForms,
+    observer::{EvalEvent, EvalObserver, EvalStats, OnVisited, VisitedSoFar},
+    options::EvalOptions,
+    parallel::ConcurrentSet,
+    profile::{Counters, EvalProfile},
+    rules::{RuleSet, Step},
+    strategy::{Frontier, SearchStrategy},
+    trace::{Rewrite, Trace},
+    Definition, Expression, Runtime,
+};
 
-impl Runtime {
-    fn get_lower_neighbours(&self, expression: &Expression) -> Vec<Expression> {
-        let mut neighbours = Vec::new();
+/// Bookkeeping shared by every search: what has been visited, the best result so far and the
+/// trace. Kept by an [`Evaluation`] between its steps.
+struct Progress {
+    interner: Arc<Interner>,
+    visited: CompactSet,
+    cost: Cost,
 
-        for size in 1..=expression.tokens.len() {
-            for (window_start, window) in expression.tokens.windows(size).enumerate() {
-                for definition in self.definitions() {
-                    if let Some(lowered_window) = definition.lower(window) {
-                        let mut lowered = expression.tokens[..window_start].to_vec();
-                        lowered.extend(lowered_window.tokens);
-                        lowered.extend(expression.tokens[window_start + size..].to_vec());
+    /// The cheapest expression so far, together with its cost.
+    best: Option<(u64, CompactExpression)>,
+    trace: Option<Trace>,
 
-                        let neighbour = Expression::new(lowered);
-                        neighbours.push(neighbour);
+    /// Only collected when tracing, to rebuild the bindings of each rewrite, or profiling.
+    definitions: Vec<Definition>,
 
-                        break;
-                    }
-                }
+    /// Only kept when profiling.
+    counters: Option<Counters>,
+
+    stopwatch: Stopwatch,
+
+    /// Limits that were already reported as [`EvalEvent::Pruned`].
+    pruned: Vec<Limit>,
+
+    /// Whether the observer asked to stop.
+    stopped: bool,
+}
+
+/// The [`Progress`] of a search during a single step, which also tells the observer about it.
+pub(super) struct SearchState<'a, O: EvalObserver> {
+    progress: &'a mut Progress,
+    observer: &'a mut O,
+
+    /// How many more expressions can be expanded before the step is over. Iterative deepening
+    /// expands expressions again on every pass, so this doesn't count the visited set.
+    remaining: usize,
+}
+
+impl<O: EvalObserver> SearchState<'_, O> {
+    pub fn visited_count(&self) -> usize {
+        self.progress.visited.len()
+    }
+
+    pub fn is_visited(&self, expression: &CompactExpression) -> bool {
+        self.progress.visited.contains(expression)
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.progress.trace.is_some()
+    }
+
+    /// How many more expressions can be expanded in this step.
+    pub fn remaining_in_step(&self) -> usize {
+        self.remaining
+    }
+
+    /// Breaks with how the search should end, if the observer stopped it or some limit has been
+    /// reached. Otherwise returns whether the step is over, so the search should pause.
+    ///
+    /// The original expression is always visited, so that there is always a result.
+    pub fn exceeded(&self, budget: &Budget) -> ControlFlow<Completion, bool> {
+        if self.progress.stopped {
+            return ControlFlow::Break(Completion::Cancelled);
+        }
+
+        if self.visited_count() > 0 {
+            if let Some(completion) = budget.exceeded(self.visited_count()) {
+                return ControlFlow::Break(completion);
+            }
+        }
+
+        ControlFlow::Continue(self.remaining_in_step() == 0)
+    }
+
+    /// The neighbours of an expression, counted if profiling.
+    pub fn expand(
+        &mut self,
+        rules: &RuleSet,
+        expression: &CompactExpression,
+    ) -> Vec<(CompactExpression, Step)> {
+        match &mut self.progress.counters {
+            Some(counters) => rules.counted_lower_neighbours(expression, counters),
+            None => rules.lower_neighbours(expression),
+        }
+    }
+
+    /// Whether to count the work of other threads, see [`SearchState::merge`].
+    pub fn is_profiling(&self) -> bool {
+        self.progress.counters.is_some()
+    }
+
+    /// Counts an expression that was found again.
+    pub fn duplicate(&mut self) {
+        if let Some(counters) = &mut self.progress.counters {
+            counters.duplicates += 1;
+        }
+    }
+
+    /// Counts how many expressions are waiting to be visited.
+    pub fn frontier_size(&mut self, size: usize) {
+        if let Some(counters) = &mut self.progress.counters {
+            counters.frontier_size(size);
+        }
+    }
+
+    /// Adds up what another thread counted.
+    pub fn merge(&mut self, other: Counters) {
+        if let Some(counters) = &mut self.progress.counters {
+            counters.merge(other);
+        }
+    }
+
+    fn send(&mut self, event: EvalEvent<'_>) {
+        if self.observer.observe(event).is_break() {
+            self.progress.stopped = true;
+        }
+    }
+
+    /// Tells the observer that some limit left out expressions, the first time it does.
+    pub fn prune(&mut self, limit: Option<Limit>) {
+        let Some(limit) = limit else {
+            return;
+        };
+
+        if !self.progress.pruned.contains(&limit) {
+            self.progress.pruned.push(limit);
+            self.send(EvalEvent::Pruned(limit));
+        }
+    }
+
+    /// Marks an expression as visited, telling the observer if it is the cheapest one so far. It
+    /// counts towards the step even if it was visited already.
+    ///
+    /// Returns whether the expression was not visited already.
+    pub fn visit(&mut self, expression: CompactExpression) -> bool {
+        self.remaining = self.remaining.saturating_sub(1);
+
+        if self.is_visited(&expression) {
+            return false;
+        }
+
+        let cost = self.progress.cost.of(&expression);
+        let is_best = self.progress.best.as_ref().is_none_or(|(best_cost, best)| {
+            cost.cmp(best_cost)
+                .then_with(|| self.progress.interner.compare(&expression, best))
+                .is_lt()
+        });
+
+        if is_best || self.observer.wants_visited() {
+            let expanded = self.progress.interner.expand(&expression);
+
+            if self.observer.wants_visited() {
+                self.send(EvalEvent::Visited(&expanded));
+            }
+
+            if is_best {
+                let elapsed = self.progress.stopwatch.elapsed();
+                self.send(EvalEvent::NewBest(&expanded, elapsed));
+            }
+        }
+
+        if is_best {
+            self.progress.best = Some((cost, expression.clone()));
+        }
+
+        self.progress.visited.insert(expression.clone());
+        self.observer.visited_so_far(&VisitedSoFar {
+            visited: &self.progress.visited,
+            latest: &expression,
+            interner: &self.progress.interner,
+        });
+        true
+    }
+
+    /// Records a rewrite in the trace, if tracing.
+    pub fn record(&mut self, parent: &CompactExpression, child: &CompactExpression, step: Step) {
+        let Some(trace) = &mut self.progress.trace else {
+            return;
+        };
+
+        let interner = &self.progress.interner;
+        let parent = interner.expand(parent);
+        let child = interner.expand(child);
+
+        let definition = &self.progress.definitions[step.definition];
+        let rewrite = Rewrite::from_step(definition, &parent, step);
+
+        trace.record(&parent, &child, rewrite);
+    }
+
+    /// Tells the observer that the search ended.
+    fn finish(&mut self, completion: Completion) {
+        let stats = EvalStats {
+            visited: self.visited_count(),
+            elapsed: self.progress.stopwatch.elapsed(),
+            completion,
+        };
+        // The evaluation is over, so there is nothing left to stop
+        let _ = self.observer.observe(EvalEvent::Finished(&stats));
+    }
+}
+
+/// Where a search left off, so that the next step can pick it up.
+enum Search {
+    Frontier(Frontier),
+
+    /// A frontier expanded on several threads, which claim expressions through a shared set.
+    Parallel {
+        frontier: Frontier,
+        threads: usize,
+        claimed: ConcurrentSet<CompactExpression>,
+    },
+
+    IterativeDeepening {
+        root: CompactExpression,
+        max_depth: usize,
+
+        /// Depth at which each expression has been seen in this pass. Expressions can be
+        /// revisited if they are found again at a lower depth.
+        depths: CompactMap<usize>,
+        stack: Vec<(CompactExpression, usize)>,
+    },
+}
+
+/// An evaluation that runs a bounded number of expressions at a time, started with
+/// [`Runtime::start_evaluation`].
+///
+/// Between steps the caller can do other work, show the progress so far or drop the evaluation
+/// altogether. It doesn't borrow the runtime, so it can be stored right next to it. The time limit
+/// keeps counting between steps.
+pub struct Evaluation {
+    rules: RuleSet,
+    budget: Budget,
+    search: Search,
+    progress: Progress,
+    completion: Option<Completion>,
+}
+
+impl Evaluation {
+    /// Visits up to `visits` more expressions, sending every event to `observer`. Expressions that
+    /// iterative deepening visits again on a deeper pass count as well.
+    ///
+    /// Returns how the evaluation ended, if it did. [`EvalEvent::Finished`] is sent during the
+    /// step in which that happens, and later steps do nothing.
+    pub fn step(&mut self, visits: usize, observer: &mut impl EvalObserver) -> Option<Completion> {
+        if self.completion.is_some() {
+            return self.completion;
+        }
+
+        let mut state = SearchState {
+            remaining: visits,
+            progress: &mut self.progress,
+            observer,
+        };
+
+        let flow = match &mut self.search {
+            Search::Frontier(frontier) => {
+                frontier_search(frontier, &self.rules, &mut self.budget, &mut state)
             }
+            Search::Parallel {
+                frontier,
+                threads,
+                claimed,
+            } => super::parallel::frontier_search(
+                frontier,
+                *threads,
+                claimed,
+                &self.rules,
+                &mut self.budget,
+                &mut state,
+            ),
+            Search::IterativeDeepening {
+                root,
+                max_depth,
+                depths,
+                stack,
+            } => iterative_deepening(
+                root,
+                max_depth,
+                depths,
+                stack,
+                &self.rules,
+                &mut self.budget,
+                &mut state,
+            ),
+        };
+
+        let ControlFlow::Break(completion) = flow else {
+            return None;
+        };
+
+        state.finish(completion);
+        self.completion = Some(completion);
+        self.completion
+    }
+
+    /// How the evaluation ended, or `None` while it is still running.
+    pub fn completion(&self) -> Option<Completion> {
+        self.completion
+    }
+
+    pub fn visited_count(&self) -> usize {
+        self.progress.visited.len()
+    }
+
+    /// The cheapest expression found so far, or `None` before the first step.
+    pub fn best(&self) -> Option<Expression> {
+        let (_, best) = self.progress.best.as_ref()?;
+        Some(self.progress.interner.expand(best))
+    }
+
+    /// Statistics about the search so far, if
+    /// [`EvalOptions::profile`](super::options::EvalOptions::profile) was set.
+    pub fn profile(&self) -> Option<EvalProfile> {
+        let counters = self.progress.counters.as_ref()?;
+        Some(counters.profile(self.visited_count(), &self.progress.definitions))
+    }
+
+    /// The expressions found so far that can't be rewritten any further. If the evaluation hasn't
+    /// ended, it counts as cancelled.
+    pub fn normal_forms(&self) -> NormalForms {
+        let progress = &self.progress;
+
+        NormalForms {
+            forms: super::normal::normal_forms(&self.rules, &progress.visited, &progress.interner),
+            visited: self.visited_count(),
+            completion: self.completion.unwrap_or(Completion::Cancelled),
         }
+    }
+
+    /// Everything found so far. If the evaluation hasn't ended, it counts as cancelled.
+    ///
+    /// # Panics
+    ///
+    /// If no step has run yet, since there isn't even a result then.
+    pub fn into_outcome(self) -> EvalOutcome {
+        let interner = &self.progress.interner;
 
-        neighbours
+        let (_, best) = self
+            .progress
+            .best
+            .as_ref()
+            .expect("Should have at least the original expression");
+
+        EvalOutcome {
+            best: interner.expand(best),
+            evaluations: self
+                .progress
+                .visited
+                .iter()
+                .map(|expression| interner.expand(expression))
+                .collect::<BTreeSet<_>>(),
+            completion: self.completion.unwrap_or(Completion::Cancelled),
+            profile: self.profile(),
+            trace: self.progress.trace,
+        }
     }
+}
 
-    /// Returns the lowest possible evaluation
+impl Runtime {
+    /// Returns the cheapest evaluation found with the given options
     pub fn eval(
         &self,
         expression: Expression,
-        callback: &mut impl FnMut(&BTreeSet<Expression>),
+        options: &EvalOptions,
+        callback: &mut impl FnMut(&VisitedSoFar<'_>),
     ) -> Expression {
-        self.evaluations(expression, callback)
-            .into_iter()
-            .next()
+        let mut observer = OnVisited::new(callback);
+        let mut evaluation = self.start_evaluation(expression, options);
+        while evaluation.step(usize::MAX, &mut observer).is_none() {}
+        evaluation
+            .best()
             .expect("Should have at least the original expression")
     }
 
-    /// Returns all possible evaluations and runs the callback on each iteration
+    /// Returns all evaluations found with the given options and runs the callback on each
+    /// iteration, with every expression visited so far.
     pub fn evaluations(
         &self,
         expression: Expression,
-        callback: &mut impl FnMut(&BTreeSet<Expression>),
-    ) -> BTreeSet<Expression> {
-        let mut visited = BTreeSet::new();
-        let mut queue = Vec::new();
+        options: &EvalOptions,
+        callback: &mut impl FnMut(&VisitedSoFar<'_>),
+    ) -> EvalOutcome {
+        self.evaluate_with(expression, options, &mut OnVisited::new(callback))
+    }
+
+    /// Like [`Runtime::evaluations`], but sending every event of the evaluation to `observer`,
+    /// which can also stop it.
+    pub fn evaluate_with(
+        &self,
+        expression: Expression,
+        options: &EvalOptions,
+        observer: &mut impl EvalObserver,
+    ) -> EvalOutcome {
+        let mut evaluation = self.start_evaluation(expression, options);
+        while evaluation.step(usize::MAX, observer).is_none() {}
+        evaluation.into_outcome()
+    }
+
+    /// Prepares an evaluation that runs in steps, see [`Evaluation::step`]. Nothing is visited
+    /// until the first step.
+    pub fn start_evaluation(&self, expression: Expression, options: &EvalOptions) -> Evaluation {
+        let rules = RuleSet::compile(self, options.match_mode, options.grouping);
+        let counters = options.profile.then(|| Counters::new(rules.len()));
+        let root = self.interner().compact(&expression);
+
+        let model = options.cost.clone().unwrap_or_else(|| self.cost_model());
+        let cost = Cost::new(model, self.interner().clone());
+
+        let search = match options.strategy {
+            SearchStrategy::IterativeDeepening => Search::IterativeDeepening {
+                root: root.clone(),
+                max_depth: 0,
+                depths: CompactMap::default(),
+                stack: vec![(root, 0)],
+            },
+            strategy => {
+                let mut frontier = Frontier::new(strategy, cost.clone());
+                frontier.push(root);
+
+                match options.thread_count() {
+                    1 => Search::Frontier(frontier),
+                    threads => Search::Parallel {
+                        frontier,
+                        threads,
+                        claimed: ConcurrentSet::new(threads * 4),
+                    },
+                }
+            }
+        };
+
+        Evaluation {
+            rules,
+            budget: Budget::new(&options.limits),
+            search,
+            progress: Progress {
+                interner: self.interner().clone(),
+                visited: CompactSet::default(),
+                cost,
+                best: None,
+                trace: options.trace.then(|| Trace::new(expression)),
+                definitions: match options.trace || options.profile {
+                    true => self.definitions().cloned().collect(),
+                    false => Vec::new(),
+                },
+                counters,
+                stopwatch: Stopwatch::start(),
+                pruned: Vec::new(),
+                stopped: false,
+            },
+            completion: None,
+        }
+    }
+}
+
+/// Continues a frontier search, until it ends or the step is over.
+fn frontier_search<O: EvalObserver>(
+    frontier: &mut Frontier,
+    rules: &RuleSet,
+    budget: &mut Budget,
+    state: &mut SearchState<O>,
+) -> ControlFlow<Completion> {
+    loop {
+        let Some(expression) = frontier.pop() else {
+            return ControlFlow::Break(budget.finished(frontier.pruned()));
+        };
+
+        if state.is_visited(&expression) {
+            state.duplicate();
+            continue;
+        }
+
+        if state.exceeded(budget)? {
+            frontier.put_back(expression);
+            return ControlFlow::Continue(());
+        }
+
+        for (neighbour, step) in state.expand(rules, &expression) {
+            if !budget.admits(neighbour.len()) {
+                continue;
+            }
+
+            state.record(&expression, &neighbour, step);
 
-        queue.push(expression);
+            match state.is_visited(&neighbour) {
+                true => state.duplicate(),
+                false => frontier.push(neighbour),
+            }
+        }
+
+        state.frontier_size(frontier.len());
+
+        state.prune(budget.discarded());
+        state.prune(frontier.pruned());
+        state.visit(expression);
+    }
+}
 
-        while let Some(expression) = queue.pop() {
-            if visited.contains(&expression) {
+/// Depth first searches of increasing depth, until one of them can't go any deeper. Continues
+/// from wherever the previous step paused.
+fn iterative_deepening<O: EvalObserver>(
+    root: &CompactExpression,
+    max_depth: &mut usize,
+    depths: &mut CompactMap<usize>,
+    stack: &mut Vec<(CompactExpression, usize)>,
+    rules: &RuleSet,
+    budget: &mut Budget,
+    state: &mut SearchState<O>,
+) -> ControlFlow<Completion> {
+    loop {
+        while let Some((expression, depth)) = stack.pop() {
+            if depths.get(&expression).is_some_and(|&seen| seen <= depth) {
+                state.duplicate();
                 continue;
             }
 
-            visited.insert(expression.clone());
+            if state.exceeded(budget)? {
+                stack.push((expression, depth));
+                return ControlFlow::Continue(());
+            }
+
+            depths.insert(expression.clone(), depth);
+
+            if depth < *max_depth {
+                for (neighbour, step) in state.expand(rules, &expression) {
+                    if !budget.admits(neighbour.len()) {
+                        continue;
+                    }
 
-            let neighbours = self.get_lower_neighbours(&expression);
+                    state.record(&expression, &neighbour, step);
+                    stack.push((neighbour, depth + 1));
+                }
+
+                state.frontier_size(stack.len());
+            }
 
-            queue.extend(neighbours);
+            state.prune(budget.discarded());
+            state.visit(expression);
+        }
 
-            callback(&visited);
+        // Nothing is exactly `max_depth` rewrites away, so there is nothing further either
+        if !depths.values().any(|&depth| depth == *max_depth) {
+            return ControlFlow::Break(budget.finished(None));
         }
 
-        visited
+        *max_depth += 1;
+        depths.clear();
+        stack.push((root.clone(), 0));
     }
 }
@@ -0,0 +1,124 @@
+//! Definitions whose lower side is built by the host program.
+//!
+//! A definition like `x + y => @add` matches its left side like any other, and then hands the
+//! window and the bindings to the host function registered as `add`. The function builds the
+//! replacement however it wants, or returns `None` to leave the window alone, so that pink can
+//! delegate things like big number arithmetic, hashing or lookups to native code while still
+//! searching for the best evaluation.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    sync::Arc,
+};
+
+use super::{int, trace::Bindings, Definition, Expression, Runtime, Structure, Token};
+
+/// A function of the host program that lowers a window, given what each variable of the left
+/// side of the definition was bound to.
+pub type HostFunction = dyn Fn(&[Token], &Bindings) -> Option<Expression> + Send + Sync;
+
+/// Host functions by name.
+#[derive(Clone, Default)]
+pub(crate) struct HostFunctions(BTreeMap<String, Arc<HostFunction>>);
+
+impl HostFunctions {
+    /// The functions every runtime starts with, which the standard library uses.
+    pub fn builtin() -> Self {
+        let mut functions = Self::default();
+        int::register(&mut functions);
+        functions
+    }
+
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        function: impl Fn(&[Token], &Bindings) -> Option<Expression> + Send + Sync + 'static,
+    ) {
+        self.0.insert(name.into(), Arc::new(function));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&HostFunction> {
+        self.0.get(name).map(Arc::as_ref)
+    }
+}
+
+/// Functions can't be compared, so tables with functions of the same names are equal.
+impl PartialEq for HostFunctions {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.keys().eq(other.0.keys())
+    }
+}
+
+impl Eq for HostFunctions {}
+
+impl Debug for HostFunctions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl Structure {
+    /// Registers a host function that comes with this structure, so that it doesn't have to be
+    /// registered on every runtime that uses it. Every definition of the runtime can use it, like
+    /// any other function. Functions registered on the runtime itself take precedence.
+    pub fn register_host_function(
+        &mut self,
+        name: impl Into<String>,
+        function: impl Fn(&[Token], &Bindings) -> Option<Expression> + Send + Sync + 'static,
+    ) {
+        self.host_functions.insert(name, function);
+    }
+}
+
+impl Runtime {
+    /// Registers a host function, used by definitions like `x + y => @name`. Replaces any
+    /// function that was registered with the same name, including the `int_` functions that
+    /// every runtime starts with for `std/int` and those registered on its structures.
+    ///
+    /// Definitions that use a function that isn't registered never apply, see
+    /// [`Runtime::unregistered_host_definitions`].
+    pub fn register_host_function(
+        &mut self,
+        name: impl Into<String>,
+        function: impl Fn(&[Token], &Bindings) -> Option<Expression> + Send + Sync + 'static,
+    ) {
+        self.host_functions.insert(name, function);
+    }
+
+    /// The names of every registered host function, built in, registered on the runtime or on
+    /// one of its structures.
+    pub fn host_functions(&self) -> impl Iterator<Item = &String> + '_ {
+        self.structures
+            .values()
+            .flat_map(|structure| structure.host_functions.0.keys())
+            .chain(self.host_functions.0.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+    }
+
+    /// Definitions that use a host function that isn't registered, so they never apply. This is
+    /// usually a typo, or a function that the host program forgot to register.
+    pub fn unregistered_host_definitions(&self) -> impl Iterator<Item = &Definition> + '_ {
+        let table = self.host_function_table();
+
+        self.definitions().filter(move |definition| {
+            definition
+                .host_function()
+                .is_some_and(|name| table.get(name).is_none())
+        })
+    }
+
+    /// Every function the definitions can use, where those of the runtime replace those of its
+    /// structures.
+    pub(crate) fn host_function_table(&self) -> HostFunctions {
+        let mut table = HostFunctions::default();
+
+        for structure in self.structures.values() {
+            table.0.extend(structure.host_functions.0.clone());
+        }
+
+        table.0.extend(self.host_functions.0.clone());
+        table
+    }
+}
@@ -0,0 +1,333 @@
+//! Arbitrary precision integers, for the numerals of `std/int`.
+//!
+//! Structures with `numerals { integers }` in their head have every decimal literal (like `-12`
+//! or `340282366920938463463374607431768211456`) in their domain. Since there are infinitely
+//! many of them, arithmetic on numerals can't be written as definitions, so `std/int` hands it
+//! to the host functions registered here, which every runtime starts with.
+
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
+
+use super::{host::HostFunctions, trace::Bindings, Expression, Token};
+
+/// Each limb holds 9 decimal digits, so that printing and parsing are straightforward.
+const BASE: u64 = 1_000_000_000;
+const DIGITS: usize = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Integer {
+    /// Zero is never negative.
+    negative: bool,
+
+    /// Little endian, without trailing zeros. Zero has no limbs.
+    limbs: Vec<u32>,
+}
+
+impl Integer {
+    fn zero() -> Self {
+        Self {
+            negative: false,
+            limbs: Vec::new(),
+        }
+    }
+
+    fn one() -> Self {
+        Self {
+            negative: false,
+            limbs: vec![1],
+        }
+    }
+
+    fn new(negative: bool, mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+
+        Self {
+            negative: negative && !limbs.is_empty(),
+            limbs,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    fn negate(self) -> Self {
+        Self::new(!self.negative, self.limbs)
+    }
+
+    /// The integer an element stands for, if it is a numeral.
+    pub fn from_token(token: &Token) -> Option<Self> {
+        match token {
+            Token::Element(element) => element.parse().ok(),
+            Token::Literal(_) => None,
+        }
+    }
+
+    pub fn to_token(&self) -> Token {
+        Token::Element(self.to_string())
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        if self.negative == other.negative {
+            return Self::new(self.negative, add(&self.limbs, &other.limbs));
+        }
+
+        // Different signs, so the one with the bigger magnitude gives the sign
+        match compare(&self.limbs, &other.limbs) {
+            Ordering::Less => Self::new(other.negative, subtract(&other.limbs, &self.limbs)),
+            _ => Self::new(self.negative, subtract(&self.limbs, &other.limbs)),
+        }
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.clone().negate())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Self::new(
+            self.negative != other.negative,
+            multiply(&self.limbs, &other.limbs),
+        )
+    }
+
+    /// Euclidean division, so that the remainder of [`Integer::rem_euclid`] is never negative.
+    /// `None` when dividing by zero.
+    pub fn div_euclid(&self, other: &Self) -> Option<Self> {
+        Some(self.div_rem_euclid(other)?.0)
+    }
+
+    pub fn rem_euclid(&self, other: &Self) -> Option<Self> {
+        Some(self.div_rem_euclid(other)?.1)
+    }
+
+    fn div_rem_euclid(&self, other: &Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+
+        let (quotient, remainder) = divide(&self.limbs, &other.limbs);
+        let quotient = Self::new(self.negative != other.negative, quotient);
+        let remainder = Self::new(self.negative, remainder);
+
+        if !remainder.negative {
+            return Some((quotient, remainder));
+        }
+
+        // Truncation rounded towards zero, but the remainder has to be positive
+        let divisor = Self::new(false, other.limbs.clone());
+        let remainder = remainder.add(&divisor);
+        let quotient = match other.negative {
+            false => quotient.sub(&Self::one()),
+            true => quotient.add(&Self::one()),
+        };
+
+        Some((quotient, remainder))
+    }
+}
+
+impl Ord for Integer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare(&self.limbs, &other.limbs),
+            (true, true) => compare(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ParseIntegerError;
+
+impl FromStr for Integer {
+    type Err = ParseIntegerError;
+
+    /// Parses an optional `-` followed by decimal digits.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+
+        if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(ParseIntegerError);
+        }
+
+        let limbs = digits
+            .as_bytes()
+            .rchunks(DIGITS)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold(0, |limb, digit| limb * 10 + u32::from(digit - b'0'))
+            })
+            .collect();
+
+        Ok(Self::new(negative, limbs))
+    }
+}
+
+impl Display for Integer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some((last, rest)) = self.limbs.split_last() else {
+            return write!(f, "0");
+        };
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+
+        write!(f, "{last}")?;
+
+        for limb in rest.iter().rev() {
+            write!(f, "{limb:0width$}", width = DIGITS)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The length of a numeral at the start of `input`, if it has one.
+pub(crate) fn numeral_len(input: &str) -> Option<usize> {
+    let sign = usize::from(input.starts_with('-'));
+    let digits = input[sign..].bytes().take_while(u8::is_ascii_digit).count();
+
+    (digits > 0).then_some(sign + digits)
+}
+
+/// Compares magnitudes, which might have trailing zeros.
+fn compare(a: &[u32], b: &[u32]) -> Ordering {
+    let significant = |limbs: &[u32]| {
+        limbs
+            .iter()
+            .rposition(|&limb| limb != 0)
+            .map_or(0, |i| i + 1)
+    };
+    let (a, b) = (&a[..significant(a)], &b[..significant(b)]);
+
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+
+    for i in 0..a.len().max(b.len()) {
+        let sum = carry
+            + u64::from(a.get(i).copied().unwrap_or(0))
+            + u64::from(b.get(i).copied().unwrap_or(0));
+
+        result.push((sum % BASE) as u32);
+        carry = sum / BASE;
+    }
+
+    result.push(carry as u32);
+    result
+}
+
+/// `a - b`, where `a` is at least `b`.
+fn subtract(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+
+    for (i, &limb) in a.iter().enumerate() {
+        let subtrahend = u64::from(b.get(i).copied().unwrap_or(0)) + borrow;
+        let limb = u64::from(limb);
+
+        if limb >= subtrahend {
+            result.push((limb - subtrahend) as u32);
+            borrow = 0;
+        } else {
+            result.push((limb + BASE - subtrahend) as u32);
+            borrow = 1;
+        }
+    }
+
+    result
+}
+
+fn multiply(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u64; a.len() + b.len()];
+
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0;
+
+        for (j, &y) in b.iter().enumerate() {
+            let product = result[i + j] + u64::from(x) * u64::from(y) + carry;
+            result[i + j] = product % BASE;
+            carry = product / BASE;
+        }
+
+        result[i + b.len()] += carry;
+    }
+
+    result.into_iter().map(|limb| limb as u32).collect()
+}
+
+/// Long division of magnitudes, finding each limb of the quotient with a binary search.
+fn divide(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let mut quotient = vec![0; a.len()];
+    let mut remainder = Integer::zero();
+
+    for (i, &limb) in a.iter().enumerate().rev() {
+        // remainder * BASE + limb
+        let mut limbs = Vec::with_capacity(remainder.limbs.len() + 1);
+        limbs.push(limb);
+        limbs.extend(&remainder.limbs);
+        remainder = Integer::new(false, limbs);
+
+        let (mut low, mut high) = (0, BASE as u32 - 1);
+        while low < high {
+            let middle = low + (high - low).div_ceil(2);
+            match compare(&multiply(b, &[middle]), &remainder.limbs) {
+                Ordering::Greater => high = middle - 1,
+                _ => low = middle,
+            }
+        }
+
+        quotient[i] = low;
+        remainder = Integer::new(false, subtract(&remainder.limbs, &multiply(b, &[low])));
+    }
+
+    (quotient, remainder.limbs)
+}
+
+/// Registers the functions that `std/int` uses, each named `int_` and what it does.
+pub(crate) fn register(functions: &mut HostFunctions) {
+    fn binary(
+        operation: impl Fn(&Integer, &Integer) -> Option<Token> + Send + Sync + 'static,
+    ) -> impl Fn(&[Token], &Bindings) -> Option<Expression> + Send + Sync + 'static {
+        move |_, bindings| {
+            let x = Integer::from_token(bindings.single.get("x")?)?;
+            let y = Integer::from_token(bindings.single.get("y")?)?;
+            Some(Expression::new(vec![operation(&x, &y)?]))
+        }
+    }
+
+    fn boolean(value: bool) -> Token {
+        Token::Element(value.to_string())
+    }
+
+    functions.insert("int_add", binary(|x, y| Some(x.add(y).to_token())));
+    functions.insert("int_sub", binary(|x, y| Some(x.sub(y).to_token())));
+    functions.insert("int_mul", binary(|x, y| Some(x.mul(y).to_token())));
+    functions.insert("int_div", binary(|x, y| Some(x.div_euclid(y)?.to_token())));
+    functions.insert("int_mod", binary(|x, y| Some(x.rem_euclid(y)?.to_token())));
+    functions.insert("int_lt", binary(|x, y| Some(boolean(x < y))));
+    functions.insert("int_eq", binary(|x, y| Some(boolean(x == y))));
+
+    // `successor ( n )` is `n + 1`
+    functions.insert("int_successor", |_: &[Token], bindings: &Bindings| {
+        let n = Integer::from_token(bindings.single.get("n")?)?;
+        Some(Expression::new(vec![n.add(&Integer::one()).to_token()]))
+    });
+}
@@ -0,0 +1,232 @@
+//! Compact representation of expressions used while searching.
+//!
+//! Tokens are interned into small integer [`Symbol`]s, so that an expression is just a slice of
+//! integers with a precomputed hash. This makes cloning, hashing and comparing expressions much
+//! cheaper than with the owned strings of [`Token`].
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    hash::{BuildHasherDefault, Hash, Hasher},
+    sync::RwLock,
+};
+
+use super::{Expression, Token};
+
+/// An interned [`Token`]. Only meaningful together with the [`Interner`] that created it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct Symbol(u32);
+
+#[derive(Debug, Clone, Default)]
+struct Table {
+    tokens: Vec<Token>,
+    symbols: HashMap<Token, Symbol>,
+}
+
+impl Table {
+    fn insert(&mut self, token: Token) -> Symbol {
+        let symbol = Symbol(self.tokens.len() as u32);
+        self.tokens.push(token.clone());
+        self.symbols.insert(token, symbol);
+        symbol
+    }
+}
+
+/// Maps tokens to symbols and back.
+///
+/// Symbols of the tokens given to [`Interner::new`] are ordered the same way as the tokens
+/// themselves. Tokens interned afterwards (like numerals or the results of host functions) are
+/// appended at the end in whatever order they come up, so symbols only give an arbitrary order.
+/// Use [`Interner::compare`] to order compact expressions like [`Expression`]s.
+#[derive(Debug, Default)]
+pub(crate) struct Interner {
+    table: RwLock<Table>,
+}
+
+impl Interner {
+    pub fn new(tokens: impl IntoIterator<Item = Token>) -> Self {
+        let mut tokens: Vec<_> = tokens.into_iter().collect();
+        tokens.sort();
+        tokens.dedup();
+
+        let mut table = Table::default();
+        for token in tokens {
+            table.insert(token);
+        }
+
+        Self {
+            table: RwLock::new(table),
+        }
+    }
+
+    pub fn intern(&self, token: &Token) -> Symbol {
+        if let Some(symbol) = self.table.read().unwrap().symbols.get(token) {
+            return *symbol;
+        }
+
+        let mut table = self.table.write().unwrap();
+
+        // Someone else might have interned it in the meantime
+        match table.symbols.get(token) {
+            Some(symbol) => *symbol,
+            None => table.insert(token.clone()),
+        }
+    }
+
+    pub fn compact(&self, expression: &Expression) -> CompactExpression {
+        CompactExpression::new(
+            expression
+                .tokens
+                .iter()
+                .map(|token| self.intern(token))
+                .collect(),
+        )
+    }
+
+    pub fn expand(&self, expression: &CompactExpression) -> Expression {
+        Expression::new(self.tokens(&expression.symbols))
+    }
+
+    pub fn is_literal(&self, symbol: Symbol) -> bool {
+        matches!(
+            self.table.read().unwrap().tokens[symbol.0 as usize],
+            Token::Literal(_)
+        )
+    }
+
+    pub fn token(&self, symbol: Symbol) -> Token {
+        self.table.read().unwrap().tokens[symbol.0 as usize].clone()
+    }
+
+    /// Orders compact expressions like the [`Expression`]s they stand for, no matter when their
+    /// tokens were interned.
+    pub fn compare(&self, a: &CompactExpression, b: &CompactExpression) -> Ordering {
+        let table = self.table.read().unwrap();
+
+        a.len().cmp(&b.len()).then_with(|| {
+            a.symbols
+                .iter()
+                .zip(b.symbols.iter())
+                .find(|(a, b)| a != b)
+                .map_or(Ordering::Equal, |(a, b)| {
+                    table.tokens[a.0 as usize].cmp(&table.tokens[b.0 as usize])
+                })
+        })
+    }
+
+    /// The tokens of some symbols.
+    pub fn tokens(&self, symbols: &[Symbol]) -> Vec<Token> {
+        let table = self.table.read().unwrap();
+
+        symbols
+            .iter()
+            .map(|symbol| table.tokens[symbol.0 as usize].clone())
+            .collect()
+    }
+}
+
+impl Clone for Interner {
+    fn clone(&self) -> Self {
+        Self {
+            table: RwLock::new(self.table.read().unwrap().clone()),
+        }
+    }
+}
+
+/// An expression made of symbols, with its hash computed up front.
+///
+/// Ordered shortest first, then by symbol, which is only consistent within a single interner. See
+/// [`Interner::compare`] for the order of the expressions themselves.
+#[derive(Debug, Clone)]
+pub(crate) struct CompactExpression {
+    symbols: Box<[Symbol]>,
+    hash: u64,
+}
+
+impl CompactExpression {
+    pub fn new(symbols: Vec<Symbol>) -> Self {
+        // FNV-1a, which is more than enough for a handful of small integers
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for symbol in &symbols {
+            hash ^= symbol.0 as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+
+        Self {
+            symbols: symbols.into_boxed_slice(),
+            hash,
+        }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Replaces `size` symbols starting at `start` with `replacement`.
+    pub fn splice(&self, start: usize, size: usize, replacement: &[Symbol]) -> Self {
+        let mut symbols = Vec::with_capacity(self.len() - size + replacement.len());
+        symbols.extend_from_slice(&self.symbols[..start]);
+        symbols.extend_from_slice(replacement);
+        symbols.extend_from_slice(&self.symbols[start + size..]);
+
+        Self::new(symbols)
+    }
+}
+
+impl PartialEq for CompactExpression {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.symbols == other.symbols
+    }
+}
+
+impl Eq for CompactExpression {}
+
+impl Hash for CompactExpression {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+impl PartialOrd for CompactExpression {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CompactExpression {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.len().cmp(&other.len()) {
+            Ordering::Equal => self.symbols.cmp(&other.symbols),
+            ordering => ordering,
+        }
+    }
+}
+
+/// Passes through the hash that [`CompactExpression`] already computed.
+#[derive(Debug, Default)]
+pub(crate) struct PrecomputedHasher(u64);
+
+impl Hasher for PrecomputedHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // Only used for types other than `CompactExpression`
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
+}
+
+pub(crate) type CompactSet = HashSet<CompactExpression, BuildHasherDefault<PrecomputedHasher>>;
+pub(crate) type CompactMap<V> =
+    HashMap<CompactExpression, V, BuildHasherDefault<PrecomputedHasher>>;
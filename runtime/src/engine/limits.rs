@@ -0,0 +1,275 @@
+//! Resource budgets for evaluations.
+//!
+//! Some structures can be rewritten forever (think of `n + m <=> m + n` in `std/peano`), so
+//! [`Runtime::evaluations`](super::Runtime::evaluations) can be told when to give up.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use super::{profile::EvalProfile, trace::Trace, Expression};
+
+/// Limits on how much work an evaluation is allowed to do.
+///
+/// Every limit is optional. The default has no limits at all, which means that the search runs
+/// until every reachable expression has been visited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// Maximum number of expressions to visit.
+    pub max_visited: Option<usize>,
+
+    /// Expressions with more tokens than this are discarded instead of being visited.
+    pub max_length: Option<usize>,
+
+    /// Maximum wall time the search is allowed to run for.
+    pub max_time: Option<Duration>,
+
+    /// Stops the search as soon as it is cancelled, from this or any other thread.
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl EvalLimits {
+    /// No limits, search until the visited set is exhausted.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn allows_length(&self, length: usize) -> bool {
+        self.max_length.is_none_or(|max| length <= max)
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+/// A handle to cancel an evaluation that is running somewhere else.
+///
+/// Clones share the same flag, so one clone can be given to the search (through
+/// [`EvalLimits::cancellation`]) while another one is kept around to call [`Self::cancel`], for
+/// example from a signal handler. The flag is polled between expansions.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the evaluation to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Clears a previous cancellation, so that the token can be used for another evaluation.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Two tokens are equal if they share the same flag.
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+impl Eq for CancellationToken {}
+
+/// The limit that cut off a search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// Reached [`EvalLimits::max_visited`].
+    Visited(usize),
+
+    /// Some expressions were discarded because of [`EvalLimits::max_length`].
+    Length(usize),
+
+    /// Ran out of [`EvalLimits::max_time`].
+    Time(Duration),
+
+    /// Some expressions were discarded by a [`SearchStrategy::Beam`](super::strategy::SearchStrategy::Beam) of this width.
+    BeamWidth(usize),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Visited(max) => write!(f, "visited {max} expressions"),
+            Limit::Length(max) => write!(f, "discarded expressions longer than {max} tokens"),
+            Limit::Time(max) => write!(f, "ran for {:.2}s", max.as_secs_f32()),
+            Limit::BeamWidth(width) => write!(f, "kept only {width} expressions per level"),
+        }
+    }
+}
+
+/// How a search ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    /// Every reachable expression was visited.
+    Exhaustive,
+
+    /// The search was stopped before visiting every reachable expression.
+    CutOff(Limit),
+
+    /// The search was stopped through a [`CancellationToken`].
+    Cancelled,
+}
+
+impl Display for Completion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Completion::Exhaustive => write!(f, "search was exhaustive"),
+            Completion::CutOff(limit) => write!(f, "search was cut off ({limit})"),
+            Completion::Cancelled => write!(f, "search was cancelled"),
+        }
+    }
+}
+
+/// The result of [`Runtime::evaluations`](super::Runtime::evaluations).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalOutcome {
+    /// Every expression that was visited, shortest first.
+    pub evaluations: BTreeSet<Expression>,
+
+    /// The evaluation that costs the least.
+    pub best: Expression,
+
+    /// Whether the search was exhaustive or which limit stopped it.
+    pub completion: Completion,
+
+    /// Every rewrite that was found, if [`EvalOptions::trace`](super::options::EvalOptions::trace) was set.
+    pub trace: Option<Trace>,
+
+    /// Statistics about the search, if [`EvalOptions::profile`](super::options::EvalOptions::profile) was set.
+    pub profile: Option<EvalProfile>,
+}
+
+impl EvalOutcome {
+    /// The expression that costs the least among the ones that were found.
+    pub fn best(&self) -> &Expression {
+        &self.best
+    }
+
+    pub fn is_exhaustive(&self) -> bool {
+        self.completion == Completion::Exhaustive
+    }
+}
+
+/// Measures wall time, also on `wasm32` where `std::time::Instant` is not available.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+    start: std::time::Instant,
+
+    /// Milliseconds, as given by `Date.now()`
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    start: f64,
+}
+
+impl Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+    pub fn start() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    pub fn start() -> Self {
+        Self {
+            start: js_sys::Date::now(),
+        }
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64((js_sys::Date::now() - self.start).max(0.0) / 1000.0)
+    }
+}
+
+/// Keeps track of the limits while a search is running.
+pub(crate) struct Budget {
+    limits: EvalLimits,
+    stopwatch: Stopwatch,
+    discarded_long: bool,
+}
+
+impl Budget {
+    pub fn new(limits: &EvalLimits) -> Self {
+        Self {
+            limits: limits.clone(),
+            stopwatch: Stopwatch::start(),
+            discarded_long: false,
+        }
+    }
+
+    /// Whether an expression of this length is short enough to be visited. Remembers if it wasn't.
+    pub fn admits(&mut self, length: usize) -> bool {
+        let admitted = self.limits.allows_length(length);
+        self.discarded_long |= !admitted;
+        admitted
+    }
+
+    /// Returns how the search should end if some limit has been reached.
+    pub fn exceeded(&self, visited: usize) -> Option<Completion> {
+        if self.limits.is_cancelled() {
+            return Some(Completion::Cancelled);
+        }
+
+        if let Some(max) = self.limits.max_visited {
+            if visited >= max {
+                return Some(Completion::CutOff(Limit::Visited(max)));
+            }
+        }
+
+        if let Some(max) = self.limits.max_time {
+            if self.stopwatch.elapsed() >= max {
+                return Some(Completion::CutOff(Limit::Time(max)));
+            }
+        }
+
+        None
+    }
+
+    /// How many more expressions can be visited, if there is a limit.
+    pub fn remaining_visits(&self, visited: usize) -> Option<usize> {
+        self.limits
+            .max_visited
+            .map(|max| max.saturating_sub(visited).max(1))
+    }
+
+    /// The length limit, if it discarded any expression.
+    pub fn discarded(&self) -> Option<Limit> {
+        match (self.discarded_long, self.limits.max_length) {
+            (true, Some(max)) => Some(Limit::Length(max)),
+            _ => None,
+        }
+    }
+
+    /// How the search ended when it ran out of expressions to visit.
+    pub fn finished(&self, pruned: Option<Limit>) -> Completion {
+        pruned
+            .or(self.discarded())
+            .map_or(Completion::Exhaustive, Completion::CutOff)
+    }
+}
@@ -1,4 +1,22 @@
+pub mod completion;
+pub mod confluence;
+pub mod cost;
+pub mod equality;
 pub mod eval;
+pub mod host;
+pub(crate) mod int;
+mod intern;
+pub mod limits;
+pub mod normal;
+pub mod observer;
+pub mod options;
+mod parallel;
+pub mod profile;
+pub mod reduction;
+mod rules;
+pub mod strategy;
+pub mod termination;
+pub mod trace;
 
 #[cfg(test)]
 mod test;
@@ -10,14 +28,22 @@ use std::{
     collections::{BTreeMap, BTreeSet},
     error::Error,
     fmt::Display,
+    sync::Arc,
 };
 
 use crate::{
-    matching::get_match_bindings,
+    matching::{self, Bracket},
     parser::{self, ParseError},
 };
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+use self::{
+    host::HostFunctions,
+    intern::Interner,
+    options::{Grouping, MatchMode},
+    trace::{Bindings, Direction},
+};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum Token {
     /// An actual element of the domain of a structure
     Element(String),
@@ -27,6 +53,16 @@ pub enum Token {
     Literal(String),
 }
 
+impl Token {
+    /// The text of the token, without any formatting.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Token::Element(element) => element,
+            Token::Literal(literal) => literal,
+        }
+    }
+}
+
 #[cfg(not(feature = "wasm"))]
 impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -54,19 +90,77 @@ impl Display for Token {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub enum PatternToken {
+pub enum PatternToken<T = Token> {
     /// Either element or literal
-    Concrete(Token),
+    Concrete(T),
 
     /// Variable that binds to only one token
     Variable(String),
 
     /// Variable that binds arbitrary number of tokens
     SpreadVariable(String),
+
+    /// Like a spread variable, but it can also bind no tokens at all, written like `rest...?`
+    OptionalSpreadVariable(String),
+
+    /// Like a spread variable, but it only binds a single item of a group, like `f ( b )` in
+    /// `{ a, f ( b ) }`, written like `x...: item`
+    ItemVariable(String),
+
+    /// Variable that binds to only one token of some sort, written like `x: element`
+    Sorted(String, Sort<T>),
+
+    /// Matches any one token without binding it, written `_`
+    Wildcard,
+}
+
+impl<T> PatternToken<T> {
+    /// Whether the variable can bind any number of tokens.
+    pub fn is_spread(&self) -> bool {
+        matches!(
+            self,
+            Self::SpreadVariable(_) | Self::OptionalSpreadVariable(_) | Self::ItemVariable(_)
+        )
+    }
+}
+
+/// What a [`PatternToken::Sorted`] variable can bind.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Sort<T = Token> {
+    /// Elements of the domain, written `element`
+    Element,
+
+    /// Reserved literals, written `literal`
+    Literal,
+
+    /// Tokens `x` for which `x in set` is rewritten to `true`, written with the name of the set
+    /// (like `n: Naturals`)
+    Set(T),
+}
+
+impl Sort {
+    /// Whether a token has this sort, or `None` for sets, since that takes rewriting.
+    pub fn contains(&self, token: &Token) -> Option<bool> {
+        match (self, token) {
+            (Sort::Element, token) => Some(matches!(token, Token::Element(_))),
+            (Sort::Literal, token) => Some(matches!(token, Token::Literal(_))),
+            (Sort::Set(_), _) => None,
+        }
+    }
+}
+
+impl<T: Display> Display for Sort<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sort::Element => write!(f, "element"),
+            Sort::Literal => write!(f, "literal"),
+            Sort::Set(set) => write!(f, "{set}"),
+        }
+    }
 }
 
 #[cfg(not(feature = "wasm"))]
-impl Display for PatternToken {
+impl<T: Display> Display for PatternToken<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use termion::style::{Bold, Italic, Reset};
 
@@ -74,22 +168,32 @@ impl Display for PatternToken {
             Self::Concrete(token) => write!(f, "{token}"),
             Self::Variable(name) => write!(f, "{}{name}{}", Bold, Reset),
             Self::SpreadVariable(name) => write!(f, "{}{}{name}{}", Bold, Italic, Reset),
+            Self::OptionalSpreadVariable(name) => {
+                write!(f, "{}{}{name}{}?", Bold, Italic, Reset)
+            }
+            Self::ItemVariable(name) => write!(f, "{}{}{name}{}: item", Bold, Italic, Reset),
+            Self::Sorted(name, sort) => write!(f, "{}{name}{}: {sort}", Bold, Reset),
+            Self::Wildcard => write!(f, "{}_{}", Bold, Reset),
         }
     }
 }
 
 #[cfg(feature = "wasm")]
-impl Display for PatternToken {
+impl<T: Display> Display for PatternToken<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Concrete(token) => write!(f, "{token}"),
             Self::Variable(name) => write!(f, "*{name}*"),
             Self::SpreadVariable(name) => write!(f, "*_{name}_*"),
+            Self::OptionalSpreadVariable(name) => write!(f, "*_{name}_*?"),
+            Self::ItemVariable(name) => write!(f, "*_{name}_*: item"),
+            Self::Sorted(name, sort) => write!(f, "*{name}*: {sort}"),
+            Self::Wildcard => write!(f, "*\\_*"),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct Expression {
     pub tokens: Vec<Token>,
 }
@@ -102,10 +206,7 @@ impl Expression {
 
 impl PartialOrd for Expression {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self.tokens.len().partial_cmp(&other.tokens.len())? {
-            std::cmp::Ordering::Equal => self.tokens.partial_cmp(&other.tokens),
-            ordering => ordering.into(),
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -136,6 +237,56 @@ impl Display for Expression {
     }
 }
 
+/// A condition on what the variables of a definition are bound to, written after its right side
+/// like `x + y => y where x = 0;`. The definition only applies when it holds.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Guard<T = Token> {
+    /// Holds if the condition, with the variables replaced, is rewritten to the element `true`.
+    /// Written `where x = 0`.
+    Normalises(Vec<PatternToken<T>>),
+
+    /// Holds if both sides are different once the variables are replaced, without rewriting
+    /// anything. Written `where x != y`.
+    Differ(Vec<PatternToken<T>>, Vec<PatternToken<T>>),
+}
+
+impl<T> Guard<T> {
+    /// Every token of the guard, in order.
+    pub fn tokens(&self) -> impl Iterator<Item = &PatternToken<T>> + '_ {
+        let (first, second): (&[_], &[_]) = match self {
+            Guard::Normalises(condition) => (condition, &[]),
+            Guard::Differ(a, b) => (a, b),
+        };
+
+        first.iter().chain(second)
+    }
+}
+
+impl<T: Display> Display for Guard<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Guard::Normalises(condition) => {
+                for token in condition {
+                    write!(f, "{} ", token)?;
+                }
+            }
+            Guard::Differ(a, b) => {
+                for token in a {
+                    write!(f, "{} ", token)?;
+                }
+
+                write!(f, "!= ")?;
+
+                for token in b {
+                    write!(f, "{} ", token)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 /// A definition has a "high" and a "low" side. Pink tries to lower the definitions.
 ///
@@ -143,6 +294,13 @@ impl Display for Expression {
 pub struct Definition {
     low: Vec<PatternToken>,
     high: Vec<PatternToken>,
+
+    /// The host function that builds the low side instead, for definitions like `x + y => @add`.
+    /// `low` is empty then.
+    host: Option<String>,
+
+    /// The definition is only lowered when this holds, and can't be read as an equation.
+    guard: Option<Guard>,
 }
 
 impl Definition {
@@ -151,42 +309,131 @@ impl Definition {
         Self {
             high: lhs,
             low: rhs,
+            host: None,
+            guard: None,
         }
     }
 
+    /// A definition that is lowered by the host function `function`, written `lhs => @function`.
+    /// See [`Runtime::register_host_function`].
+    pub fn with_host(lhs: Vec<PatternToken>, function: impl Into<String>) -> Self {
+        Self {
+            high: lhs,
+            low: Vec::new(),
+            host: Some(function.into()),
+            guard: None,
+        }
+    }
+
+    /// The host function that lowers this definition, if any.
+    pub fn host_function(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// The condition under which this definition applies, if any.
+    pub fn guard(&self) -> Option<&Guard> {
+        self.guard.as_ref()
+    }
+
+    pub fn set_guard(&mut self, guard: Option<Guard>) {
+        self.guard = guard;
+    }
+
     /// Transform an expression from one pattern to another.
-    fn transform(
-        from: &[PatternToken],
+    fn transform<'a, 'b>(
+        &self,
+        from: &'a [PatternToken],
         to: &[PatternToken],
-        expression: &[Token],
-    ) -> Option<Expression> {
-        let (single_bindings, spread_bindings) = get_match_bindings(from, expression)?;
+        expression: &'b [Token],
+    ) -> Option<(Expression, matching::Bindings<'a, 'b>)> {
+        // Only the runtime can call host functions
+        if self.host.is_some() {
+            return None;
+        }
 
-        let mut result = Vec::new();
+        let bindings = matching::get_match_bindings(from, expression)?;
+        let (result, bindings) = matching::build(to, bindings)?;
 
-        for token in to {
-            match token {
-                PatternToken::Concrete(token) => result.push(token.clone()),
-                PatternToken::Variable(name) => {
-                    let binding = *single_bindings.get(name)?;
-                    result.push(binding.clone());
-                }
-                PatternToken::SpreadVariable(name) => {
-                    let binding = spread_bindings.get(name)?;
-                    result.extend_from_slice(binding);
-                }
-            };
-        }
+        let holds = match &self.guard {
+            None => true,
+            Some(Guard::Differ(a, b)) => {
+                let build = |side| matching::build(side, bindings.clone()).map(|(side, _)| side);
+                matches!((build(a), build(b)), (Some(a), Some(b)) if a != b)
+            }
+            // Only the runtime can rewrite the condition
+            Some(Guard::Normalises(_)) => false,
+        };
 
-        Some(Expression::new(result))
+        holds.then(|| (Expression::new(result), bindings))
     }
 
+    /// Lowers the expression, or returns `None` if it doesn't match. Definitions that use a host
+    /// function or have a guard like `where x = 0` never match here, since only the runtime can
+    /// call the function or rewrite the condition.
     pub fn lower(&self, expression: &[Token]) -> Option<Expression> {
-        Self::transform(&self.high, &self.low, expression)
+        self.transform(&self.high, &self.low, expression)
+            .map(|(lowered, _)| lowered)
+    }
+
+    /// Same as [`Self::lower`], but also returns what each variable was bound to.
+    pub fn lower_with_bindings(&self, expression: &[Token]) -> Option<(Expression, Bindings)> {
+        self.transform(&self.high, &self.low, expression)
+            .map(|(lowered, bindings)| (lowered, Bindings::from(bindings)))
     }
 
     pub fn raise(&self, expression: &[Token]) -> Option<Expression> {
-        Self::transform(&self.low, &self.high, expression)
+        self.transform(&self.low, &self.high, expression)
+            .map(|(raised, _)| raised)
+    }
+
+    /// Same as [`Self::raise`], but also returns what each variable was bound to.
+    pub fn raise_with_bindings(&self, expression: &[Token]) -> Option<(Expression, Bindings)> {
+        self.transform(&self.low, &self.high, expression)
+            .map(|(raised, bindings)| (raised, Bindings::from(bindings)))
+    }
+
+    /// What each variable is bound to when lowering or raising the expression, only matching
+    /// whole subterms in tree mode.
+    pub(crate) fn bindings(
+        &self,
+        direction: Direction,
+        grouping: Grouping,
+        expression: &[Token],
+    ) -> Option<Bindings> {
+        let pattern = match direction {
+            Direction::Lower => &self.high,
+            Direction::Raise => &self.low,
+        };
+
+        // The runtime already checked whether the tokens are in their sets
+        let has_sort = |token: &Token, sort: &Sort| sort.contains(token).unwrap_or(true);
+
+        let bindings = match grouping {
+            Grouping::Flat => matching::get_match_bindings_where(
+                pattern,
+                expression,
+                &|_| true,
+                &has_sort,
+                &Bracket::of,
+            )?,
+            Grouping::Tree => {
+                let is_grouped = |run: &[Token]| matching::is_grouped(run, Bracket::of);
+
+                if !is_grouped(expression) {
+                    return None;
+                }
+
+                matching::get_match_bindings_where(
+                    pattern,
+                    expression,
+                    &is_grouped,
+                    &has_sort,
+                    &Bracket::of,
+                )?
+            }
+        };
+
+        Some(Bindings::from(bindings))
     }
 }
 
@@ -198,8 +445,17 @@ impl Display for Definition {
 
         write!(f, "=> ")?;
 
-        for token in &self.low {
-            write!(f, "{} ", token)?;
+        match &self.host {
+            Some(function) => write!(f, "@{function} ")?,
+            None => {
+                for token in &self.low {
+                    write!(f, "{} ", token)?;
+                }
+            }
+        }
+
+        if let Some(guard) = &self.guard {
+            write!(f, "where {guard}")?;
         }
 
         Ok(())
@@ -211,6 +467,17 @@ pub struct Structure {
     domain: BTreeSet<String>,
     reserved: BTreeSet<String>,
     definitions: Vec<Definition>,
+    match_mode: MatchMode,
+    grouping: Grouping,
+
+    /// Whether every decimal integer, like `-12`, is an element of the domain.
+    numerals: bool,
+
+    /// Cost of each token that doesn't cost 1, by its text.
+    weights: BTreeMap<String, u64>,
+
+    /// Registered with [`Structure::register_host_function`].
+    host_functions: HostFunctions,
 }
 
 impl Structure {
@@ -232,6 +499,11 @@ impl Structure {
             domain,
             reserved,
             definitions,
+            match_mode: MatchMode::default(),
+            grouping: Grouping::default(),
+            numerals: false,
+            weights: BTreeMap::new(),
+            host_functions: HostFunctions::default(),
         })
     }
 
@@ -245,6 +517,11 @@ impl Structure {
             domain: BTreeSet::new(),
             reserved,
             definitions: Vec::new(),
+            match_mode: MatchMode::default(),
+            grouping: Grouping::default(),
+            numerals: false,
+            weights: BTreeMap::new(),
+            host_functions: HostFunctions::default(),
         }
     }
 
@@ -254,6 +531,11 @@ impl Structure {
             domain: BTreeSet::new(),
             reserved: BTreeSet::new(),
             definitions: Vec::new(),
+            match_mode: MatchMode::default(),
+            grouping: Grouping::default(),
+            numerals: false,
+            weights: BTreeMap::new(),
+            host_functions: HostFunctions::default(),
         }
     }
 
@@ -264,6 +546,46 @@ impl Structure {
     pub fn get_domain(&self) -> &BTreeSet<String> {
         &self.domain
     }
+
+    pub fn get_match_mode(&self) -> MatchMode {
+        self.match_mode
+    }
+
+    /// Sets which of the definitions of this structure are applied to a window. With
+    /// [`MatchMode::First`], a match stops the search for definitions of any structure on that
+    /// window.
+    pub fn set_match_mode(&mut self, match_mode: MatchMode) {
+        self.match_mode = match_mode;
+    }
+
+    pub fn get_grouping(&self) -> Grouping {
+        self.grouping
+    }
+
+    /// Sets which windows the definitions of this structure can rewrite, see [`Grouping`].
+    pub fn set_grouping(&mut self, grouping: Grouping) {
+        self.grouping = grouping;
+    }
+
+    pub fn has_numerals(&self) -> bool {
+        self.numerals
+    }
+
+    /// Sets whether every decimal integer is an element of the domain. Arithmetic on them is
+    /// defined by `std/int`.
+    pub fn set_numerals(&mut self, numerals: bool) {
+        self.numerals = numerals;
+    }
+
+    pub fn get_weights(&self) -> &BTreeMap<String, u64> {
+        &self.weights
+    }
+
+    /// Sets how much each token costs when picking the best evaluation, see
+    /// [`CostModel::Weights`](cost::CostModel::Weights).
+    pub fn set_weights(&mut self, weights: BTreeMap<String, u64>) {
+        self.weights = weights;
+    }
 }
 
 impl Display for Structure {
@@ -274,6 +596,27 @@ impl Display for Structure {
         let reserved: Vec<_> = self.reserved.iter().cloned().collect();
         writeln!(f, "Reserved: {{ {} }}", reserved.join(", "))?;
 
+        if self.match_mode != MatchMode::default() {
+            writeln!(f, "Match: {}", self.match_mode)?;
+        }
+
+        if self.grouping != Grouping::default() {
+            writeln!(f, "Grouping: {}", self.grouping)?;
+        }
+
+        if self.numerals {
+            writeln!(f, "Numerals: integers")?;
+        }
+
+        if !self.weights.is_empty() {
+            let weights: Vec<_> = self
+                .weights
+                .iter()
+                .map(|(token, weight)| format!("{token} = {weight}"))
+                .collect();
+            writeln!(f, "Cost: {{ {} }}", weights.join(", "))?;
+        }
+
         writeln!(f, "Definitions: ")?;
 
         for definition in &self.definitions {
@@ -306,35 +649,83 @@ impl Display for StructureError {
 
 impl Error for StructureError {}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct Runtime {
     pub structures: BTreeMap<String, Structure>,
+
+    /// Symbols for every token of the structures, used while searching.
+    ///
+    /// Tokens added by mutating `structures` directly are interned on demand.
+    ///
+    /// Shared with clones of the runtime and with running evaluations.
+    interner: Arc<Interner>,
+
+    host_functions: HostFunctions,
 }
 
 impl Runtime {
     pub fn new(structures: BTreeMap<String, Structure>) -> Self {
-        Self { structures }
+        let interner = Arc::new(Interner::new(Self::tokens(&structures)));
+
+        Self {
+            structures,
+            interner,
+            host_functions: HostFunctions::builtin(),
+        }
+    }
+
+    /// Every token that appears in the given structures.
+    fn tokens(structures: &BTreeMap<String, Structure>) -> impl Iterator<Item = Token> + '_ {
+        structures.values().flat_map(Self::structure_tokens)
+    }
+
+    /// Every token that appears in a single structure.
+    fn structure_tokens(structure: &Structure) -> impl Iterator<Item = Token> + '_ {
+        let domain = structure.domain.iter().cloned().map(Token::Element);
+        let reserved = structure.reserved.iter().cloned().map(Token::Literal);
+        let concrete = structure
+            .definitions
+            .iter()
+            .flat_map(|definition| {
+                let guard = definition.guard.iter().flat_map(Guard::tokens);
+                definition.high.iter().chain(&definition.low).chain(guard)
+            })
+            .filter_map(|token| match token {
+                PatternToken::Concrete(token) => Some(token.clone()),
+                _ => None,
+            });
+
+        domain.chain(reserved).chain(concrete)
+    }
+
+    pub(crate) fn interner(&self) -> &Arc<Interner> {
+        &self.interner
     }
 
     /// Iterator that goes through each element in the domains of the structures of the runtime
     pub fn domain(&self) -> impl Iterator<Item = &String> + '_ {
         self.structures
-            .iter()
-            .flat_map(|(_name, structure)| structure.domain.iter())
+            .values()
+            .flat_map(|structure| structure.domain.iter())
     }
 
     /// Iterator that goes through each literal in the reserved keywords of the structures of the runtime
     pub fn reserved(&self) -> impl Iterator<Item = &String> + '_ {
         self.structures
-            .iter()
-            .flat_map(|(_name, structure)| structure.reserved.iter())
+            .values()
+            .flat_map(|structure| structure.reserved.iter())
     }
 
     /// Iterator that goes through each literal in the reserved keywords of the structures of the runtime
     pub fn definitions(&self) -> impl Iterator<Item = &Definition> + '_ {
         self.structures
-            .iter()
-            .flat_map(|(_name, structure)| structure.definitions.iter())
+            .values()
+            .flat_map(|structure| structure.definitions.iter())
+    }
+
+    /// Whether any structure has every decimal integer in its domain.
+    pub fn has_numerals(&self) -> bool {
+        self.structures.values().any(Structure::has_numerals)
     }
 
     pub fn contains(&self, name: &str) -> bool {
@@ -342,19 +733,20 @@ impl Runtime {
     }
 
     pub fn insert(&mut self, name: String, structure: Structure) -> Option<Structure> {
+        for token in Self::structure_tokens(&structure) {
+            self.interner.intern(&token);
+        }
+
         self.structures.insert(name, structure)
     }
 
     pub fn from_partial(structures: &BTreeMap<String, Option<Structure>>) -> Self {
-        let mut runtime = Runtime::new(BTreeMap::new());
-
-        for (name, structure) in structures {
-            if let Some(structure) = structure {
-                runtime.insert(name.to_owned(), structure.to_owned());
-            }
-        }
+        let structures = structures
+            .iter()
+            .filter_map(|(name, structure)| Some((name.to_owned(), structure.clone()?)))
+            .collect();
 
-        runtime
+        Runtime::new(structures)
     }
 
     pub fn parse_expression(&self, expression: &str) -> Result<Expression, ParseError> {
@@ -362,6 +754,15 @@ impl Runtime {
     }
 }
 
+/// Runtimes are equal if they have the same structures, regardless of how tokens were interned.
+impl PartialEq for Runtime {
+    fn eq(&self, other: &Self) -> bool {
+        self.structures == other.structures
+    }
+}
+
+impl Eq for Runtime {}
+
 impl Display for Runtime {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (name, structure) in &self.structures {
@@ -0,0 +1,145 @@
+//! The expressions that an evaluation can't rewrite any further.
+//!
+//! An evaluation visits every expression it can reach, most of which are just steps along the
+//! way. Its normal forms are the ones that no definition lowers. When rewriting in any order ends
+//! in the same place, there is only one, so finding several of them for the same query points at
+//! definitions that don't agree with each other (see also
+//! [`Runtime::check_confluence`](super::Runtime::check_confluence)).
+
+use std::{fmt::Display, ops::ControlFlow};
+
+use super::{
+    intern::{CompactExpression, CompactMap, CompactSet, Interner},
+    limits::Completion,
+    observer::EvalEvent,
+    options::EvalOptions,
+    rules::RuleSet,
+    Expression, Runtime,
+};
+
+/// An expression that no definition lowers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalForm {
+    pub expression: Expression,
+
+    /// How many of the visited expressions (including the normal form itself) can be rewritten
+    /// into it, which is how likely it is to be the result of rewriting in some arbitrary order.
+    pub reached_from: usize,
+}
+
+/// The result of [`Runtime::normal_forms`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalForms {
+    /// Shortest first.
+    pub forms: Vec<NormalForm>,
+
+    pub visited: usize,
+
+    /// Whether the search was exhaustive or which limit stopped it. Unless it was exhaustive,
+    /// there might be more normal forms.
+    pub completion: Completion,
+}
+
+impl NormalForms {
+    /// Whether every path of rewrites that was found ends in the same expression.
+    pub fn is_unique(&self) -> bool {
+        self.forms.len() == 1
+    }
+}
+
+impl Display for NormalForms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.forms.len() {
+            0 => writeln!(f, "No normal forms found")?,
+            1 => writeln!(f, "1 normal form:")?,
+            count => writeln!(f, "{count} normal forms:")?,
+        }
+
+        for form in &self.forms {
+            writeln!(
+                f,
+                "{:>10}  {}",
+                form.reached_from,
+                form.expression.to_string().trim_end()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the normal forms among `visited`, counting which of the other visited expressions
+/// reach each of them.
+pub(crate) fn normal_forms(
+    rules: &RuleSet,
+    visited: &CompactSet,
+    interner: &Interner,
+) -> Vec<NormalForm> {
+    let expressions: Vec<&CompactExpression> = visited.iter().collect();
+    let indices: CompactMap<usize> = expressions
+        .iter()
+        .enumerate()
+        .map(|(i, &expression)| (expression.clone(), i))
+        .collect();
+
+    // The visited expressions that lower into each one
+    let mut parents: Vec<Vec<usize>> = vec![Vec::new(); expressions.len()];
+    let mut normal = Vec::new();
+
+    for (i, expression) in expressions.iter().enumerate() {
+        let neighbours = rules.lower_neighbours(expression);
+
+        if neighbours.is_empty() {
+            normal.push(i);
+        }
+
+        for (neighbour, _) in neighbours {
+            if let Some(&j) = indices.get(&neighbour) {
+                parents[j].push(i);
+            }
+        }
+    }
+
+    let mut forms: Vec<_> = normal
+        .into_iter()
+        .map(|i| NormalForm {
+            expression: interner.expand(expressions[i]),
+            reached_from: ancestors(&parents, i),
+        })
+        .collect();
+
+    forms.sort_by(|a, b| a.expression.cmp(&b.expression));
+    forms
+}
+
+/// How many nodes can reach `start` (including itself) following `parents`.
+fn ancestors(parents: &[Vec<usize>], start: usize) -> usize {
+    let mut seen = vec![false; parents.len()];
+    let mut stack = vec![start];
+    seen[start] = true;
+
+    let mut count = 0;
+    while let Some(node) = stack.pop() {
+        count += 1;
+
+        for &parent in &parents[node] {
+            if !std::mem::replace(&mut seen[parent], true) {
+                stack.push(parent);
+            }
+        }
+    }
+
+    count
+}
+
+impl Runtime {
+    /// Evaluates `expression` like [`Runtime::evaluations`], but returns only the expressions
+    /// that can't be rewritten any further.
+    pub fn normal_forms(&self, expression: Expression, options: &EvalOptions) -> NormalForms {
+        let mut evaluation = self.start_evaluation(expression, options);
+        let mut observer = |_: EvalEvent<'_>| ControlFlow::Continue(());
+
+        while evaluation.step(usize::MAX, &mut observer).is_none() {}
+        evaluation.normal_forms()
+    }
+}
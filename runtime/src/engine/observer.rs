@@ -0,0 +1,142 @@
+//! Following an evaluation while it runs.
+//!
+//! [`Runtime::evaluate_with`](super::Runtime::evaluate_with) sends an [`EvalEvent`] to an
+//! [`EvalObserver`] whenever something happens, so that embedders can log, render or stop the
+//! search without keeping track of the visited expressions themselves.
+
+use std::{collections::BTreeSet, ops::ControlFlow, time::Duration};
+
+use super::{
+    intern::{CompactExpression, CompactSet, Interner},
+    limits::{Completion, Limit},
+    Expression,
+};
+
+/// Something that happened during an evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalEvent<'a> {
+    /// An expression was visited for the first time. Only sent if
+    /// [`EvalObserver::wants_visited`].
+    Visited(&'a Expression),
+
+    /// An expression cheaper than every one before it was visited, this long after the
+    /// evaluation started. The original expression is always the first one.
+    NewBest(&'a Expression, Duration),
+
+    /// Expressions started being left out because of this limit. Sent once per limit.
+    Pruned(Limit),
+
+    /// The evaluation ended. Always the last event.
+    Finished(&'a EvalStats),
+}
+
+/// Summary of an evaluation, sent with [`EvalEvent::Finished`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalStats {
+    pub visited: usize,
+    pub elapsed: Duration,
+    pub completion: Completion,
+}
+
+/// Every expression visited so far, kept compact. Expressions are only turned back into
+/// [`Expression`]s when asked for.
+pub struct VisitedSoFar<'a> {
+    pub(crate) visited: &'a CompactSet,
+    pub(crate) latest: &'a CompactExpression,
+    pub(crate) interner: &'a Interner,
+}
+
+impl VisitedSoFar<'_> {
+    pub fn len(&self) -> usize {
+        self.visited.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.visited.is_empty()
+    }
+
+    /// The expression that was just visited.
+    pub fn latest(&self) -> Expression {
+        self.interner.expand(self.latest)
+    }
+
+    pub fn contains(&self, expression: &Expression) -> bool {
+        self.visited.contains(&self.interner.compact(expression))
+    }
+
+    /// Every visited expression, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = Expression> + '_ {
+        self.visited
+            .iter()
+            .map(|expression| self.interner.expand(expression))
+    }
+
+    pub fn to_set(&self) -> BTreeSet<Expression> {
+        self.iter().collect()
+    }
+}
+
+/// Receives the events of an evaluation.
+///
+/// Returning [`ControlFlow::Break`] stops the evaluation, which then ends as
+/// [`Completion::Cancelled`].
+pub trait EvalObserver {
+    fn observe(&mut self, event: EvalEvent<'_>) -> ControlFlow<()>;
+
+    /// Whether to send [`EvalEvent::Visited`], which costs turning every visited expression back
+    /// into an [`Expression`].
+    fn wants_visited(&self) -> bool {
+        true
+    }
+
+    /// Called after every newly visited expression, with all of them so far. Does nothing by
+    /// default.
+    fn visited_so_far(&mut self, _visited: &VisitedSoFar<'_>) {}
+}
+
+/// Closures only get [`EvalEvent::Visited`] when wrapped in [`WithVisited`], so that following
+/// the best expression doesn't cost expanding every other one.
+impl<F: FnMut(EvalEvent<'_>) -> ControlFlow<()>> EvalObserver for F {
+    fn observe(&mut self, event: EvalEvent<'_>) -> ControlFlow<()> {
+        self(event)
+    }
+
+    fn wants_visited(&self) -> bool {
+        false
+    }
+}
+
+/// A closure observer that gets [`EvalEvent::Visited`] as well.
+pub struct WithVisited<F>(pub F);
+
+impl<F: FnMut(EvalEvent<'_>) -> ControlFlow<()>> EvalObserver for WithVisited<F> {
+    fn observe(&mut self, event: EvalEvent<'_>) -> ControlFlow<()> {
+        (self.0)(event)
+    }
+}
+
+/// Runs a callback with every expression visited so far each time one is visited, for
+/// [`Runtime::evaluations`](super::Runtime::evaluations).
+pub(crate) struct OnVisited<F> {
+    callback: F,
+}
+
+impl<F> OnVisited<F> {
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F: FnMut(&VisitedSoFar<'_>)> EvalObserver for OnVisited<F> {
+    fn observe(&mut self, _event: EvalEvent<'_>) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    fn wants_visited(&self) -> bool {
+        false
+    }
+
+    fn visited_so_far(&mut self, visited: &VisitedSoFar<'_>) {
+        (self.callback)(visited);
+    }
+}
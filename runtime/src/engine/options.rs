@@ -0,0 +1,114 @@
+//! Per-query configuration of evaluations.
+
+use std::fmt::Display;
+
+use super::{cost::CostModel, limits::EvalLimits, strategy::SearchStrategy};
+
+/// Everything that can be configured about a single call to
+/// [`Runtime::evaluations`](super::Runtime::evaluations).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalOptions {
+    pub limits: EvalLimits,
+    pub strategy: SearchStrategy,
+
+    /// Record every rewrite, so that derivations can be rebuilt with
+    /// [`Runtime::derivation`](super::Runtime::derivation) afterwards.
+    pub trace: bool,
+
+    /// Count how often each definition is tried and applied, along with other statistics, and
+    /// return them as an [`EvalProfile`](super::profile::EvalProfile). Timing every expansion
+    /// makes the search a bit slower.
+    pub profile: bool,
+
+    /// Number of threads that expand expressions at the same time. `1` searches on the calling
+    /// thread and `0` uses every available core.
+    ///
+    /// Iterative deepening always runs on a single thread, and so does everything on `wasm32`.
+    pub threads: usize,
+
+    /// Overrides the [`MatchMode`] of every structure, if set.
+    pub match_mode: Option<MatchMode>,
+
+    /// Overrides the [`Grouping`] of every structure, if set.
+    pub grouping: Option<Grouping>,
+
+    /// Overrides how the best evaluation is picked, if set. Otherwise the weights declared by
+    /// the structures are used, see [`Runtime::cost_model`](super::Runtime::cost_model).
+    pub cost: Option<CostModel>,
+}
+
+/// Which definitions are applied to a window of an expression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MatchMode {
+    /// Only the first definition that matches, in the order they were defined. Later definitions
+    /// act as fallbacks, as in `true and true => true; p and q => false;`.
+    #[default]
+    First,
+
+    /// Every definition that matches, each giving a different neighbour.
+    All,
+}
+
+impl Display for MatchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchMode::First => write!(f, "first"),
+            MatchMode::All => write!(f, "all"),
+        }
+    }
+}
+
+/// Which windows of an expression the definitions are applied to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Grouping {
+    /// Any run of tokens, even one that starts inside parentheses and ends outside of them.
+    #[default]
+    Flat,
+
+    /// Expressions are trees, where `(`/`)` and `{`/`}` delimit subterms. Definitions only
+    /// rewrite whole subterms or runs of siblings, and variables only bind those too, so
+    /// `~false or true` can only be read one way once it is written as `(~false) or true`.
+    Tree,
+}
+
+impl Display for Grouping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Grouping::Flat => write!(f, "flat"),
+            Grouping::Tree => write!(f, "tree"),
+        }
+    }
+}
+
+impl EvalOptions {
+    pub fn new(limits: EvalLimits, strategy: SearchStrategy) -> Self {
+        Self {
+            limits,
+            strategy,
+            trace: false,
+            profile: false,
+            threads: 1,
+            match_mode: None,
+            grouping: None,
+            cost: None,
+        }
+    }
+
+    /// The number of threads to actually use, resolving `0` to the available parallelism.
+    pub(crate) fn thread_count(&self) -> usize {
+        if cfg!(target_arch = "wasm32") {
+            return 1;
+        }
+
+        match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            threads => threads,
+        }
+    }
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self::new(EvalLimits::default(), SearchStrategy::default())
+    }
+}
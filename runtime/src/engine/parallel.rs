@@ -0,0 +1,283 @@
+//! Parallel expansion of the search frontier.
+//!
+//! Finding the neighbours of an expression is pure, so a batch of expressions from the frontier
+//! can be expanded on several threads at once. Threads claim expressions through a shared visited
+//! set, so that no expression is expanded twice. They are started once per search, or per step of
+//! an [`Evaluation`](super::eval::Evaluation), and fed batches through channels, since starting
+//! threads for every batch costs more than expanding a small one.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::Scope,
+};
+
+use super::{
+    eval::SearchState,
+    intern::CompactExpression,
+    limits::{Budget, Completion},
+    observer::EvalObserver,
+    profile::Counters,
+    rules::{RuleSet, Step},
+    strategy::Frontier,
+};
+
+/// A hash set split into independently locked shards, so that threads rarely wait on each other.
+pub(crate) struct ConcurrentSet<T> {
+    shards: Vec<Mutex<HashSet<T>>>,
+}
+
+impl<T: Hash + Eq> ConcurrentSet<T> {
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| Mutex::new(HashSet::new()))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, value: &T) -> &Mutex<HashSet<T>> {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    /// Returns whether the value was not in the set already.
+    pub fn insert(&self, value: T) -> bool {
+        self.shard(&value).lock().unwrap().insert(value)
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.shard(value).lock().unwrap().contains(value)
+    }
+}
+
+/// How many expressions are expanded per batch, for each thread.
+const BATCH_SIZE_PER_THREAD: usize = 32;
+
+/// An expression together with its neighbours.
+type Expansion = (CompactExpression, Vec<(CompactExpression, Step)>);
+
+/// Like a sequential frontier search, but expanding batches of the frontier on `threads` threads.
+/// The threads are started once and stay alive until the search ends or pauses.
+pub(super) fn frontier_search<O: EvalObserver>(
+    frontier: &mut Frontier,
+    threads: usize,
+    claimed: &ConcurrentSet<CompactExpression>,
+    rules: &RuleSet,
+    budget: &mut Budget,
+    state: &mut SearchState<O>,
+) -> ControlFlow<Completion> {
+    std::thread::scope(|scope| {
+        let workers = Workers::spawn(
+            scope,
+            threads,
+            rules,
+            claimed,
+            !state.is_tracing(),
+            state.is_profiling(),
+        );
+
+        loop {
+            if state.exceeded(budget)? {
+                return ControlFlow::Continue(());
+            }
+
+            let mut batch_size = (threads * BATCH_SIZE_PER_THREAD).min(state.remaining_in_step());
+            if let Some(max) = budget.remaining_visits(state.visited_count()) {
+                batch_size = batch_size.min(max);
+            }
+
+            let batch = frontier.pop_batch(batch_size);
+
+            if batch.is_empty() {
+                return ControlFlow::Break(budget.finished(frontier.pruned()));
+            }
+
+            let (expansions, counters) = workers.expand(batch);
+
+            if let Some(counters) = counters {
+                state.merge(counters);
+            }
+
+            for (expression, neighbours) in expansions {
+                for (neighbour, step) in neighbours {
+                    if !budget.admits(neighbour.len()) {
+                        continue;
+                    }
+
+                    state.record(&expression, &neighbour, step);
+
+                    match claimed.contains(&neighbour) {
+                        true => state.duplicate(),
+                        false => frontier.push(neighbour),
+                    }
+                }
+
+                state.frontier_size(frontier.len());
+
+                state.prune(budget.discarded());
+                state.prune(frontier.pruned());
+                state.visit(expression);
+            }
+        }
+    })
+}
+
+/// A batch shared by every thread, which take its expressions one at a time.
+struct Batch {
+    expressions: Vec<CompactExpression>,
+    next: AtomicUsize,
+}
+
+/// What a thread expanded of a batch, with the index of each expression in it.
+type Reply = (Vec<(usize, Expansion)>, Option<Counters>);
+
+/// The current thread together with `threads - 1` more, which expand the batches they are sent
+/// until they are dropped.
+struct Workers<'a> {
+    batches: Vec<Sender<Arc<Batch>>>,
+    replies: Receiver<Reply>,
+    rules: &'a RuleSet,
+    claimed: &'a ConcurrentSet<CompactExpression>,
+    skip_claimed: bool,
+    profiling: bool,
+}
+
+impl<'a> Workers<'a> {
+    /// Neighbours that have already been claimed are dropped if `skip_claimed` is set. They are
+    /// still needed when tracing, since they are edges of the derivation graph.
+    fn spawn(
+        scope: &'a Scope<'a, '_>,
+        threads: usize,
+        rules: &'a RuleSet,
+        claimed: &'a ConcurrentSet<CompactExpression>,
+        skip_claimed: bool,
+        profiling: bool,
+    ) -> Self {
+        let (reply, replies) = mpsc::channel();
+
+        let batches = (1..threads)
+            .map(|_| {
+                let (sender, batches) = mpsc::channel::<Arc<Batch>>();
+                let reply = reply.clone();
+
+                scope.spawn(move || {
+                    for batch in batches {
+                        let expanded = expand(&batch, rules, claimed, skip_claimed, profiling);
+                        if reply.send(expanded).is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                sender
+            })
+            .collect();
+
+        Self {
+            batches,
+            replies,
+            rules,
+            claimed,
+            skip_claimed,
+            profiling,
+        }
+    }
+
+    /// Expands every expression of the batch that hasn't been claimed yet. The results are in
+    /// the same order as the batch, so that the search stays deterministic.
+    ///
+    /// With profiling, also returns what every thread counted, added up.
+    ///
+    /// A batch of a single expression is expanded right away, without waking any other thread.
+    fn expand(&self, expressions: Vec<CompactExpression>) -> (Vec<Expansion>, Option<Counters>) {
+        let helpers = self.batches.len().min(expressions.len() - 1);
+        let batch = Arc::new(Batch {
+            expressions,
+            next: AtomicUsize::new(0),
+        });
+
+        for sender in &self.batches[..helpers] {
+            sender.send(batch.clone()).expect("Worker thread panicked");
+        }
+
+        let (mut expansions, mut total) = expand(
+            &batch,
+            self.rules,
+            self.claimed,
+            self.skip_claimed,
+            self.profiling,
+        );
+
+        for _ in 0..helpers {
+            let (expanded, counters) = self.replies.recv().expect("Worker thread panicked");
+
+            if let (Some(total), Some(counters)) = (&mut total, counters) {
+                total.merge(counters);
+            }
+
+            expansions.extend(expanded);
+        }
+
+        expansions.sort_by_key(|(index, _)| *index);
+        let expansions = expansions
+            .into_iter()
+            .map(|(_, expansion)| expansion)
+            .collect();
+
+        (expansions, total)
+    }
+}
+
+/// Expands expressions of the batch on the current thread until none are left.
+fn expand(
+    batch: &Batch,
+    rules: &RuleSet,
+    claimed: &ConcurrentSet<CompactExpression>,
+    skip_claimed: bool,
+    profiling: bool,
+) -> Reply {
+    let mut expansions = Vec::new();
+    let mut counters = profiling.then(|| Counters::new(rules.len()));
+
+    loop {
+        let index = batch.next.fetch_add(1, Ordering::Relaxed);
+        let Some(expression) = batch.expressions.get(index) else {
+            break;
+        };
+
+        if !claimed.insert(expression.clone()) {
+            if let Some(counters) = &mut counters {
+                counters.duplicates += 1;
+            }
+            continue;
+        }
+
+        let neighbours = match &mut counters {
+            Some(counters) => rules.counted_lower_neighbours(expression, counters),
+            None => rules.lower_neighbours(expression),
+        };
+
+        let neighbours = neighbours
+            .into_iter()
+            .filter(|(neighbour, _)| {
+                let keep = !skip_claimed || !claimed.contains(neighbour);
+                if let (false, Some(counters)) = (keep, &mut counters) {
+                    counters.duplicates += 1;
+                }
+                keep
+            })
+            .collect();
+
+        expansions.push((index, (expression.clone(), neighbours)));
+    }
+
+    (expansions, counters)
+}
@@ -0,0 +1,137 @@
+//! Statistics about an evaluation, to tune definitions.
+//!
+//! When [`EvalOptions::profile`](super::options::EvalOptions::profile) is set, the search counts
+//! how often each definition is tried and how often it rewrites something, along with how much
+//! work the search did overall. That's enough to spot definitions that are hot (tried on most
+//! windows), dead (never apply) or explosive (apply so often that the search never ends).
+
+use std::{fmt::Display, time::Duration};
+
+use super::Definition;
+
+/// How a single definition fared during an evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionProfile {
+    pub definition: Definition,
+
+    /// Windows the definition was matched against. Windows of the wrong length or that start or
+    /// end with the wrong token are ruled out before that, so they don't count.
+    pub attempts: usize,
+
+    /// Windows the definition rewrote.
+    pub successes: usize,
+}
+
+/// Statistics about an evaluation, returned in
+/// [`EvalOutcome::profile`](super::limits::EvalOutcome::profile).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalProfile {
+    pub visited: usize,
+
+    /// The most expressions that were waiting to be visited at once.
+    pub frontier_peak: usize,
+
+    /// How many times an expression was found again after it was visited (or while it was already
+    /// waiting, since those are dropped when they come up).
+    pub duplicates: usize,
+
+    /// Time spent finding the neighbours of expressions, which is mostly matching definitions.
+    /// With several threads, it is added up over all of them.
+    pub matching: Duration,
+
+    /// Every definition, in the order of [`Runtime::definitions`](super::Runtime::definitions).
+    pub definitions: Vec<DefinitionProfile>,
+}
+
+impl EvalProfile {
+    /// Definitions that never rewrote anything.
+    pub fn dead(&self) -> impl Iterator<Item = &DefinitionProfile> + '_ {
+        self.definitions
+            .iter()
+            .filter(|profile| profile.successes == 0)
+    }
+}
+
+impl Display for EvalProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Visited {} expressions, found {} again and had at most {} waiting. Matching took {:.2?}.",
+            self.visited,
+            self.duplicates,
+            self.frontier_peak,
+            self.matching,
+        )?;
+
+        writeln!(f, "{:>10} {:>10}", "attempts", "successes")?;
+
+        for profile in &self.definitions {
+            writeln!(
+                f,
+                "{:>10} {:>10}  {}",
+                profile.attempts, profile.successes, profile.definition
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What is counted while searching, before it is turned into an [`EvalProfile`]. Threads count
+/// separately and merge their counts afterwards.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Counters {
+    pub frontier_peak: usize,
+    pub duplicates: usize,
+    pub matching: Duration,
+
+    /// Indexed like the definitions of a [`RuleSet`](super::rules::RuleSet).
+    pub attempts: Vec<usize>,
+    pub successes: Vec<usize>,
+}
+
+impl Counters {
+    pub fn new(definitions: usize) -> Self {
+        Self {
+            attempts: vec![0; definitions],
+            successes: vec![0; definitions],
+            ..Self::default()
+        }
+    }
+
+    pub fn frontier_size(&mut self, size: usize) {
+        self.frontier_peak = self.frontier_peak.max(size);
+    }
+
+    pub fn merge(&mut self, other: Counters) {
+        self.frontier_peak = self.frontier_peak.max(other.frontier_peak);
+        self.duplicates += other.duplicates;
+        self.matching += other.matching;
+
+        for (total, count) in self.attempts.iter_mut().zip(other.attempts) {
+            *total += count;
+        }
+
+        for (total, count) in self.successes.iter_mut().zip(other.successes) {
+            *total += count;
+        }
+    }
+
+    pub fn profile(&self, visited: usize, definitions: &[Definition]) -> EvalProfile {
+        EvalProfile {
+            visited,
+            frontier_peak: self.frontier_peak,
+            duplicates: self.duplicates,
+            matching: self.matching,
+            definitions: definitions
+                .iter()
+                .zip(self.attempts.iter().zip(&self.successes))
+                .map(|(definition, (&attempts, &successes))| DefinitionProfile {
+                    definition: definition.clone(),
+                    attempts,
+                    successes,
+                })
+                .collect(),
+        }
+    }
+}
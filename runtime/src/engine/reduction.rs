@@ -0,0 +1,236 @@
+//! Normalising an expression one redex at a time, the way a functional language evaluates it.
+//!
+//! A redex is a window that some definition lowers. Windows nest like subterms do: a redex is
+//! outermost if no other redex contains it, and innermost if it doesn't contain any other one.
+//! Rewriting only some redexes at each step follows a single path to a normal form instead of
+//! visiting every evaluation, which is much faster for confluent definitions (like the conditionals
+//! of `std/core`), but might miss smaller evaluations or never end otherwise.
+
+use std::{fmt::Display, str::FromStr};
+
+use super::{
+    intern::{CompactExpression, Symbol},
+    limits::{Budget, Completion, EvalLimits},
+    rules::RuleSet,
+    strategy::ParseStrategyError,
+    Expression, Runtime,
+};
+
+/// Which redexes [`Runtime::reduce`] rewrites at each step.
+///
+/// When several definitions lower the same window, the first one is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReductionStrategy {
+    /// The leftmost of the redexes that don't contain any other, so arguments are rewritten
+    /// before what they are passed to (like call by value).
+    LeftmostInnermost,
+
+    /// The leftmost of the redexes that no other contains, so arguments are only rewritten once
+    /// nothing around them can be (like call by name).
+    LeftmostOutermost,
+
+    /// Every outermost redex at once, skipping the ones that overlap one to their left. Rewriting
+    /// all of them counts as a single step.
+    ParallelOutermost,
+}
+
+impl Display for ReductionStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReductionStrategy::LeftmostInnermost => write!(f, "innermost"),
+            ReductionStrategy::LeftmostOutermost => write!(f, "outermost"),
+            ReductionStrategy::ParallelOutermost => write!(f, "parallel-outermost"),
+        }
+    }
+}
+
+/// Parses the same format as the `Display` implementation, so `innermost`, `outermost` and
+/// `parallel-outermost`.
+impl FromStr for ReductionStrategy {
+    type Err = ParseStrategyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "innermost" | "leftmost-innermost" => Ok(ReductionStrategy::LeftmostInnermost),
+            "outermost" | "leftmost-outermost" => Ok(ReductionStrategy::LeftmostOutermost),
+            "parallel-outermost" => Ok(ReductionStrategy::ParallelOutermost),
+            other => Err(ParseStrategyError::UnknownReduction(other.to_string())),
+        }
+    }
+}
+
+/// The result of [`Runtime::reduce`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reduction {
+    /// Where the reduction ended, which is a normal form unless some limit stopped it.
+    pub expression: Expression,
+
+    /// The number of rewriting steps that were taken.
+    pub steps: usize,
+
+    /// [`Completion::Exhaustive`] once no definition applies anymore, or which limit stopped the
+    /// reduction before that.
+    pub completion: Completion,
+}
+
+impl Reduction {
+    pub fn is_normal_form(&self) -> bool {
+        self.completion == Completion::Exhaustive
+    }
+}
+
+/// A window that some definition lowers, and what it lowers it to.
+struct Redex {
+    start: usize,
+    size: usize,
+    replacement: Vec<Symbol>,
+}
+
+impl Redex {
+    fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    /// Whether `other` is a different window inside of this one.
+    fn contains(&self, other: &Redex) -> bool {
+        self.start <= other.start
+            && other.end() <= self.end()
+            && (self.start, self.size) != (other.start, other.size)
+    }
+}
+
+/// Every window of `expression` that some definition lowers, using the first definition that
+/// does.
+fn redexes(rules: &RuleSet, expression: &CompactExpression, depth: usize) -> Vec<Redex> {
+    let mut redexes: Vec<Redex> = Vec::new();
+
+    for (neighbour, step) in rules.nested_lower_neighbours(expression, depth) {
+        let (start, size) = (step.window_start, step.window_size);
+
+        // Definitions are tried in order on each window
+        if redexes
+            .iter()
+            .any(|redex| (redex.start, redex.size) == (start, size))
+        {
+            continue;
+        }
+
+        let replacement_size = neighbour.len() + size - expression.len();
+        let replacement = neighbour.symbols()[start..start + replacement_size].to_vec();
+
+        redexes.push(Redex {
+            start,
+            size,
+            replacement,
+        });
+    }
+
+    redexes
+}
+
+impl ReductionStrategy {
+    /// The redexes to rewrite in a single step, which don't overlap, from left to right.
+    fn pick(self, redexes: Vec<Redex>) -> Vec<Redex> {
+        let candidates: Vec<bool> = redexes
+            .iter()
+            .map(|redex| match self {
+                ReductionStrategy::LeftmostInnermost => {
+                    !redexes.iter().any(|other| redex.contains(other))
+                }
+                _ => !redexes.iter().any(|other| other.contains(redex)),
+            })
+            .collect();
+
+        // No two candidates start at the same place, since one would contain the other
+        let mut candidates: Vec<_> = redexes
+            .into_iter()
+            .zip(candidates)
+            .filter_map(|(redex, is_candidate)| is_candidate.then_some(redex))
+            .collect();
+        candidates.sort_by_key(|redex| redex.start);
+
+        match self {
+            ReductionStrategy::LeftmostInnermost | ReductionStrategy::LeftmostOutermost => {
+                candidates.truncate(1);
+                candidates
+            }
+            ReductionStrategy::ParallelOutermost => {
+                let mut picked: Vec<Redex> = Vec::new();
+
+                for redex in candidates {
+                    if picked.last().is_none_or(|last| last.end() <= redex.start) {
+                        picked.push(redex);
+                    }
+                }
+
+                picked
+            }
+        }
+    }
+}
+
+/// Rewrites `expression` with `strategy` until no definition applies or `budget` runs out,
+/// returning where it ended, after how many steps and why. `depth` is how many guards are being
+/// evaluated around it.
+pub(crate) fn normalise(
+    rules: &RuleSet,
+    mut current: CompactExpression,
+    strategy: ReductionStrategy,
+    budget: &mut Budget,
+    depth: usize,
+) -> (CompactExpression, usize, Completion) {
+    let mut steps = 0;
+
+    let completion = loop {
+        let redexes = redexes(rules, &current, depth);
+
+        if redexes.is_empty() {
+            break Completion::Exhaustive;
+        }
+
+        if let Some(completion) = budget.exceeded(steps) {
+            break completion;
+        }
+
+        // From right to left, so that the windows to the left stay where they were
+        let mut next = current.clone();
+        for redex in strategy.pick(redexes).iter().rev() {
+            next = next.splice(redex.start, redex.size, &redex.replacement);
+        }
+
+        if !budget.admits(next.len()) {
+            break budget.finished(None);
+        }
+
+        current = next;
+        steps += 1;
+    };
+
+    (current, steps, completion)
+}
+
+impl Runtime {
+    /// Rewrites `expression` with `strategy` until no definition applies anymore, following a
+    /// single path instead of searching every evaluation like [`Runtime::evaluations`].
+    ///
+    /// `max_visited` limits the number of steps, and expressions longer than `max_length` stop
+    /// the reduction.
+    pub fn reduce(
+        &self,
+        expression: Expression,
+        strategy: ReductionStrategy,
+        limits: &EvalLimits,
+    ) -> Reduction {
+        let rules = RuleSet::compile(self, None, None);
+        let root = self.interner().compact(&expression);
+
+        let (normal, steps, completion) =
+            normalise(&rules, root, strategy, &mut Budget::new(limits), 0);
+
+        Reduction {
+            expression: self.interner().expand(&normal),
+            steps,
+            completion,
+        }
+    }
+}
@@ -0,0 +1,575 @@
+//! Definitions compiled to interned symbols, ready to be matched against compact expressions.
+//!
+//! Definitions are indexed by their leading and trailing concrete tokens, and each one knows how
+//! long a window it can match, so that most (window, definition) pairs are ruled out without
+//! running the matcher at all. Definitions can be applied backwards too, raising a window that
+//! matches their lower side.
+//!
+//! In tree mode, windows and variables that aren't whole subterms are skipped, see
+//! [`Grouping::Tree`]. Definitions lowered by a host function call it with the expanded window,
+//! and can't be raised. Guards like `where x = 0` are normalised with the same definitions, which
+//! might have guards of their own, so how deeply they nest is limited.
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::matching::{self, Bracket};
+
+use super::{
+    host::HostFunctions,
+    intern::{CompactExpression, Interner, Symbol},
+    limits::{Budget, EvalLimits, Stopwatch},
+    options::{Grouping, MatchMode},
+    profile::Counters,
+    reduction::{self, ReductionStrategy},
+    trace::Bindings,
+    trace::Direction,
+    Guard, PatternToken, Runtime, Sort, Token,
+};
+
+/// Guards nested deeper than this don't hold.
+const MAX_GUARD_DEPTH: usize = 8;
+
+/// Rewriting steps allowed to normalise a guard, after which it doesn't hold.
+const MAX_GUARD_STEPS: usize = 1000;
+
+/// A [`Definition`](super::Definition) where every concrete token has been interned.
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledDefinition {
+    high: Vec<PatternToken<Symbol>>,
+    low: Vec<PatternToken<Symbol>>,
+
+    /// Whether no other definition is tried on a window after this one lowered it.
+    exclusive: bool,
+
+    grouping: Grouping,
+
+    /// The host function that builds the low side, if any.
+    host: Option<String>,
+
+    guard: Option<Guard<Symbol>>,
+}
+
+impl CompiledDefinition {
+    /// The pattern to match and the one to build, when applying the definition in `direction`.
+    fn sides(&self, direction: Direction) -> (&[PatternToken<Symbol>], &[PatternToken<Symbol>]) {
+        match direction {
+            Direction::Lower => (&self.high, &self.low),
+            Direction::Raise => (&self.low, &self.high),
+        }
+    }
+}
+
+/// What any window matched by a pattern has to look like.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Shape {
+    first: Option<Symbol>,
+    last: Option<Symbol>,
+    min_length: usize,
+
+    /// `None` if the pattern has spread variables, which can match any number of tokens.
+    max_length: Option<usize>,
+}
+
+impl Shape {
+    fn of(pattern: &[PatternToken<Symbol>]) -> Self {
+        let concrete = |token: Option<&PatternToken<Symbol>>| match token {
+            Some(PatternToken::Concrete(symbol)) => Some(*symbol),
+            _ => None,
+        };
+
+        let has_spread = pattern.iter().any(PatternToken::is_spread);
+        let optional = pattern
+            .iter()
+            .filter(|token| matches!(token, PatternToken::OptionalSpreadVariable(_)))
+            .count();
+
+        Self {
+            first: concrete(pattern.first()),
+            last: concrete(pattern.last()),
+            // Spread variables match at least one token, unless they are optional
+            min_length: pattern.len() - optional,
+            max_length: (!has_spread).then_some(pattern.len()),
+        }
+    }
+
+    fn admits_length(&self, length: usize) -> bool {
+        length >= self.min_length && self.max_length.is_none_or(|max| length <= max)
+    }
+
+    fn admits(&self, window: &[Symbol]) -> bool {
+        self.admits_length(window.len())
+            && self
+                .first
+                .is_none_or(|first| window.first() == Some(&first))
+            && self.last.is_none_or(|last| window.last() == Some(&last))
+    }
+}
+
+fn compile_guard(guard: &Guard, interner: &Interner) -> Guard<Symbol> {
+    match guard {
+        Guard::Normalises(condition) => Guard::Normalises(compile_pattern(condition, interner)),
+        Guard::Differ(a, b) => {
+            Guard::Differ(compile_pattern(a, interner), compile_pattern(b, interner))
+        }
+    }
+}
+
+fn compile_pattern(pattern: &[PatternToken], interner: &Interner) -> Vec<PatternToken<Symbol>> {
+    pattern
+        .iter()
+        .map(|token| match token {
+            PatternToken::Concrete(token) => PatternToken::Concrete(interner.intern(token)),
+            PatternToken::Variable(name) => PatternToken::Variable(name.clone()),
+            PatternToken::SpreadVariable(name) => PatternToken::SpreadVariable(name.clone()),
+            PatternToken::OptionalSpreadVariable(name) => {
+                PatternToken::OptionalSpreadVariable(name.clone())
+            }
+            PatternToken::ItemVariable(name) => PatternToken::ItemVariable(name.clone()),
+            PatternToken::Sorted(name, sort) => {
+                let sort = match sort {
+                    Sort::Element => Sort::Element,
+                    Sort::Literal => Sort::Literal,
+                    Sort::Set(set) => Sort::Set(interner.intern(set)),
+                };
+
+                PatternToken::Sorted(name.clone(), sort)
+            }
+            PatternToken::Wildcard => PatternToken::Wildcard,
+        })
+        .collect()
+}
+
+/// Where a definition was applied, without the bindings (which are only rebuilt when tracing).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Step {
+    /// Index of the definition in [`Runtime::definitions`].
+    pub definition: usize,
+    pub direction: Direction,
+    pub window_start: usize,
+    pub window_size: usize,
+
+    /// How the window was grouped, to bind the same variables when rebuilding the bindings.
+    pub grouping: Grouping,
+}
+
+/// Indices of definitions, grouped by the concrete token their pattern starts or ends with.
+///
+/// Each definition is in exactly one group: by its first token if it is concrete, else by its last
+/// token if that one is concrete, else with the unanchored ones.
+#[derive(Debug, Clone, Default)]
+struct RuleIndex {
+    by_first: HashMap<Symbol, Vec<usize>>,
+    by_last: HashMap<Symbol, Vec<usize>>,
+    unanchored: Vec<usize>,
+}
+
+impl RuleIndex {
+    fn new(shapes: &[Shape]) -> Self {
+        let mut index = Self::default();
+
+        for (i, shape) in shapes.iter().enumerate() {
+            match (shape.first, shape.last) {
+                (Some(first), _) => index.by_first.entry(first).or_default().push(i),
+                (None, Some(last)) => index.by_last.entry(last).or_default().push(i),
+                (None, None) => index.unanchored.push(i),
+            }
+        }
+
+        index
+    }
+
+    /// Indices of the definitions that might match a window, in the order they were defined.
+    fn candidates(&self, window: &[Symbol], candidates: &mut Vec<usize>) {
+        candidates.clear();
+
+        let (Some(first), Some(last)) = (window.first(), window.last()) else {
+            return;
+        };
+
+        candidates.extend(self.by_first.get(first).into_iter().flatten());
+        candidates.extend(self.by_last.get(last).into_iter().flatten());
+        candidates.extend(&self.unanchored);
+
+        candidates.sort_unstable();
+    }
+}
+
+/// Everything needed to quickly find the definitions that might match a window, on one of their
+/// sides.
+#[derive(Debug, Clone)]
+struct SideIndex {
+    shapes: Vec<Shape>,
+    index: RuleIndex,
+
+    /// Window sizes outside of this range can't be matched by any definition.
+    min_window: usize,
+    max_window: Option<usize>,
+}
+
+impl SideIndex {
+    fn new(patterns: impl Iterator<Item = impl AsRef<[PatternToken<Symbol>]>>) -> Self {
+        let shapes: Vec<_> = patterns
+            .map(|pattern| Shape::of(pattern.as_ref()))
+            .collect();
+
+        Self {
+            index: RuleIndex::new(&shapes),
+            min_window: shapes
+                .iter()
+                .map(|shape| shape.min_length)
+                .min()
+                .unwrap_or(0)
+                .max(1),
+            // Unbounded as soon as one definition is
+            max_window: shapes
+                .iter()
+                .map(|shape| shape.max_length)
+                .try_fold(0, |max, length| length.map(|length| length.max(max))),
+            shapes,
+        }
+    }
+}
+
+/// Every definition of a runtime, compiled.
+#[derive(Debug, Clone)]
+pub(crate) struct RuleSet {
+    definitions: Vec<CompiledDefinition>,
+    high: SideIndex,
+    low: SideIndex,
+
+    /// The symbols of every bracket, for definitions in tree mode.
+    brackets: HashMap<Symbol, Bracket>,
+
+    host_functions: HostFunctions,
+
+    /// To give host functions tokens, and read back what they return.
+    interner: Arc<Interner>,
+
+    /// The element `true`, which guards have to be rewritten to.
+    truth: Symbol,
+
+    /// The literal `in`, to check whether a token is in a set.
+    membership: Symbol,
+}
+
+impl RuleSet {
+    /// Compiles the definitions of every structure, applied according to `match_mode` and
+    /// `grouping` if set and to the ones of their structure otherwise.
+    pub fn compile(
+        runtime: &Runtime,
+        match_mode: Option<MatchMode>,
+        grouping: Option<Grouping>,
+    ) -> Self {
+        let interner = runtime.interner();
+
+        let definitions = runtime
+            .structures
+            .values()
+            .flat_map(|structure| {
+                let exclusive =
+                    match_mode.unwrap_or(structure.get_match_mode()) == MatchMode::First;
+                let grouping = grouping.unwrap_or(structure.get_grouping());
+
+                structure
+                    .definitions
+                    .iter()
+                    .map(move |definition| CompiledDefinition {
+                        high: compile_pattern(&definition.high, interner),
+                        low: compile_pattern(&definition.low, interner),
+                        exclusive,
+                        grouping,
+                        host: definition.host.clone(),
+                        guard: definition
+                            .guard
+                            .as_ref()
+                            .map(|guard| compile_guard(guard, interner)),
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            high: SideIndex::new(definitions.iter().map(|definition| &definition.high)),
+            low: SideIndex::new(definitions.iter().map(|definition| &definition.low)),
+            definitions,
+            brackets: Bracket::tokens()
+                .map(|(token, bracket)| (interner.intern(&token), bracket))
+                .collect(),
+            host_functions: runtime.host_function_table(),
+            interner: interner.clone(),
+            truth: interner.intern(&Token::Element("true".to_string())),
+            membership: interner.intern(&Token::Literal("in".to_string())),
+        }
+    }
+
+    /// The number of definitions.
+    pub fn len(&self) -> usize {
+        self.definitions.len()
+    }
+
+    /// Whether a window is a run of whole subterms.
+    fn is_grouped(&self, window: &[Symbol]) -> bool {
+        matching::is_grouped(window, |symbol| self.brackets.get(symbol).copied())
+    }
+
+    /// Applies a definition to a window as its grouping allows, if its guard holds when lowering.
+    /// `depth` is how many guards are being evaluated already.
+    fn transform(
+        &self,
+        definition: &CompiledDefinition,
+        direction: Direction,
+        window: &[Symbol],
+        depth: usize,
+    ) -> Option<Vec<Symbol>> {
+        let (from, to) = definition.sides(direction);
+
+        let accepts = |run: &[Symbol]| match definition.grouping {
+            Grouping::Flat => true,
+            Grouping::Tree => self.is_grouped(run),
+        };
+
+        if !accepts(window) {
+            return None;
+        }
+
+        let has_sort = |symbol: &Symbol, sort: &Sort<Symbol>| match sort {
+            Sort::Element => !self.interner.is_literal(*symbol),
+            Sort::Literal => self.interner.is_literal(*symbol),
+            Sort::Set(set) => {
+                depth < MAX_GUARD_DEPTH
+                    && self.normalises_to_truth(vec![*symbol, self.membership, *set], depth)
+            }
+        };
+
+        let bracket = |symbol: &Symbol| self.brackets.get(symbol).copied();
+        let bindings =
+            matching::get_match_bindings_where(from, window, &accepts, &has_sort, &bracket)?;
+
+        // Raising is checked by lowering the result again, which evaluates the guard then
+        if let (Some(guard), Direction::Lower) = (&definition.guard, direction) {
+            if !self.holds(guard, &bindings, depth) {
+                return None;
+            }
+        }
+
+        match (&definition.host, direction) {
+            (None, _) => matching::build(to, bindings).map(|(result, _)| result),
+            (Some(name), Direction::Lower) => self.call_host(name, window, bindings),
+            (Some(_), Direction::Raise) => None,
+        }
+    }
+
+    /// Whether a guard holds with the bindings of a window.
+    fn holds(
+        &self,
+        guard: &Guard<Symbol>,
+        bindings: &matching::Bindings<Symbol>,
+        depth: usize,
+    ) -> bool {
+        let build = |pattern| matching::build(pattern, bindings.clone()).map(|(result, _)| result);
+
+        match guard {
+            Guard::Differ(a, b) => {
+                matches!((build(a), build(b)), (Some(a), Some(b)) if a != b)
+            }
+            Guard::Normalises(condition) => match build(condition) {
+                Some(condition) if depth < MAX_GUARD_DEPTH => {
+                    self.normalises_to_truth(condition, depth)
+                }
+                _ => false,
+            },
+        }
+    }
+
+    /// Whether `expression` is rewritten to `true`, for guards and sets.
+    fn normalises_to_truth(&self, expression: Vec<Symbol>, depth: usize) -> bool {
+        let limits = EvalLimits {
+            max_visited: Some(MAX_GUARD_STEPS),
+            ..EvalLimits::none()
+        };
+
+        let (normal, _, _) = reduction::normalise(
+            self,
+            CompactExpression::new(expression),
+            ReductionStrategy::LeftmostOutermost,
+            &mut Budget::new(&limits),
+            depth + 1,
+        );
+
+        normal.symbols() == [self.truth]
+    }
+
+    /// Lowers a window with a host function, if it is registered.
+    fn call_host(
+        &self,
+        name: &str,
+        window: &[Symbol],
+        (single, spread): matching::Bindings<Symbol>,
+    ) -> Option<Vec<Symbol>> {
+        let function = self.host_functions.get(name)?;
+        let interner = &self.interner;
+
+        let bindings = Bindings {
+            single: single
+                .into_iter()
+                .map(|(name, symbol)| (name.clone(), interner.token(*symbol)))
+                .collect(),
+            spread: spread
+                .into_iter()
+                .map(|(name, symbols)| (name.clone(), interner.tokens(symbols)))
+                .collect(),
+        };
+
+        let lowered = function(&interner.tokens(window), &bindings)?;
+
+        Some(
+            lowered
+                .tokens
+                .iter()
+                .map(|token| interner.intern(token))
+                .collect(),
+        )
+    }
+
+    /// Every expression that can be reached by lowering one window of `expression`, together
+    /// with where the lowering happened.
+    ///
+    /// Definitions are tried in order on each window, until an exclusive one lowers it.
+    pub fn lower_neighbours(
+        &self,
+        expression: &CompactExpression,
+    ) -> Vec<(CompactExpression, Step)> {
+        self.neighbours(expression, Direction::Lower, None, 0)
+    }
+
+    /// Like [`RuleSet::lower_neighbours`], while evaluating `depth` nested guards.
+    pub fn nested_lower_neighbours(
+        &self,
+        expression: &CompactExpression,
+        depth: usize,
+    ) -> Vec<(CompactExpression, Step)> {
+        self.neighbours(expression, Direction::Lower, None, depth)
+    }
+
+    /// Like [`RuleSet::lower_neighbours`], but counting how often each definition is tried and
+    /// applied, and how long it takes.
+    pub fn counted_lower_neighbours(
+        &self,
+        expression: &CompactExpression,
+        counters: &mut Counters,
+    ) -> Vec<(CompactExpression, Step)> {
+        let stopwatch = Stopwatch::start();
+        let neighbours = self.neighbours(expression, Direction::Lower, Some(counters), 0);
+        counters.matching += stopwatch.elapsed();
+        neighbours
+    }
+
+    /// Every expression that lowers to `expression` in one step, together with where the raising
+    /// happened.
+    ///
+    /// A window is only raised with a definition if lowering the result would use that same
+    /// definition, so that exclusive definitions keep shadowing the ones after them.
+    pub fn raise_neighbours(
+        &self,
+        expression: &CompactExpression,
+    ) -> Vec<(CompactExpression, Step)> {
+        self.neighbours(expression, Direction::Raise, None, 0)
+    }
+
+    fn neighbours(
+        &self,
+        expression: &CompactExpression,
+        direction: Direction,
+        mut counters: Option<&mut Counters>,
+        depth: usize,
+    ) -> Vec<(CompactExpression, Step)> {
+        let side = match direction {
+            Direction::Lower => &self.high,
+            Direction::Raise => &self.low,
+        };
+
+        let mut neighbours = Vec::new();
+        let mut candidates = Vec::new();
+        let symbols = expression.symbols();
+
+        let max_window = side
+            .max_window
+            .map_or(symbols.len(), |max| max.min(symbols.len()));
+
+        for size in side.min_window..=max_window {
+            for (window_start, window) in symbols.windows(size).enumerate() {
+                side.index.candidates(window, &mut candidates);
+
+                for &index in &candidates {
+                    if !side.shapes[index].admits(window) {
+                        continue;
+                    }
+
+                    let definition = &self.definitions[index];
+
+                    if let Some(counters) = counters.as_deref_mut() {
+                        counters.attempts[index] += 1;
+                    }
+
+                    let Some(result) = self.transform(definition, direction, window, depth) else {
+                        continue;
+                    };
+
+                    if direction == Direction::Raise && !self.lowers_with(index, &result, window) {
+                        continue;
+                    }
+
+                    if let Some(counters) = counters.as_deref_mut() {
+                        counters.successes[index] += 1;
+                    }
+
+                    let step = Step {
+                        definition: index,
+                        direction,
+                        window_start,
+                        window_size: size,
+                        grouping: definition.grouping,
+                    };
+
+                    neighbours.push((expression.splice(window_start, size, &result), step));
+
+                    if direction == Direction::Lower && definition.exclusive {
+                        break;
+                    }
+                }
+            }
+        }
+
+        neighbours
+    }
+
+    /// Whether lowering `window` applies the definition at `index`, giving `expected`.
+    fn lowers_with(&self, index: usize, window: &[Symbol], expected: &[Symbol]) -> bool {
+        let mut candidates = Vec::new();
+        self.high.index.candidates(window, &mut candidates);
+
+        for &candidate in candidates
+            .iter()
+            .take_while(|&&candidate| candidate <= index)
+        {
+            if !self.high.shapes[candidate].admits(window) {
+                continue;
+            }
+
+            let definition = &self.definitions[candidate];
+
+            let Some(lowered) = self.transform(definition, Direction::Lower, window, 0) else {
+                continue;
+            };
+
+            if candidate == index {
+                return lowered == expected;
+            }
+
+            if definition.exclusive {
+                return false;
+            }
+        }
+
+        false
+    }
+}
@@ -0,0 +1,273 @@
+//! Strategies for the order in which the rewrite search visits expressions.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, VecDeque},
+    fmt::Display,
+    str::FromStr,
+    sync::Arc,
+};
+
+use thiserror::Error;
+
+use super::{
+    cost::Cost,
+    intern::{CompactExpression, Interner},
+    limits::Limit,
+};
+
+/// The order in which [`Runtime::evaluations`](super::Runtime::evaluations) explores expressions.
+///
+/// Every strategy except [`SearchStrategy::Beam`] eventually visits every reachable expression,
+/// they only differ on how quickly they get to small ones and how much memory they need.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchStrategy {
+    /// Always expand the most recently found expression.
+    #[default]
+    DepthFirst,
+
+    /// Expand expressions in the order they were found, closest to the original first.
+    BreadthFirst,
+
+    /// Always expand the cheapest expression found so far.
+    BestFirst,
+
+    /// Depth first search up to a maximum number of rewrites, increasing the maximum until
+    /// nothing new can be found.
+    IterativeDeepening,
+
+    /// Breadth first search that only keeps the `width` cheapest expressions of each level.
+    ///
+    /// This is not complete, so the search might miss the actual cheapest expression.
+    Beam { width: usize },
+}
+
+impl Display for SearchStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchStrategy::DepthFirst => write!(f, "dfs"),
+            SearchStrategy::BreadthFirst => write!(f, "bfs"),
+            SearchStrategy::BestFirst => write!(f, "best-first"),
+            SearchStrategy::IterativeDeepening => write!(f, "iterative-deepening"),
+            SearchStrategy::Beam { width } => write!(f, "beam:{width}"),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseStrategyError {
+    #[error("Unknown search strategy \"{0}\" (expected dfs, bfs, best-first, iterative-deepening or beam:<width>)")]
+    Unknown(String),
+
+    #[error("Invalid beam width \"{0}\" (expected a positive integer)")]
+    InvalidBeamWidth(String),
+
+    #[error(
+        "Unknown reduction strategy \"{0}\" (expected innermost, outermost or parallel-outermost)"
+    )]
+    UnknownReduction(String),
+}
+
+/// Parses the same format as the `Display` implementation, so `dfs`, `bfs`, `best-first`,
+/// `iterative-deepening` and `beam:<width>`.
+impl FromStr for SearchStrategy {
+    type Err = ParseStrategyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some(width) = s.strip_prefix("beam:") {
+            return match width.trim().parse() {
+                Ok(width) if width > 0 => Ok(SearchStrategy::Beam { width }),
+                _ => Err(ParseStrategyError::InvalidBeamWidth(width.to_string())),
+            };
+        }
+
+        match s {
+            "dfs" | "depth-first" => Ok(SearchStrategy::DepthFirst),
+            "bfs" | "breadth-first" => Ok(SearchStrategy::BreadthFirst),
+            "best-first" => Ok(SearchStrategy::BestFirst),
+            "iterative-deepening" | "iddfs" => Ok(SearchStrategy::IterativeDeepening),
+            other => Err(ParseStrategyError::Unknown(other.to_string())),
+        }
+    }
+}
+
+/// An expression waiting in a best-first frontier. Expressions that cost the same are ordered like
+/// the tokens they stand for, so that they are popped in the same order no matter when their
+/// tokens were interned.
+pub(crate) struct Ranked {
+    cost: u64,
+    expression: CompactExpression,
+    interner: Arc<Interner>,
+}
+
+impl Ranked {
+    fn new(cost: &Cost, expression: CompactExpression) -> Self {
+        Self {
+            cost: cost.of(&expression),
+            expression,
+            interner: cost.interner().clone(),
+        }
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cost
+            .cmp(&other.cost)
+            .then_with(|| self.interner.compare(&self.expression, &other.expression))
+    }
+}
+
+/// Expressions that have been found but not expanded yet.
+///
+/// Iterative deepening is not a frontier on its own (it is a series of depth first searches), so
+/// it is handled separately.
+pub(crate) enum Frontier {
+    Stack(Vec<CompactExpression>),
+    Queue(VecDeque<CompactExpression>),
+    Heap {
+        heap: BinaryHeap<Reverse<Ranked>>,
+        cost: Cost,
+    },
+    Beam {
+        width: usize,
+        current: VecDeque<CompactExpression>,
+        next: Vec<CompactExpression>,
+        pruned: bool,
+        cost: Cost,
+    },
+}
+
+impl Frontier {
+    pub fn new(strategy: SearchStrategy, cost: Cost) -> Self {
+        match strategy {
+            SearchStrategy::DepthFirst | SearchStrategy::IterativeDeepening => {
+                Frontier::Stack(Vec::new())
+            }
+            SearchStrategy::BreadthFirst => Frontier::Queue(VecDeque::new()),
+            SearchStrategy::BestFirst => Frontier::Heap {
+                heap: BinaryHeap::new(),
+                cost,
+            },
+            SearchStrategy::Beam { width } => Frontier::Beam {
+                width,
+                current: VecDeque::new(),
+                next: Vec::new(),
+                pruned: false,
+                cost,
+            },
+        }
+    }
+
+    pub fn push(&mut self, expression: CompactExpression) {
+        match self {
+            Frontier::Stack(stack) => stack.push(expression),
+            Frontier::Queue(queue) => queue.push_back(expression),
+            Frontier::Heap { heap, cost } => heap.push(Reverse(Ranked::new(cost, expression))),
+            Frontier::Beam { next, .. } => next.push(expression),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<CompactExpression> {
+        match self {
+            Frontier::Stack(stack) => stack.pop(),
+            Frontier::Queue(queue) => queue.pop_front(),
+            Frontier::Heap { heap, .. } => heap.pop().map(|Reverse(ranked)| ranked.expression),
+            Frontier::Beam {
+                width,
+                current,
+                next,
+                pruned,
+                cost,
+            } => {
+                if current.is_empty() {
+                    // Go down one level, keeping only the cheapest expressions. The sort is
+                    // stable, so expressions that cost the same stay in order.
+                    next.sort_by(|a, b| cost.tie_break(a, b));
+                    next.dedup();
+                    next.sort_by_cached_key(|expression| cost.of(expression));
+
+                    if next.len() > *width {
+                        *pruned = true;
+                        next.truncate(*width);
+                    }
+
+                    current.extend(next.drain(..));
+                }
+
+                current.pop_front()
+            }
+        }
+    }
+
+    /// Undoes a [`Frontier::pop`], so that the expression is the next one to be popped.
+    pub fn put_back(&mut self, expression: CompactExpression) {
+        match self {
+            Frontier::Stack(stack) => stack.push(expression),
+            Frontier::Queue(queue) => queue.push_front(expression),
+            Frontier::Heap { heap, cost } => heap.push(Reverse(Ranked::new(cost, expression))),
+            Frontier::Beam { current, .. } => current.push_front(expression),
+        }
+    }
+
+    /// The number of expressions waiting to be popped.
+    pub fn len(&self) -> usize {
+        match self {
+            Frontier::Stack(stack) => stack.len(),
+            Frontier::Queue(queue) => queue.len(),
+            Frontier::Heap { heap, .. } => heap.len(),
+            Frontier::Beam { current, next, .. } => current.len() + next.len(),
+        }
+    }
+
+    /// Pops up to `max` expressions at once.
+    ///
+    /// A beam search never mixes levels in a single batch, since the next level can only be
+    /// pruned once the current one has been completely expanded.
+    pub fn pop_batch(&mut self, max: usize) -> Vec<CompactExpression> {
+        let mut batch = Vec::new();
+
+        while batch.len() < max {
+            if let Frontier::Beam { current, .. } = self {
+                if current.is_empty() && !batch.is_empty() {
+                    break;
+                }
+            }
+
+            match self.pop() {
+                Some(expression) => batch.push(expression),
+                None => break,
+            }
+        }
+
+        batch
+    }
+
+    /// The limit that made the search incomplete, if some expressions were thrown away.
+    pub fn pruned(&self) -> Option<Limit> {
+        match self {
+            Frontier::Beam {
+                width,
+                pruned: true,
+                ..
+            } => Some(Limit::BeamWidth(*width)),
+            _ => None,
+        }
+    }
+}
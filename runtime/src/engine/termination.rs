@@ -0,0 +1,513 @@
+//! Static analysis of whether rewriting with a set of definitions can go on forever.
+//!
+//! Three independent checks are done:
+//! - Definitions whose right side can be longer than their left side. Without any, every
+//!   evaluation only visits a finite number of expressions.
+//! - Rewrite cycles, found by rewriting the left side of each definition (with its variables
+//!   standing for themselves) until it comes back.
+//! - A termination proof, either because every definition makes expressions shorter, or with the
+//!   lexicographic path ordering (LPO), treating each token as a unary function symbol, reading
+//!   expressions left to right or right to left. If one is found, every sequence of rewrites ends.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use super::{
+    limits::EvalLimits, options::EvalOptions, strategy::SearchStrategy, trace::Derivation,
+    trace::DerivationStep, Definition, Expression, PatternToken, Runtime, Structure, Token,
+};
+
+/// How many expressions are visited from each definition while looking for cycles.
+const CYCLE_SEARCH_VISITS: usize = 500;
+
+/// How much longer than the left side of a definition expressions in a cycle can get.
+const CYCLE_SEARCH_GROWTH: usize = 4;
+
+/// How much longer the right side of a definition can be than its left side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Growth {
+    /// At most this many tokens longer.
+    Bounded(usize),
+
+    /// Arbitrarily longer, since this spread variable appears more often on the right side.
+    Unbounded { variable: String },
+
+    /// Unknown, since this host function builds the right side.
+    Host { function: String },
+}
+
+/// A definition that can make expressions longer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrowingDefinition {
+    pub definition: Definition,
+    pub growth: Growth,
+}
+
+/// A strict order between tokens, used by the lexicographic path ordering.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Precedence {
+    /// Each token and the tokens directly below it.
+    below: BTreeMap<Token, BTreeSet<Token>>,
+}
+
+impl Precedence {
+    pub fn is_greater(&self, a: &Token, b: &Token) -> bool {
+        let mut stack = vec![a];
+        let mut seen = BTreeSet::new();
+
+        while let Some(token) = stack.pop() {
+            for smaller in self.below.get(token).into_iter().flatten() {
+                if smaller == b {
+                    return true;
+                }
+
+                if seen.insert(smaller) {
+                    stack.push(smaller);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// A precedence where each token is greater than the ones after it.
+    pub fn from_ranking(tokens: impl IntoIterator<Item = Token>) -> Self {
+        let tokens: Vec<_> = tokens.into_iter().collect();
+        let mut precedence = Self::default();
+
+        for pair in tokens.windows(2) {
+            if let Some(extended) = precedence.with(&pair[0], &pair[1]) {
+                precedence = extended;
+            }
+        }
+
+        precedence
+    }
+
+    /// Adds `a > b`, unless it contradicts the order so far.
+    fn with(&self, a: &Token, b: &Token) -> Option<Self> {
+        if a == b || self.is_greater(b, a) {
+            return None;
+        }
+
+        let mut precedence = self.clone();
+        precedence
+            .below
+            .entry(a.clone())
+            .or_default()
+            .insert(b.clone());
+
+        Some(precedence)
+    }
+
+    /// Every `a > b` that was needed, in no particular order.
+    pub fn pairs(&self) -> impl Iterator<Item = (&Token, &Token)> + '_ {
+        self.below
+            .iter()
+            .flat_map(|(a, below)| below.iter().map(move |b| (a, b)))
+    }
+}
+
+impl Display for Precedence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pairs: Vec<_> = self.pairs().map(|(a, b)| format!("{a} > {b}")).collect();
+        write!(f, "{}", pairs.join(", "))
+    }
+}
+
+/// Outcome of trying to prove termination, by the length of expressions and then with the
+/// lexicographic path ordering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LpoProof {
+    /// Every definition makes expressions shorter, however its variables are bound.
+    Shorter,
+
+    /// Every definition makes expressions smaller with this precedence, when expressions are read
+    /// in the given direction.
+    Proven {
+        precedence: Precedence,
+        right_to_left: bool,
+    },
+
+    /// No precedence was found that makes this definition, and the ones before it, decrease.
+    Failed(Definition),
+}
+
+/// Everything found out by [`Runtime::check_termination`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminationReport {
+    pub growing: Vec<GrowingDefinition>,
+
+    /// Derivations from the left side of a definition back to itself.
+    pub cycles: Vec<Derivation>,
+
+    pub lpo: LpoProof,
+}
+
+impl TerminationReport {
+    /// Whether every sequence of rewrites is proven to end.
+    pub fn is_terminating(&self) -> bool {
+        matches!(self.lpo, LpoProof::Shorter | LpoProof::Proven { .. })
+    }
+
+    /// Whether every evaluation is sure to visit finitely many expressions, which is the case
+    /// when no definition makes expressions longer.
+    pub fn is_search_finite(&self) -> bool {
+        self.growing.is_empty()
+    }
+}
+
+impl Display for TerminationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.lpo {
+            LpoProof::Shorter => {
+                writeln!(f, "Terminates: every definition makes expressions shorter.")?
+            }
+            LpoProof::Proven { precedence, .. } if precedence.pairs().next().is_none() => {
+                writeln!(f, "Terminates: every definition makes expressions smaller.")?
+            }
+            LpoProof::Proven {
+                precedence,
+                right_to_left,
+            } => writeln!(
+                f,
+                "Terminates: every definition makes expressions smaller with the precedence {precedence}, reading expressions {}.",
+                match right_to_left {
+                    true => "right to left",
+                    false => "left to right",
+                }
+            )?,
+            LpoProof::Failed(definition) => writeln!(
+                f,
+                "Could not prove termination: no precedence makes `{definition}` decrease along with the definitions before it."
+            )?,
+        }
+
+        for GrowingDefinition { definition, growth } in &self.growing {
+            match growth {
+                Growth::Bounded(1) => writeln!(
+                    f,
+                    "- `{definition}` can make expressions one token longer."
+                )?,
+                Growth::Bounded(tokens) => writeln!(
+                    f,
+                    "- `{definition}` can make expressions up to {tokens} tokens longer."
+                )?,
+                Growth::Unbounded { variable } => writeln!(
+                    f,
+                    "- `{definition}` can make expressions arbitrarily longer, since `{variable}` appears more often on its right side."
+                )?,
+                Growth::Host { function } => writeln!(
+                    f,
+                    "- `{definition}` might make expressions longer, since the host function `{function}` builds its right side."
+                )?,
+            }
+        }
+
+        if !self.growing.is_empty() {
+            writeln!(
+                f,
+                "  Evaluations might never end without limits on the search."
+            )?;
+        }
+
+        for cycle in &self.cycles {
+            writeln!(f, "- Rewrite cycle:")?;
+            write!(f, "{cycle}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Runtime {
+    /// Analyses whether rewriting with the definitions of every structure can go on forever.
+    pub fn check_termination(&self) -> TerminationReport {
+        let definitions: Vec<_> = self.definitions().collect();
+
+        TerminationReport {
+            growing: definitions
+                .iter()
+                .filter_map(|definition| {
+                    growth(definition).map(|growth| GrowingDefinition {
+                        definition: (*definition).clone(),
+                        growth,
+                    })
+                })
+                .collect(),
+            cycles: self.find_cycles(),
+            lpo: match definitions.iter().all(|definition| shrinks(definition)) {
+                true => LpoProof::Shorter,
+                false => lpo_proof(&definitions),
+            },
+        }
+    }
+
+    /// Looks for a way to rewrite the left side of each definition back into itself.
+    fn find_cycles(&self) -> Vec<Derivation> {
+        let mut cycles: Vec<Derivation> = Vec::new();
+
+        for (index, definition) in self.definitions().enumerate() {
+            // Definitions that are already part of a cycle would just find it again
+            let in_cycle = cycles
+                .iter()
+                .flat_map(|cycle| &cycle.steps)
+                .any(|step| step.rewrite.definition == index);
+
+            if in_cycle {
+                continue;
+            }
+
+            let start = instantiate(&definition.high);
+
+            let limits = EvalLimits {
+                max_visited: Some(CYCLE_SEARCH_VISITS),
+                max_length: Some(start.tokens.len() * CYCLE_SEARCH_GROWTH + CYCLE_SEARCH_GROWTH),
+                ..EvalLimits::none()
+            };
+            let options = EvalOptions {
+                trace: true,
+                ..EvalOptions::new(limits, SearchStrategy::BreadthFirst)
+            };
+
+            let outcome = self.evaluations(start.clone(), &options, &mut |_| ());
+            let Some(trace) = outcome.trace else {
+                continue;
+            };
+
+            // The shortest way back to the start
+            let cycle = trace
+                .rewrites_into(&start)
+                .filter_map(|(parent, rewrite)| {
+                    let mut derivation = self.derivation(&trace, parent)?;
+
+                    derivation.steps.push(DerivationStep {
+                        rewrite: rewrite.clone(),
+                        definition: self.definitions().nth(rewrite.definition)?.clone(),
+                        result: start.clone(),
+                    });
+
+                    Some(derivation)
+                })
+                .min_by_key(|derivation| derivation.steps.len());
+
+            cycles.extend(cycle);
+        }
+
+        cycles
+    }
+}
+
+impl Structure {
+    /// Analyses whether rewriting with the definitions of this structure alone can go on forever.
+    pub fn check_termination(&self) -> TerminationReport {
+        Runtime::new(BTreeMap::from([(String::new(), self.clone())])).check_termination()
+    }
+}
+
+/// An instance of a pattern, where each variable is replaced by a token with its name.
+pub(super) fn instantiate(pattern: &[PatternToken]) -> Expression {
+    Expression::new(
+        pattern
+            .iter()
+            .map(|token| match token {
+                PatternToken::Concrete(token) => token.clone(),
+                PatternToken::Variable(name)
+                | PatternToken::SpreadVariable(name)
+                | PatternToken::OptionalSpreadVariable(name)
+                | PatternToken::ItemVariable(name)
+                | PatternToken::Sorted(name, _) => Token::Element(name.clone()),
+                PatternToken::Wildcard => Token::Element("_".to_string()),
+            })
+            .collect(),
+    )
+}
+
+/// Both sides of a definition with the first optional spread variable binding nothing, and then
+/// with it binding some tokens, or `None` if there are no optional spread variables. An ordering
+/// holds for a definition when it holds in both cases.
+pub(super) fn optional_cases(
+    high: &[PatternToken],
+    low: &[PatternToken],
+) -> Option<[(Vec<PatternToken>, Vec<PatternToken>); 2]> {
+    let name = high.iter().chain(low).find_map(|token| match token {
+        PatternToken::OptionalSpreadVariable(name) => Some(name),
+        _ => None,
+    })?;
+
+    let is_optional = |token: &PatternToken| matches!(token, PatternToken::OptionalSpreadVariable(other) if other == name);
+    let without = |pattern: &[PatternToken]| {
+        pattern
+            .iter()
+            .filter(|token| !is_optional(token))
+            .cloned()
+            .collect()
+    };
+    let spread = |pattern: &[PatternToken]| {
+        pattern
+            .iter()
+            .map(|token| match is_optional(token) {
+                true => PatternToken::SpreadVariable(name.clone()),
+                false => token.clone(),
+            })
+            .collect()
+    };
+
+    Some([(without(high), without(low)), (spread(high), spread(low))])
+}
+
+/// How much longer a definition can make an expression, if at all.
+fn growth(definition: &Definition) -> Option<Growth> {
+    if let Some(function) = &definition.host {
+        return Some(Growth::Host {
+            function: function.clone(),
+        });
+    }
+
+    match length_difference(definition) {
+        Err(variable) => Some(Growth::Unbounded { variable }),
+        Ok(growth) => (growth > 0).then_some(Growth::Bounded(growth as usize)),
+    }
+}
+
+/// Whether every rewrite with a definition makes the expression shorter.
+fn shrinks(definition: &Definition) -> bool {
+    definition.host.is_none() && length_difference(definition).is_ok_and(|growth| growth < 0)
+}
+
+/// How many tokens longer the right side of a definition is than its left side, at most, or the
+/// first spread variable that appears more often on the right side, which makes it unbounded.
+fn length_difference(definition: &Definition) -> Result<isize, String> {
+    // Occurrences of each spread variable on the left and right side
+    let mut spreads: BTreeMap<&String, (isize, isize)> = BTreeMap::new();
+
+    for token in &definition.high {
+        if let PatternToken::SpreadVariable(name)
+        | PatternToken::OptionalSpreadVariable(name)
+        | PatternToken::ItemVariable(name) = token
+        {
+            spreads.entry(name).or_default().0 += 1;
+        }
+    }
+
+    for token in &definition.low {
+        if let PatternToken::SpreadVariable(name)
+        | PatternToken::OptionalSpreadVariable(name)
+        | PatternToken::ItemVariable(name) = token
+        {
+            spreads.entry(name).or_default().1 += 1;
+        }
+    }
+
+    if let Some((variable, _)) = spreads.iter().find(|(_, (high, low))| low > high) {
+        return Err(variable.to_string());
+    }
+
+    // Every other spread variable makes the right side shorter the longer it is, so the most
+    // growth is when they bind a single token, and then they count like any other token. Optional
+    // ones can bind nothing, so they don't count at all
+    let length = |pattern: &[PatternToken]| {
+        pattern
+            .iter()
+            .filter(|token| !matches!(token, PatternToken::OptionalSpreadVariable(_)))
+            .count() as isize
+    };
+
+    Ok(length(&definition.low) - length(&definition.high))
+}
+
+/// Looks for a precedence that orients every definition, one definition at a time, reading
+/// expressions left to right and then right to left.
+///
+/// A string rewriting system terminates if and only if its reverse does, so either proof works.
+fn lpo_proof(definitions: &[&Definition]) -> LpoProof {
+    let mut first_failure = None;
+
+    for right_to_left in [false, true] {
+        let read = |pattern: &[PatternToken]| {
+            let mut pattern = pattern.to_vec();
+            if right_to_left {
+                pattern.reverse();
+            }
+            pattern
+        };
+
+        let mut precedence = Some(Precedence::default());
+
+        for definition in definitions {
+            // Nothing is known about what host functions return
+            precedence = precedence
+                .filter(|_| definition.host.is_none())
+                .and_then(|precedence| {
+                    lpo_greater(&read(&definition.high), &read(&definition.low), &precedence)
+                });
+
+            if precedence.is_none() {
+                first_failure.get_or_insert((*definition).clone());
+                break;
+            }
+        }
+
+        if let Some(precedence) = precedence {
+            return LpoProof::Proven {
+                precedence,
+                right_to_left,
+            };
+        }
+    }
+
+    LpoProof::Failed(first_failure.expect("Only reached if both directions failed"))
+}
+
+/// Whether `high` is greater than `low` in the lexicographic path ordering, with `precedence`
+/// extended as needed.
+///
+/// A sequence of tokens `f rest` is read as the term `f(rest)`. Variables are only ever compared
+/// to themselves, so that the ordering holds for whatever they bind to.
+pub(super) fn lpo_greater(
+    high: &[PatternToken],
+    low: &[PatternToken],
+    precedence: &Precedence,
+) -> Option<Precedence> {
+    if let Some([(high, low), (spread_high, spread_low)]) = optional_cases(high, low) {
+        let precedence = lpo_greater(&high, &low, precedence)?;
+        return lpo_greater(&spread_high, &spread_low, &precedence);
+    }
+
+    let (f, high_rest) = high.split_first()?;
+
+    let Some((g, low_rest)) = low.split_first() else {
+        // Anything is greater than the empty sequence
+        return Some(precedence.clone());
+    };
+
+    // Same head, smaller argument
+    if f == g {
+        if let Some(precedence) = lpo_greater(high_rest, low_rest, precedence) {
+            return Some(precedence);
+        }
+    }
+
+    // The argument is already greater or equal
+    if high_rest == low {
+        return Some(precedence.clone());
+    }
+
+    if let Some(precedence) = lpo_greater(high_rest, low, precedence) {
+        return Some(precedence);
+    }
+
+    // Greater head, and greater than the argument
+    if let (PatternToken::Concrete(f), PatternToken::Concrete(g)) = (f, g) {
+        let precedence = match precedence.is_greater(f, g) {
+            true => precedence.clone(),
+            false => precedence.with(f, g)?,
+        };
+
+        return lpo_greater(high, low_rest, &precedence);
+    }
+
+    None
+}
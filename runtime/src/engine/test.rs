@@ -1,5 +1,45 @@
+use std::{collections::BTreeSet, ops::ControlFlow, time::Duration};
+
+use super::completion::{CompletionError, CompletionOptions, ReductionOrder};
+use super::confluence::Joinability;
+use super::cost::CostModel;
+use super::equality::Equivalence;
+use super::int::Integer;
+use super::limits::{CancellationToken, Completion, EvalLimits, Limit};
+use super::observer::{EvalEvent, WithVisited};
+use super::options::{EvalOptions, Grouping, MatchMode};
+use super::reduction::ReductionStrategy;
+use super::strategy::SearchStrategy;
+use super::termination::{Growth, LpoProof, Precedence};
+use super::trace::Direction;
 use super::*;
 
+use crate::{
+    parser::parse,
+    resolvers::{MapResolver, Resolver, StdResolver},
+};
+
+fn runtime_from(program: &str) -> Runtime {
+    let mut resolver = MapResolver::new();
+    resolver.insert("main".to_string(), program.to_string());
+    parse("main", &mut resolver).unwrap()
+}
+
+fn runtime_with_std(program: &str) -> Runtime {
+    let mut resolver = MapResolver::new();
+    resolver.insert("main".to_string(), program.to_string());
+    parse("main", &mut StdResolver::default().chain(resolver)).unwrap()
+}
+
+/// `a` can be rewritten into arbitrarily long strings of `a`s
+fn unbounded_runtime() -> Runtime {
+    runtime_from("domain { a } reserve { } use { } a => a a;")
+}
+
+fn with_limits(limits: EvalLimits) -> EvalOptions {
+    EvalOptions::new(limits, SearchStrategy::default())
+}
+
 #[test]
 fn structures_with_overlap() {
     let domain = BTreeSet::from(["a".to_string(), "b".to_string()]);
@@ -12,3 +52,1623 @@ fn structures_with_overlap() {
         })
     );
 }
+
+#[test]
+fn exhaustive_without_limits() {
+    let runtime = runtime_from("domain { a, b } reserve { } use { } b => a;");
+    let expression = runtime.parse_expression("b b").unwrap();
+
+    let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+
+    assert_eq!(outcome.completion, Completion::Exhaustive);
+    assert_eq!(outcome.evaluations.len(), 4);
+    assert_eq!(outcome.best(), &runtime.parse_expression("a a").unwrap());
+}
+
+#[test]
+fn max_visited_cuts_off() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let limits = EvalLimits {
+        max_visited: Some(10),
+        ..EvalLimits::none()
+    };
+
+    let outcome = runtime.evaluations(expression, &with_limits(limits), &mut |_| ());
+
+    assert_eq!(outcome.completion, Completion::CutOff(Limit::Visited(10)));
+    assert_eq!(outcome.evaluations.len(), 10);
+}
+
+#[test]
+fn max_length_discards_long_expressions() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let limits = EvalLimits {
+        max_length: Some(5),
+        ..EvalLimits::none()
+    };
+
+    let outcome = runtime.evaluations(expression, &with_limits(limits), &mut |_| ());
+
+    assert_eq!(outcome.completion, Completion::CutOff(Limit::Length(5)));
+    assert_eq!(outcome.evaluations.len(), 5);
+    assert!(outcome.evaluations.iter().all(|e| e.tokens.len() <= 5));
+}
+
+#[test]
+fn max_time_cuts_off() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let limits = EvalLimits {
+        max_time: Some(Duration::ZERO),
+        ..EvalLimits::none()
+    };
+
+    let outcome = runtime.evaluations(expression.clone(), &with_limits(limits), &mut |_| ());
+
+    assert_eq!(
+        outcome.completion,
+        Completion::CutOff(Limit::Time(Duration::ZERO))
+    );
+    assert_eq!(outcome.best(), &expression);
+}
+
+#[test]
+fn cancelled_search_stops() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let cancellation = CancellationToken::new();
+    let limits = EvalLimits {
+        cancellation: Some(cancellation.clone()),
+        ..EvalLimits::none()
+    };
+
+    // Cancel from within the search, after a few expansions
+    let outcome = runtime.evaluations(expression, &with_limits(limits), &mut |visited| {
+        if visited.len() == 3 {
+            cancellation.cancel();
+        }
+    });
+
+    assert_eq!(outcome.completion, Completion::Cancelled);
+    assert_eq!(outcome.evaluations.len(), 3);
+}
+
+/// Every `b` becomes a `c`, and any `c c` collapses into a single `a`
+fn branching_runtime() -> Runtime {
+    runtime_from("domain { a, b, c } reserve { } use { } b => c; c c => a;")
+}
+
+#[test]
+fn complete_strategies_agree() {
+    let runtime = branching_runtime();
+    let expression = runtime.parse_expression("b b b").unwrap();
+
+    let strategies = [
+        SearchStrategy::DepthFirst,
+        SearchStrategy::BreadthFirst,
+        SearchStrategy::BestFirst,
+        SearchStrategy::IterativeDeepening,
+    ];
+
+    let outcomes: Vec<_> = strategies
+        .into_iter()
+        .map(|strategy| {
+            let options = EvalOptions::new(EvalLimits::none(), strategy);
+            runtime.evaluations(expression.clone(), &options, &mut |_| ())
+        })
+        .collect();
+
+    for outcome in &outcomes {
+        assert_eq!(outcome.completion, Completion::Exhaustive);
+        assert_eq!(outcome.evaluations, outcomes[0].evaluations);
+    }
+
+    assert_eq!(
+        outcomes[0].best(),
+        &runtime.parse_expression("a b").unwrap()
+    );
+}
+
+#[test]
+fn best_first_visits_smallest_first() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a a a").unwrap();
+
+    let mut order = Vec::new();
+    let options = EvalOptions::new(
+        EvalLimits {
+            max_length: Some(6),
+            ..EvalLimits::none()
+        },
+        SearchStrategy::BestFirst,
+    );
+
+    runtime.evaluations(expression, &options, &mut |visited| {
+        order.push(visited.latest().tokens.len())
+    });
+
+    assert_eq!(order, vec![3, 4, 5, 6]);
+}
+
+#[test]
+fn best_first_avoids_growing_branches() {
+    // Rewriting both `y`s at once leads to an ever growing branch, which depth first search
+    // gets stuck in
+    let runtime =
+        runtime_from("domain { a, b, y } reserve { } use { } y => a; y y => b b b b; b => b b;");
+    let expression = runtime.parse_expression("y y").unwrap();
+
+    let limits = EvalLimits {
+        max_visited: Some(10),
+        ..EvalLimits::none()
+    };
+
+    let best_first = EvalOptions::new(limits.clone(), SearchStrategy::BestFirst);
+    let depth_first = EvalOptions::new(limits, SearchStrategy::DepthFirst);
+
+    let smallest = runtime.parse_expression("a a").unwrap();
+
+    assert_eq!(
+        runtime.eval(expression.clone(), &best_first, &mut |_| ()),
+        smallest
+    );
+    assert_ne!(
+        runtime.eval(expression, &depth_first, &mut |_| ()),
+        smallest
+    );
+}
+
+#[test]
+fn beam_search_reports_pruning() {
+    let runtime = branching_runtime();
+    let expression = runtime.parse_expression("b b b").unwrap();
+
+    let options = EvalOptions::new(EvalLimits::none(), SearchStrategy::Beam { width: 1 });
+    let outcome = runtime.evaluations(expression, &options, &mut |_| ());
+
+    assert_eq!(outcome.completion, Completion::CutOff(Limit::BeamWidth(1)));
+    assert!(outcome.evaluations.len() < 10);
+}
+
+#[test]
+fn strategy_round_trip() {
+    let strategies = [
+        SearchStrategy::DepthFirst,
+        SearchStrategy::BreadthFirst,
+        SearchStrategy::BestFirst,
+        SearchStrategy::IterativeDeepening,
+        SearchStrategy::Beam { width: 7 },
+    ];
+
+    for strategy in strategies {
+        assert_eq!(strategy.to_string().parse(), Ok(strategy));
+    }
+
+    assert!("beam:0".parse::<SearchStrategy>().is_err());
+    assert!("sideways".parse::<SearchStrategy>().is_err());
+}
+
+#[test]
+fn derivation_is_shortest() {
+    let runtime = branching_runtime();
+    let expression = runtime.parse_expression("b b").unwrap();
+
+    let options = EvalOptions {
+        trace: true,
+        ..EvalOptions::default()
+    };
+
+    let outcome = runtime.evaluations(expression.clone(), &options, &mut |_| ());
+    let trace = outcome.trace.as_ref().unwrap();
+
+    let target = runtime.parse_expression("a").unwrap();
+    let derivation = runtime.derivation(trace, &target).unwrap();
+
+    assert_eq!(derivation.start, expression);
+    assert_eq!(derivation.result(), &target);
+    assert_eq!(derivation.steps.len(), 3);
+
+    // The last step is `c c => a` applied to the whole expression
+    let last = derivation.steps.last().unwrap();
+    assert_eq!(last.rewrite.definition, 1);
+    assert_eq!(last.rewrite.window_start, 0);
+    assert_eq!(last.rewrite.window_size, 2);
+    assert_eq!(last.definition, *runtime.definitions().nth(1).unwrap());
+
+    let empty = runtime.derivation(trace, &expression).unwrap();
+    assert!(empty.steps.is_empty());
+
+    let unreachable = runtime.parse_expression("b b b").unwrap();
+    assert!(runtime.derivation(trace, &unreachable).is_none());
+}
+
+#[test]
+fn derivation_records_bindings() {
+    let runtime = runtime_from("domain { a, b } reserve { f } use { } f x y => y x;");
+    let expression = runtime.parse_expression("f a b").unwrap();
+
+    let options = EvalOptions {
+        trace: true,
+        ..EvalOptions::default()
+    };
+
+    let outcome = runtime.evaluations(expression, &options, &mut |_| ());
+    let target = runtime.parse_expression("b a").unwrap();
+    let derivation = runtime
+        .derivation(outcome.trace.as_ref().unwrap(), &target)
+        .unwrap();
+
+    let bindings = &derivation.steps[0].rewrite.bindings;
+    assert_eq!(bindings.single["x"], Token::Element("a".to_string()));
+    assert_eq!(bindings.single["y"], Token::Element("b".to_string()));
+}
+
+#[test]
+fn parallel_search_matches_sequential() {
+    let runtime = branching_runtime();
+    let expression = runtime.parse_expression("b b b b b").unwrap();
+
+    for strategy in [
+        SearchStrategy::DepthFirst,
+        SearchStrategy::BreadthFirst,
+        SearchStrategy::BestFirst,
+    ] {
+        let sequential = EvalOptions::new(EvalLimits::none(), strategy);
+        let parallel = EvalOptions {
+            threads: 4,
+            ..sequential.clone()
+        };
+
+        let expected = runtime.evaluations(expression.clone(), &sequential, &mut |_| ());
+        let outcome = runtime.evaluations(expression.clone(), &parallel, &mut |_| ());
+
+        assert_eq!(outcome.completion, Completion::Exhaustive);
+        assert_eq!(outcome.evaluations, expected.evaluations);
+    }
+}
+
+#[test]
+fn parallel_search_respects_limits() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let options = EvalOptions {
+        threads: 4,
+        ..with_limits(EvalLimits {
+            max_visited: Some(50),
+            ..EvalLimits::none()
+        })
+    };
+
+    let outcome = runtime.evaluations(expression, &options, &mut |_| ());
+
+    assert_eq!(outcome.completion, Completion::CutOff(Limit::Visited(50)));
+    assert_eq!(outcome.evaluations.len(), 50);
+}
+
+#[test]
+fn parallel_search_traces() {
+    let runtime = branching_runtime();
+    let expression = runtime.parse_expression("b b").unwrap();
+
+    let options = EvalOptions {
+        threads: 2,
+        trace: true,
+        ..EvalOptions::default()
+    };
+
+    let outcome = runtime.evaluations(expression, &options, &mut |_| ());
+    let target = runtime.parse_expression("a").unwrap();
+    let derivation = runtime
+        .derivation(outcome.trace.as_ref().unwrap(), &target)
+        .unwrap();
+
+    assert_eq!(derivation.steps.len(), 3);
+}
+
+#[test]
+fn compact_expressions_keep_order() {
+    let runtime = runtime_from("domain { a, b, c } reserve { } use { } b => a;");
+    let interner = runtime.interner();
+
+    let expressions: Vec<_> = ["c", "a b", "b a", "a a a", "c a"]
+        .into_iter()
+        .map(|expression| runtime.parse_expression(expression).unwrap())
+        .collect();
+
+    for expression in &expressions {
+        assert_eq!(&interner.expand(&interner.compact(expression)), expression);
+    }
+
+    for left in &expressions {
+        for right in &expressions {
+            assert_eq!(
+                interner.compact(left).cmp(&interner.compact(right)),
+                left.cmp(right)
+            );
+        }
+    }
+}
+
+#[test]
+fn indexed_neighbours_match_every_definition() {
+    let runtime = runtime_from(
+        "domain { a, b, c } reserve { + } use { }
+        a + b => c;
+        x + b => b;
+        a + x... => x...;
+        x... + => a;
+        x y => y;
+        c x... c => x...;",
+    );
+    let rules = rules::RuleSet::compile(&runtime, None, None);
+    let definitions: Vec<_> = runtime.definitions().collect();
+    let mut applied = BTreeSet::new();
+
+    for expression in ["a + b", "c + b c", "b + + a", "c a c + c", "a + c a", "a"] {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let tokens = &expression.tokens;
+
+        // Every definition on every window, keeping the first one that applies
+        let mut expected = Vec::new();
+        for size in 1..=tokens.len() {
+            for (start, window) in tokens.windows(size).enumerate() {
+                let first = definitions
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, d)| d.lower(window).map(|lowered| (index, lowered)));
+                if let Some((index, lowered)) = first {
+                    applied.insert(index);
+                    let mut result = tokens[..start].to_vec();
+                    result.extend(lowered.tokens);
+                    result.extend_from_slice(&tokens[start + size..]);
+                    expected.push(Expression::new(result));
+                }
+            }
+        }
+
+        let found: Vec<_> = rules
+            .lower_neighbours(&runtime.interner().compact(&expression))
+            .iter()
+            .map(|(neighbour, _)| runtime.interner().expand(neighbour))
+            .collect();
+
+        assert_eq!(found, expected);
+    }
+
+    // The fixtures exercise every definition, spreads included
+    assert_eq!(applied, (0..definitions.len()).collect());
+}
+
+#[test]
+fn all_matches_apply_every_definition() {
+    let program = "a b => c; a b => a;";
+    let first = runtime_from(&format!(
+        "domain {{ a, b, c }} reserve {{ }} use {{ }} {program}"
+    ));
+    let all = runtime_from(&format!(
+        "domain {{ a, b, c }} reserve {{ }} use {{ }} match {{ all }} {program}"
+    ));
+
+    let expression = first.parse_expression("a b").unwrap();
+    let evaluations = |runtime: &Runtime, options: &EvalOptions| {
+        runtime
+            .evaluations(expression.clone(), options, &mut |_| ())
+            .evaluations
+    };
+
+    let only_first = evaluations(&first, &EvalOptions::default());
+    assert_eq!(only_first.len(), 2);
+    assert!(!only_first.contains(&first.parse_expression("a").unwrap()));
+
+    let every = evaluations(&all, &EvalOptions::default());
+    assert_eq!(every.len(), 3);
+
+    // Per query, in either direction
+    let options = |match_mode| EvalOptions {
+        match_mode: Some(match_mode),
+        ..EvalOptions::default()
+    };
+    assert_eq!(evaluations(&first, &options(MatchMode::All)), every);
+    assert_eq!(evaluations(&all, &options(MatchMode::First)), only_first);
+}
+
+#[test]
+fn prove_equal_raises_and_lowers() {
+    // `a` and `b` are both smallest already, they only meet by going through `c`
+    let runtime =
+        runtime_from("domain { a, b, c } reserve { } use { } match { all } c => a; c => b;");
+    let a = runtime.parse_expression("a").unwrap();
+    let b = runtime.parse_expression("b").unwrap();
+
+    let Equivalence::Equal(derivation) =
+        runtime.prove_equal(a.clone(), b.clone(), &EvalLimits::none())
+    else {
+        panic!("`a` and `b` should be equal");
+    };
+
+    assert_eq!(derivation.start, a);
+    assert_eq!(derivation.result(), &b);
+
+    let directions: Vec<_> = derivation
+        .steps
+        .iter()
+        .map(|step| step.rewrite.direction)
+        .collect();
+    assert_eq!(directions, [Direction::Raise, Direction::Lower]);
+}
+
+#[test]
+fn prove_equal_respects_first_match() {
+    // `c` always lowers to `a`, so nothing is equal to `b`
+    let runtime = runtime_from("domain { a, b, c } reserve { } use { } c => a; c => b;");
+    let a = runtime.parse_expression("a").unwrap();
+    let b = runtime.parse_expression("b").unwrap();
+
+    assert_eq!(
+        runtime.prove_equal(a, b, &EvalLimits::none()),
+        Equivalence::Distinct
+    );
+}
+
+#[test]
+fn prove_equal_gives_up() {
+    // Neither side ever runs out of expressions
+    let runtime = runtime_from("domain { a, b } reserve { } use { } a => a a; b => b b;");
+    let a = runtime.parse_expression("a").unwrap();
+    let b = runtime.parse_expression("b").unwrap();
+
+    let limits = EvalLimits {
+        max_visited: Some(10),
+        ..EvalLimits::none()
+    };
+
+    assert_eq!(
+        runtime.prove_equal(a, b, &limits),
+        Equivalence::Unknown(Completion::CutOff(Limit::Visited(10)))
+    );
+}
+
+#[test]
+fn termination_growing_definitions() {
+    let runtime = runtime_from(
+        "domain { a, b } reserve { } use { } a => b b; x... a => x... x...; b b => a;",
+    );
+    let report = runtime.check_termination();
+
+    let growth: Vec<_> = report
+        .growing
+        .iter()
+        .map(|growing| growing.growth.clone())
+        .collect();
+
+    assert_eq!(
+        growth,
+        [
+            Growth::Bounded(1),
+            Growth::Unbounded {
+                variable: "x".to_string()
+            }
+        ]
+    );
+    assert!(!report.is_search_finite());
+}
+
+#[test]
+fn termination_proven() {
+    let runtime = runtime_from(
+        "domain { true, false } reserve { not, and } use { }
+        not true => false;
+        not false => true;
+        true and x => x;
+        false and x => false;",
+    );
+    let report = runtime.check_termination();
+
+    assert!(report.is_terminating());
+    assert!(report.is_search_finite());
+    assert!(report.cycles.is_empty());
+}
+
+#[test]
+fn std_core_is_proven_terminating() {
+    for module in ["std/core", "std/sets"] {
+        let runtime = runtime_with_std(&format!("domain {{ }} reserve {{ }} use {{ {module} }}"));
+        let report = runtime.check_termination();
+
+        assert_eq!(report.lpo, LpoProof::Shorter);
+        assert!(report.is_terminating());
+    }
+
+    // Definitions that keep the length need a precedence
+    let runtime = runtime_from("domain { a, b } reserve { } use { } a b => b a;");
+    assert!(matches!(
+        runtime.check_termination().lpo,
+        LpoProof::Proven { .. }
+    ));
+}
+
+#[test]
+fn termination_cycle() {
+    let runtime = runtime_from("domain { } reserve { + } use { } x + y => y + x;");
+    let report = runtime.check_termination();
+
+    assert!(!report.is_terminating());
+    assert!(matches!(report.lpo, LpoProof::Failed(_)));
+
+    assert_eq!(report.cycles.len(), 1);
+    let cycle = &report.cycles[0];
+    assert_eq!(cycle.result(), &cycle.start);
+    assert_eq!(cycle.steps.len(), 2);
+}
+
+#[test]
+fn confluence_overlap_at_offset() {
+    let runtime = runtime_from("domain { a, b, c } reserve { } use { } a b => c; b c => a;");
+    let report = runtime.check_confluence();
+
+    assert_eq!(report.pairs.len(), 1);
+
+    let (pair, joinability) = &report.pairs[0];
+    assert_eq!(pair.definitions, (0, 1));
+    assert_eq!(pair.overlap, runtime.parse_expression("a b c").unwrap());
+    assert_eq!(pair.results.0, runtime.parse_expression("c c").unwrap());
+    assert_eq!(pair.results.1, runtime.parse_expression("a a").unwrap());
+    assert_eq!(joinability, &Joinability::NotJoinable);
+
+    assert!(!report.is_locally_confluent());
+}
+
+#[test]
+fn confluence_joinable_pairs() {
+    let runtime = runtime_from("domain { a, b } reserve { } use { } a b => b; b b => b;");
+    let report = runtime.check_confluence();
+
+    assert!(!report.pairs.is_empty());
+    assert!(report.is_locally_confluent());
+}
+
+#[test]
+fn confluence_respects_first_match() {
+    let program = "domain { a, b, c, d } reserve { } use { } {} a b => c; a b => d;";
+
+    // The second definition never applies where the first one does
+    let first = runtime_from(&program.replace("{}", ""));
+    assert!(first.check_confluence().pairs.is_empty());
+
+    let all = runtime_from(&program.replace("{}", "match { all }"));
+    let report = all.check_confluence();
+
+    assert_eq!(report.pairs.len(), 1);
+    assert_eq!(report.pairs[0].1, Joinability::NotJoinable);
+}
+
+#[test]
+fn confluence_spreads_take_several_tokens() {
+    let runtime =
+        runtime_from("domain { a, b, c, g } reserve { f } use { } f s... g => a; x c => b;");
+    let report = runtime.check_confluence();
+
+    // `s...` has to stand for all of `x c`
+    let overlap = Expression::new(vec![
+        Token::Literal("f".to_string()),
+        Token::Element("x".to_string()),
+        Token::Element("c".to_string()),
+        Token::Element("g".to_string()),
+    ]);
+    assert!(report.pairs.iter().any(|(pair, _)| pair.overlap == overlap));
+    assert!(!report.is_locally_confluent());
+}
+
+#[test]
+fn confluence_variables_stand_for_whole_subterms() {
+    // `x` would have to stand for a lone `(`
+    let runtime = runtime_from("domain { a } reserve { f } use { } f x => a; ( y ) => y;");
+    assert!(runtime.critical_pairs().is_empty());
+
+    // The variables keep the names they have in the definitions
+    let runtime = runtime_from("domain { a, b } reserve { f, g } use { } f x => a; y g => b;");
+    let pairs = runtime.critical_pairs();
+
+    assert_eq!(pairs.len(), 1);
+    assert_eq!(
+        pairs[0].overlap,
+        Expression::new(vec![
+            Token::Literal("f".to_string()),
+            Token::Element("y".to_string()),
+            Token::Literal("g".to_string()),
+        ])
+    );
+}
+
+#[test]
+fn completion_adds_missing_definitions() {
+    let runtime = runtime_from("domain { a, b } reserve { } use { } a b => b; b a => a;");
+    assert!(!runtime.check_confluence().is_locally_confluent());
+
+    let completed = runtime.complete(&CompletionOptions::default()).unwrap();
+    assert_eq!(completed.definitions.len(), 4);
+    assert!(completed.check_confluence().is_locally_confluent());
+}
+
+#[test]
+fn completion_round_trips() {
+    let runtime =
+        runtime_from("domain { 0 } reserve { s, + } use { } 0 + y => y; s x + y => s ( x + y );");
+
+    let options = CompletionOptions {
+        order: ReductionOrder::Lpo(Precedence::default()),
+        ..CompletionOptions::default()
+    };
+    let completed = runtime.complete(&options).unwrap();
+
+    let reparsed = runtime_from(&completed.to_pink());
+    assert_eq!(
+        reparsed.definitions().cloned().collect::<Vec<_>>(),
+        completed.definitions
+    );
+}
+
+#[test]
+fn std_sets_membership() {
+    let runtime = runtime_with_std("domain { a, b, c } reserve { } use { std/sets }");
+
+    for (expression, expected) in [
+        ("a in { }", "false"),
+        ("a in { a }", "true"),
+        ("b in { a, b }", "true"),
+        ("a in { a, b, c }", "true"),
+        ("c in { a, b }", "false"),
+    ] {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+        assert_eq!(outcome.best(), &runtime.parse_expression(expected).unwrap());
+    }
+}
+
+#[test]
+fn completion_of_std_sets_is_convergent() {
+    let runtime = runtime_with_std("domain { a, b, c } reserve { } use { std/sets }");
+
+    // Its definitions and those of `std/core` are confluent already, since the guards keep the
+    // fallbacks apart
+    let completed = runtime.complete(&CompletionOptions::default()).unwrap();
+    assert_eq!(
+        completed.definitions,
+        runtime.definitions().cloned().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        completed.complete(&CompletionOptions::default()).unwrap(),
+        completed
+    );
+
+    let reparsed = runtime_from(&completed.to_pink());
+    assert_eq!(
+        reparsed.definitions().cloned().collect::<Vec<_>>(),
+        completed.definitions
+    );
+
+    for (expression, expected) in [
+        ("a in { }", "false"),
+        ("b in { a, b }", "true"),
+        ("c in { a, b }", "false"),
+        ("a = b", "false"),
+    ] {
+        let expression = reparsed.parse_expression(expression).unwrap();
+        let normal_forms = reparsed.normal_forms(expression, &EvalOptions::default());
+
+        assert!(normal_forms.is_unique());
+        assert_eq!(
+            normal_forms.forms[0].expression,
+            reparsed.parse_expression(expected).unwrap()
+        );
+    }
+}
+
+#[test]
+fn completion_fails_on_commutativity() {
+    let runtime = runtime_from("domain { } reserve { + } use { } x + y => y + x;");
+
+    let result = runtime.complete(&CompletionOptions::default());
+    assert!(matches!(result, Err(CompletionError::Unorientable(_))));
+}
+
+#[test]
+fn completion_gives_up() {
+    let runtime = runtime_from("domain { a, b, c } reserve { } use { } a b => c; b c => a;");
+
+    let options = CompletionOptions {
+        max_rules: 10,
+        ..CompletionOptions::default()
+    };
+
+    assert_eq!(
+        runtime.complete(&options),
+        Err(CompletionError::TooManyRules(10))
+    );
+}
+
+#[test]
+fn weights_pick_the_best_evaluation() {
+    let program = "domain { a, b } reserve { } use { } {} b => a;";
+
+    for (head, best) in [("", "a"), ("cost { a = 3 }", "b")] {
+        let runtime = runtime_from(&program.replace("{}", head));
+        let expression = runtime.parse_expression("b").unwrap();
+
+        let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+        assert_eq!(outcome.best(), &runtime.parse_expression(best).unwrap());
+    }
+}
+
+#[test]
+fn cost_functions_override_weights() {
+    let runtime = runtime_from("domain { a } reserve { } use { } cost { a = 0 } a => a a;");
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let limits = EvalLimits {
+        max_length: Some(3),
+        ..EvalLimits::none()
+    };
+
+    for strategy in [SearchStrategy::BestFirst, SearchStrategy::Beam { width: 1 }] {
+        let options = EvalOptions {
+            cost: Some(CostModel::function(|expression| {
+                10 - expression.tokens.len() as u64
+            })),
+            ..EvalOptions::new(limits.clone(), strategy)
+        };
+
+        let mut found = Vec::new();
+        let outcome = runtime.evaluations(expression.clone(), &options, &mut |visited| {
+            found.push(visited.latest().tokens.len())
+        });
+
+        assert_eq!(outcome.best().tokens.len(), 3);
+        assert_eq!(found, vec![1, 2, 3]);
+    }
+}
+
+#[test]
+fn observer_receives_every_event() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let limits = EvalLimits {
+        max_length: Some(3),
+        ..EvalLimits::none()
+    };
+
+    let mut events = Vec::new();
+    let outcome = runtime.evaluate_with(
+        expression,
+        &with_limits(limits),
+        &mut WithVisited(|event: EvalEvent<'_>| {
+            events.push(match event {
+                EvalEvent::Visited(expression) => format!("visited {}", expression.tokens.len()),
+                EvalEvent::NewBest(expression, _) => format!("best {}", expression.tokens.len()),
+                EvalEvent::Pruned(limit) => format!("pruned {limit:?}"),
+                EvalEvent::Finished(stats) => format!("finished {}", stats.visited),
+            });
+            ControlFlow::Continue(())
+        }),
+    );
+
+    assert_eq!(outcome.completion, Completion::CutOff(Limit::Length(3)));
+    assert_eq!(
+        events,
+        [
+            "visited 1",
+            "best 1",
+            "visited 2",
+            "pruned Length(3)",
+            "visited 3",
+            "finished 3"
+        ]
+    );
+}
+
+#[test]
+fn closures_only_get_visited_when_asked() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+    let options = with_limits(EvalLimits {
+        max_length: Some(3),
+        ..EvalLimits::none()
+    });
+
+    let mut visited = 0;
+    runtime.evaluate_with(expression.clone(), &options, &mut |event: EvalEvent<'_>| {
+        if let EvalEvent::Visited(_) = event {
+            visited += 1;
+        }
+        ControlFlow::Continue(())
+    });
+    assert_eq!(visited, 0);
+
+    let mut sizes = Vec::new();
+    let mut last = BTreeSet::new();
+    let outcome = runtime.evaluations(expression, &options, &mut |visited| {
+        assert!(visited.contains(&visited.latest()));
+        sizes.push(visited.len());
+        last = visited.to_set();
+    });
+    assert_eq!(sizes, [1, 2, 3]);
+    assert_eq!(outcome.evaluations, last);
+}
+
+#[test]
+fn observer_stops_the_search() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let mut visited = 0;
+    let outcome = runtime.evaluate_with(
+        expression,
+        &EvalOptions::default(),
+        &mut WithVisited(|event: EvalEvent<'_>| match event {
+            EvalEvent::Visited(_) if visited == 4 => ControlFlow::Break(()),
+            EvalEvent::Visited(_) => {
+                visited += 1;
+                ControlFlow::Continue(())
+            }
+            _ => ControlFlow::Continue(()),
+        }),
+    );
+
+    assert_eq!(outcome.completion, Completion::Cancelled);
+    assert_eq!(outcome.evaluations.len(), 5);
+}
+
+#[test]
+fn evaluation_runs_in_steps() {
+    let runtime = branching_runtime();
+    let expression = runtime.parse_expression("b b b b b").unwrap();
+
+    for strategy in [
+        SearchStrategy::DepthFirst,
+        SearchStrategy::BreadthFirst,
+        SearchStrategy::BestFirst,
+        SearchStrategy::IterativeDeepening,
+        SearchStrategy::Beam { width: 2 },
+    ] {
+        for threads in [1, 2] {
+            let options = EvalOptions {
+                threads,
+                ..EvalOptions::new(EvalLimits::none(), strategy)
+            };
+
+            let expected = runtime.evaluations(expression.clone(), &options, &mut |_| ());
+
+            let mut evaluation = runtime.start_evaluation(expression.clone(), &options);
+            let mut finished = 0;
+
+            let completion = loop {
+                let before = evaluation.visited_count();
+                let completion = evaluation.step(3, &mut |event: EvalEvent<'_>| {
+                    if let EvalEvent::Finished(_) = event {
+                        finished += 1;
+                    }
+                    ControlFlow::Continue(())
+                });
+
+                assert!(evaluation.visited_count() - before <= 3);
+
+                if let Some(completion) = completion {
+                    break completion;
+                }
+            };
+
+            assert_eq!(finished, 1);
+            assert_eq!(completion, expected.completion);
+            assert_eq!(evaluation.best().as_ref(), Some(expected.best()));
+
+            let outcome = evaluation.into_outcome();
+            assert_eq!(outcome.evaluations, expected.evaluations);
+        }
+    }
+}
+
+#[test]
+fn iterative_deepening_steps_count_every_pass() {
+    let runtime = branching_runtime();
+    let expression = runtime.parse_expression("b b b b b").unwrap();
+    let options = EvalOptions::new(EvalLimits::none(), SearchStrategy::IterativeDeepening);
+
+    let mut evaluation = runtime.start_evaluation(expression, &options);
+    let mut steps = 0;
+
+    while evaluation
+        .step(1, &mut |_: EvalEvent<'_>| ControlFlow::Continue(()))
+        .is_none()
+    {
+        steps += 1;
+    }
+
+    // Deeper passes visit the same expressions again, which takes steps too
+    assert!(steps > evaluation.visited_count());
+}
+
+#[test]
+fn evaluation_can_be_abandoned() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let mut evaluation = runtime.start_evaluation(expression, &EvalOptions::default());
+    assert_eq!(evaluation.best(), None);
+
+    assert_eq!(
+        evaluation.step(10, &mut |_: EvalEvent<'_>| ControlFlow::Continue(())),
+        None
+    );
+    assert_eq!(evaluation.visited_count(), 10);
+    assert_eq!(evaluation.completion(), None);
+
+    let outcome = evaluation.into_outcome();
+    assert_eq!(outcome.completion, Completion::Cancelled);
+    assert_eq!(outcome.evaluations.len(), 10);
+}
+
+#[test]
+fn tree_mode_only_rewrites_whole_subterms() {
+    let program = "domain { a, b } reserve { } use { } {} x b => a;";
+    let flat = runtime_from(&program.replace("{}", ""));
+    let tree = runtime_from(&program.replace("{}", "grouping { tree }"));
+
+    assert_eq!(tree.structures["main"].get_grouping(), Grouping::Tree);
+
+    let expression = tree.parse_expression("( a b ) b").unwrap();
+    let evaluations = |runtime: &Runtime| {
+        runtime
+            .evaluations(expression.clone(), &EvalOptions::default(), &mut |_| ())
+            .evaluations
+    };
+
+    // `x` binds `)` in the flat reading
+    assert_eq!(evaluations(&flat).len(), 4);
+    assert_eq!(
+        evaluations(&tree),
+        BTreeSet::from([
+            expression.clone(),
+            tree.parse_expression("( a ) b").unwrap()
+        ])
+    );
+}
+
+#[test]
+fn tree_mode_can_be_set_per_evaluation() {
+    let runtime = runtime_from("domain { a, b } reserve { } use { } x b => a;");
+    let expression = runtime.parse_expression("( a b ) b").unwrap();
+
+    let options = EvalOptions {
+        grouping: Some(Grouping::Tree),
+        trace: true,
+        ..EvalOptions::default()
+    };
+
+    let outcome = runtime.evaluations(expression, &options, &mut |_| ());
+    assert_eq!(outcome.evaluations.len(), 2);
+
+    let target = runtime.parse_expression("( a ) b").unwrap();
+    let derivation = runtime
+        .derivation(outcome.trace.as_ref().unwrap(), &target)
+        .unwrap();
+
+    let bindings = &derivation.steps[0].rewrite.bindings;
+    assert_eq!(bindings.single["x"], Token::Element("a".to_string()));
+}
+
+fn adding_runtime(program: &str) -> Runtime {
+    let mut runtime = runtime_from(program);
+    runtime.register_host_function("add", |_, bindings| {
+        let number = |name: &str| bindings.single[name].as_str().parse::<u64>().ok();
+        let sum = number("x")? + number("y")?;
+        Some(Expression::new(vec![Token::Element(sum.to_string())]))
+    });
+    runtime
+}
+
+#[test]
+fn host_functions_lower_windows() {
+    let runtime = adding_runtime("domain { 1, 2 } reserve { + } use { } x + y => @add;");
+    let expression = runtime.parse_expression("1 + 2 + 2").unwrap();
+
+    let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+    assert_eq!(
+        outcome.best(),
+        &Expression::new(vec![Token::Element("5".to_string())])
+    );
+}
+
+#[test]
+fn host_functions_can_decline() {
+    let runtime =
+        adding_runtime("domain { 1, a } reserve { + } use { } x + y => @add; x + y => y;");
+    let expression = runtime.parse_expression("1 + a").unwrap();
+
+    let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+    assert_eq!(outcome.best(), &runtime.parse_expression("a").unwrap());
+
+    // Nothing is registered as `sub`
+    let runtime = adding_runtime("domain { 1 } reserve { - } use { } x - y => @sub;");
+    let expression = runtime.parse_expression("1 - 1").unwrap();
+
+    let outcome = runtime.evaluations(expression.clone(), &EvalOptions::default(), &mut |_| ());
+    assert_eq!(outcome.evaluations.len(), 1);
+    assert_eq!(outcome.best(), &expression);
+
+    let unregistered: Vec<_> = runtime.unregistered_host_definitions().collect();
+    assert_eq!(unregistered.len(), 1);
+    assert_eq!(unregistered[0].host_function(), Some("sub"));
+}
+
+#[test]
+fn structures_can_register_host_functions() {
+    let mut runtime = runtime_from("domain { 1, 2 } reserve { + } use { } x + y => @add;");
+    assert_eq!(runtime.unregistered_host_definitions().count(), 1);
+
+    let mut structure = runtime.structures["main"].clone();
+    structure.register_host_function("add", |_, _| {
+        Some(Expression::new(vec![Token::Element("0".to_string())]))
+    });
+    runtime.insert("main".to_string(), structure);
+
+    assert_eq!(runtime.unregistered_host_definitions().count(), 0);
+    assert!(runtime.host_functions().any(|name| name == "add"));
+
+    let expression = runtime.parse_expression("1 + 2").unwrap();
+    let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+    assert_eq!(
+        outcome.best(),
+        &Expression::new(vec![Token::Element("0".to_string())])
+    );
+
+    // The runtime's own functions take precedence
+    runtime.register_host_function("add", |_, _| {
+        Some(Expression::new(vec![Token::Element("3".to_string())]))
+    });
+
+    let expression = runtime.parse_expression("1 + 2").unwrap();
+    let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+    assert_eq!(
+        outcome.best(),
+        &Expression::new(vec![Token::Element("3".to_string())])
+    );
+}
+
+#[test]
+fn host_definitions_are_not_equations() {
+    let runtime = adding_runtime("domain { } reserve { + } use { } x + y => @add;");
+
+    let report = runtime.check_termination();
+    assert_eq!(
+        report.growing[0].growth,
+        Growth::Host {
+            function: "add".to_string()
+        }
+    );
+    assert!(matches!(report.lpo, LpoProof::Failed(_)));
+
+    assert!(matches!(
+        runtime.complete(&CompletionOptions::default()),
+        Err(CompletionError::HostDefinition(_))
+    ));
+}
+
+#[test]
+fn integer_arithmetic() {
+    let integer = |s: &str| s.parse::<Integer>().unwrap();
+
+    let a = integer("123456789012345678901234567890");
+    let b = integer("-987654321098765432109876543210");
+
+    assert_eq!(
+        a.mul(&b).to_string(),
+        "-121932631137021795226185032733622923332237463801111263526900"
+    );
+    assert_eq!(a.add(&b).to_string(), "-864197532086419753208641975320");
+    assert_eq!(a.sub(&b).to_string(), "1111111110111111111011111111100");
+    assert_eq!(b.div_euclid(&a).unwrap().to_string(), "-9");
+    assert_eq!(
+        b.rem_euclid(&a).unwrap().to_string(),
+        "123456780012345678001234567800"
+    );
+    assert_eq!(a.div_euclid(&integer("0")), None);
+
+    for (x, y, quotient, remainder) in [("7", "-2", "-3", "1"), ("-7", "-2", "4", "1")] {
+        assert_eq!(
+            integer(x).div_euclid(&integer(y)).unwrap(),
+            integer(quotient)
+        );
+        assert_eq!(
+            integer(x).rem_euclid(&integer(y)).unwrap(),
+            integer(remainder)
+        );
+    }
+
+    assert_eq!(integer("-000").to_string(), "0");
+    assert_eq!(integer("-0012").to_string(), "-12");
+    assert!(integer("-12") < integer("3"));
+    assert!("1-2".parse::<Integer>().is_err());
+}
+
+#[test]
+fn std_int_evaluates_numerals() {
+    let runtime = runtime_with_std("domain { } reserve { } use { std/int }");
+    let parse = |expression: &str| runtime.parse_expression(expression).unwrap();
+    let evaluate = |expression: &str| {
+        let outcome = runtime.evaluations(parse(expression), &EvalOptions::default(), &mut |_| ());
+        outcome.best().clone()
+    };
+
+    assert_eq!(
+        evaluate("(1267650600228229401496703205376 * 2) - 1"),
+        parse("2535301200456458802993406410751")
+    );
+    assert_eq!(evaluate("-7 mod 3 = 0002"), parse("true"));
+    assert_eq!(evaluate("7 / 0"), parse("7 / 0"));
+    assert_eq!(evaluate("successor(41)"), parse("42"));
+}
+
+#[test]
+fn std_peano_works_on_numerals() {
+    let runtime = runtime_with_std(
+        "domain { a } reserve { double } use { std/peano }
+        double(0) => 0;
+        double(successor(n)) => successor(successor(double(n)));",
+    );
+
+    for (expression, expected) in [
+        ("successor(2) + successor(1)", "5"),
+        ("double(3)", "6"),
+        ("successor(successor(2))", "4"),
+    ] {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+        assert_eq!(outcome.best(), &runtime.parse_expression(expected).unwrap());
+    }
+
+    // Numerals on the right of `+` are unfolded for the definitions of `std/peano`
+    let expression = runtime.parse_expression("a + 1").unwrap();
+    let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+    let unfolded = runtime.parse_expression("successor(a + 0)").unwrap();
+    assert!(outcome.evaluations.contains(&unfolded));
+
+    for expression in ["2 in Naturals", "successor(0) in Naturals"] {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let normal_forms = runtime.normal_forms(expression, &EvalOptions::default());
+
+        assert!(normal_forms.is_unique());
+        assert_eq!(
+            normal_forms.forms[0].expression,
+            runtime.parse_expression("true").unwrap()
+        );
+    }
+}
+
+#[test]
+fn profile_counts_definitions() {
+    let runtime =
+        runtime_from("domain { a, b, c } reserve { } use { } a b => b; b b => a; c => c;");
+    let expression = runtime.parse_expression("a b b").unwrap();
+
+    let options = EvalOptions {
+        profile: true,
+        ..EvalOptions::default()
+    };
+
+    let outcome = runtime.evaluations(expression.clone(), &options, &mut |_| ());
+    let profile = outcome.profile.unwrap();
+
+    let counts: Vec<_> = profile
+        .definitions
+        .iter()
+        .map(|profile| (profile.attempts, profile.successes))
+        .collect();
+
+    // `a a` ends with the wrong token for `a b`, so it isn't even tried
+    assert_eq!(counts, [(1, 1), (2, 2), (0, 0)]);
+    assert_eq!(profile.visited, 4);
+    assert_eq!(profile.frontier_peak, 2);
+    assert_eq!(profile.duplicates, 0);
+
+    let dead: Vec<_> = profile.dead().map(|profile| &profile.definition).collect();
+    assert_eq!(dead, [runtime.definitions().last().unwrap()]);
+
+    let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+    assert!(outcome.profile.is_none());
+}
+
+#[test]
+fn parallel_profile_matches_sequential() {
+    let runtime = branching_runtime();
+    let expression = runtime.parse_expression("b b b b").unwrap();
+
+    let profile = |threads| {
+        let options = EvalOptions {
+            profile: true,
+            threads,
+            ..EvalOptions::default()
+        };
+
+        let outcome = runtime.evaluations(expression.clone(), &options, &mut |_| ());
+        outcome.profile.unwrap()
+    };
+
+    let (sequential, parallel) = (profile(1), profile(4));
+    assert_eq!(sequential.visited, parallel.visited);
+    assert_eq!(sequential.definitions, parallel.definitions);
+    assert!(sequential.duplicates > 0);
+}
+
+#[test]
+fn outermost_reduction_skips_unused_arguments() {
+    let runtime =
+        runtime_from("domain { a } reserve { first, loop } use { } first x y => x; loop => loop;");
+    let expression = runtime.parse_expression("first a loop").unwrap();
+
+    let limits = EvalLimits {
+        max_visited: Some(10),
+        ..EvalLimits::none()
+    };
+
+    let reduction = runtime.reduce(
+        expression.clone(),
+        ReductionStrategy::LeftmostOutermost,
+        &limits,
+    );
+    assert_eq!(reduction.expression, runtime.parse_expression("a").unwrap());
+    assert_eq!(reduction.steps, 1);
+    assert!(reduction.is_normal_form());
+
+    // Innermost rewrites `loop` first, forever
+    let reduction = runtime.reduce(expression, ReductionStrategy::LeftmostInnermost, &limits);
+    assert_eq!(reduction.completion, Completion::CutOff(Limit::Visited(10)));
+    assert_eq!(reduction.steps, 10);
+}
+
+#[test]
+fn parallel_reduction_rewrites_every_redex() {
+    let runtime = runtime_from("domain { a, b } reserve { } use { } a => b;");
+    let expression = runtime.parse_expression("a a a").unwrap();
+    let expected = runtime.parse_expression("b b b").unwrap();
+
+    let reduction = runtime.reduce(
+        expression.clone(),
+        ReductionStrategy::ParallelOutermost,
+        &EvalLimits::none(),
+    );
+    assert_eq!((&reduction.expression, reduction.steps), (&expected, 1));
+
+    let reduction = runtime.reduce(
+        expression,
+        ReductionStrategy::LeftmostOutermost,
+        &EvalLimits::none(),
+    );
+    assert_eq!((&reduction.expression, reduction.steps), (&expected, 3));
+}
+
+#[test]
+fn reduction_normalises_conditionals() {
+    let runtime = runtime_with_std("domain { a, b } reserve { } use { std/core }");
+    let expression = runtime
+        .parse_expression("if ( a = a ) then a else b")
+        .unwrap();
+
+    for strategy in [
+        ReductionStrategy::LeftmostInnermost,
+        ReductionStrategy::LeftmostOutermost,
+    ] {
+        let reduction = runtime.reduce(expression.clone(), strategy, &EvalLimits::none());
+
+        assert_eq!(reduction.expression, runtime.parse_expression("a").unwrap());
+        assert_eq!(reduction.steps, 4);
+        assert_eq!(reduction.completion, Completion::Exhaustive);
+    }
+}
+
+#[test]
+fn reduction_respects_max_length() {
+    let runtime = unbounded_runtime();
+    let expression = runtime.parse_expression("a").unwrap();
+
+    let limits = EvalLimits {
+        max_length: Some(4),
+        ..EvalLimits::none()
+    };
+
+    let reduction = runtime.reduce(expression, ReductionStrategy::ParallelOutermost, &limits);
+    assert_eq!(
+        reduction.expression,
+        runtime.parse_expression("a a a a").unwrap()
+    );
+    assert_eq!(reduction.completion, Completion::CutOff(Limit::Length(4)));
+}
+
+#[test]
+fn reduction_strategy_round_trip() {
+    let strategies = [
+        ReductionStrategy::LeftmostInnermost,
+        ReductionStrategy::LeftmostOutermost,
+        ReductionStrategy::ParallelOutermost,
+    ];
+
+    for strategy in strategies {
+        assert_eq!(strategy.to_string().parse(), Ok(strategy));
+    }
+
+    assert!("sideways".parse::<ReductionStrategy>().is_err());
+}
+
+#[test]
+fn normal_forms_are_counted() {
+    let runtime = runtime_from("domain { a, b, c, d } reserve { } use { } a b => c; b c => a d;");
+    let expression = runtime.parse_expression("a b c").unwrap();
+
+    let normal_forms = runtime.normal_forms(expression, &EvalOptions::default());
+
+    let forms: Vec<_> = normal_forms
+        .forms
+        .iter()
+        .map(|form| (form.expression.clone(), form.reached_from))
+        .collect();
+
+    assert_eq!(
+        forms,
+        [
+            (runtime.parse_expression("c c").unwrap(), 2),
+            (runtime.parse_expression("a a d").unwrap(), 2),
+        ]
+    );
+    assert!(!normal_forms.is_unique());
+    assert_eq!(normal_forms.visited, 3);
+    assert_eq!(normal_forms.completion, Completion::Exhaustive);
+}
+
+#[test]
+fn confluent_definitions_have_one_normal_form() {
+    let runtime = runtime_from("domain { a, b, c, d } reserve { } use { } a => b; c => d;");
+    let expression = runtime.parse_expression("a c a").unwrap();
+
+    let normal_forms = runtime.normal_forms(expression, &EvalOptions::default());
+
+    assert!(normal_forms.is_unique());
+    assert_eq!(
+        normal_forms.forms[0].expression,
+        runtime.parse_expression("b d b").unwrap()
+    );
+    assert_eq!(normal_forms.forms[0].reached_from, normal_forms.visited);
+}
+
+#[test]
+fn guards_normalise_to_true() {
+    let runtime = runtime_with_std(
+        "domain { } reserve { abs } use { std/int } numerals { integers }
+        abs x => 0 - x where x < 0;
+        abs x => x;",
+    );
+
+    for (expression, expected) in [("abs -5", "5"), ("abs 3", "3"), ("abs 0", "0")] {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+        assert_eq!(outcome.best(), &runtime.parse_expression(expected).unwrap());
+    }
+}
+
+#[test]
+fn guards_compare_bindings() {
+    let runtime = runtime_from(
+        "domain { a, b, true, false } reserve { same } use { }
+        same x y => false where x != y;
+        same x y => true;",
+    );
+
+    let normal_form = |expression| {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let normal_forms = runtime.normal_forms(expression, &EvalOptions::default());
+        assert!(normal_forms.is_unique());
+        normal_forms.forms[0].expression.clone()
+    };
+
+    let (true_, false_) = (
+        runtime.parse_expression("true").unwrap(),
+        runtime.parse_expression("false").unwrap(),
+    );
+    assert_eq!(normal_form("same a b"), false_);
+    assert_eq!(normal_form("same b b"), true_);
+
+    let definition = runtime.definitions().next().unwrap();
+    let tokens = |expression| runtime.parse_expression(expression).unwrap().tokens;
+    assert_eq!(definition.lower(&tokens("same a b")), Some(false_));
+    assert_eq!(definition.lower(&tokens("same a a")), None);
+}
+
+#[test]
+fn recursive_guards_give_up() {
+    let runtime = runtime_from("domain { a, true } reserve { p } use { } p x => true where p x;");
+    let expression = runtime.parse_expression("p a").unwrap();
+
+    let outcome = runtime.evaluations(expression.clone(), &EvalOptions::default(), &mut |_| ());
+    assert_eq!(outcome.best(), &expression);
+    assert!(outcome.is_exhaustive());
+}
+
+#[test]
+fn completion_rejects_guards() {
+    let runtime = runtime_from("domain { a, b, true } reserve { = } use { } a => b where a = b;");
+
+    assert!(matches!(
+        runtime.complete(&CompletionOptions::default()),
+        Err(CompletionError::GuardedDefinition(_))
+    ));
+}
+
+#[test]
+fn sorted_variables_only_bind_their_sort() {
+    let runtime = runtime_from(
+        "domain { a, b, true, false } reserve { in, Vowels, f, g } use { }
+        a in Vowels => true;
+        f x: Vowels => x x;
+        f x: element => x;
+        g _ x: literal _ => x;",
+    );
+
+    for (expression, expected) in [
+        ("f a", "a a"),
+        ("f b", "b"),
+        ("f g", "f g"),
+        ("g a f b", "f"),
+        ("g a b b", "g a b b"),
+    ] {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+        assert_eq!(outcome.best(), &runtime.parse_expression(expected).unwrap());
+    }
+}
+
+#[test]
+fn completion_rejects_sorted_variables() {
+    let runtime = runtime_from("domain { a } reserve { f } use { } f x: element => x;");
+
+    assert!(matches!(
+        runtime.complete(&CompletionOptions::default()),
+        Err(CompletionError::RestrictedVariables(_))
+    ));
+}
+
+#[test]
+fn optional_spreads_can_match_nothing() {
+    let runtime = runtime_from(
+        "domain { a, b, true, false } reserve { last, same, (, ) } use { }
+        last ( rest...? x ) => x;
+        same ( xs...? ) ( xs...? ) => true;",
+    );
+
+    for (expression, expected) in [
+        ("last ( a )", "a"),
+        ("last ( a b )", "b"),
+        ("last ( a b a )", "a"),
+        ("same ( ) ( )", "true"),
+        ("same ( a b ) ( a b )", "true"),
+        ("same ( a ) ( )", "same ( a ) ( )"),
+    ] {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+        assert_eq!(outcome.best(), &runtime.parse_expression(expected).unwrap());
+    }
+}
+
+#[test]
+fn optional_spreads_in_analyses() {
+    // With `rest` binding nothing, the definition rewrites `a` to itself
+    let runtime = runtime_from("domain { a } reserve { } use { } rest...? a => a;");
+    assert!(matches!(
+        runtime.check_termination().lpo,
+        LpoProof::Failed(_)
+    ));
+
+    let runtime = runtime_from("domain { a, b } reserve { f } use { } f rest...? => a; b f => b;");
+    assert!(runtime
+        .critical_pairs()
+        .iter()
+        .any(|pair| pair.overlap == runtime.parse_expression("b f").unwrap()));
+}
+
+#[test]
+fn spreads_only_bind_whole_subterms() {
+    let runtime = runtime_from(
+        "domain { a, b, c } reserve { first, count, one, g } use { }
+        ( x... ) => x...;
+        first { x...: item, rest... } => x...;
+        count { x...: item } => one;",
+    );
+
+    // `x...` would bind `a ) ( b` otherwise
+    let expression = runtime.parse_expression("( a ) ( b )").unwrap();
+    let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+    let bogus = runtime.parse_expression("a ) ( b").unwrap();
+    assert!(!outcome.evaluations.contains(&bogus));
+
+    for (expression, expected) in [
+        ("first { g { a , b } , c }", "g { a , b }"),
+        ("count { ( a , b ) }", "one"),
+        ("count { a , b }", "count { a , b }"),
+    ] {
+        let expression = runtime.parse_expression(expression).unwrap();
+        let outcome = runtime.evaluations(expression, &EvalOptions::default(), &mut |_| ());
+        assert_eq!(outcome.best(), &runtime.parse_expression(expected).unwrap());
+    }
+}
+
+#[test]
+fn ties_do_not_depend_on_interning_order() {
+    let runtime = runtime_from(
+        "domain { } reserve { pick } use { } match { all } numerals { integers }
+        pick x y => x;
+        pick x y => y;",
+    );
+
+    // `7` is interned before `3`, but `3` still comes first
+    let expression = runtime.parse_expression("pick 7 3").unwrap();
+    let best = runtime.eval(expression, &EvalOptions::default(), &mut |_| ());
+    assert_eq!(best, runtime.parse_expression("3").unwrap());
+
+    // Best first search pops them in the same order
+    let options = EvalOptions::new(EvalLimits::none(), SearchStrategy::BestFirst);
+    let mut order = Vec::new();
+    runtime.evaluations(
+        runtime.parse_expression("pick 7 3").unwrap(),
+        &options,
+        &mut |visited| order.push(visited.latest()),
+    );
+    assert_eq!(
+        order[1..],
+        ["3", "7"].map(|n| runtime.parse_expression(n).unwrap())
+    );
+
+    let interner = intern::Interner::new([Token::Element("b".to_string())]);
+    let b = interner.compact(&Expression::new(vec![Token::Element("b".to_string())]));
+    let a = interner.compact(&Expression::new(vec![Token::Element("a".to_string())]));
+    assert!(b < a);
+    assert!(interner.compare(&a, &b).is_lt());
+}
+
+#[test]
+fn completion_reads_reserved_words_as_structure() {
+    let runtime = runtime_from(
+        "domain { a } reserve { if, then, else } use { } if a then x... else y... => y...;",
+    );
+
+    let completed = runtime.complete(&CompletionOptions::default()).unwrap();
+    assert_eq!(
+        completed.definitions,
+        runtime.definitions().cloned().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn completion_keeps_guards() {
+    let runtime = runtime_from(
+        "domain { true, false } reserve { and } use { }
+        true and true => true;
+        p and q => false where p q != true true;",
+    );
+
+    let completed = runtime.complete(&CompletionOptions::default()).unwrap();
+    assert_eq!(
+        completed.definitions,
+        runtime.definitions().cloned().collect::<Vec<_>>()
+    );
+
+    let reparsed = runtime_from(&completed.to_pink());
+    assert_eq!(
+        reparsed.definitions().cloned().collect::<Vec<_>>(),
+        completed.definitions
+    );
+}
@@ -0,0 +1,248 @@
+//! Derivation traces, to explain how an evaluation got to a result.
+//!
+//! While searching, every rewrite is recorded as an edge from the expression that was rewritten
+//! to the one that came out of it. From those edges the shortest derivation from the original
+//! expression to anything that was visited can be rebuilt.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Display,
+};
+
+use crate::matching;
+
+use super::{rules::Step, Definition, Expression, Runtime, Token};
+
+/// What each variable of a definition was bound to when it was applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Bindings {
+    pub single: BTreeMap<String, Token>,
+    pub spread: BTreeMap<String, Vec<Token>>,
+}
+
+impl From<matching::Bindings<'_, '_>> for Bindings {
+    fn from((single, spread): matching::Bindings) -> Self {
+        Self {
+            single: single
+                .into_iter()
+                .map(|(name, token)| (name.clone(), token.clone()))
+                .collect(),
+            spread: spread
+                .into_iter()
+                .map(|(name, tokens)| (name.clone(), tokens.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+/// Which way a definition was applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Direction {
+    /// From the left hand side of the definition to the right hand side, as in evaluations.
+    #[default]
+    Lower,
+
+    /// From the right hand side to the left hand side.
+    Raise,
+}
+
+impl Direction {
+    pub fn reversed(self) -> Self {
+        match self {
+            Direction::Lower => Direction::Raise,
+            Direction::Raise => Direction::Lower,
+        }
+    }
+}
+
+/// A single application of a definition to a window of an expression.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Rewrite {
+    /// Index of the definition in [`Runtime::definitions`].
+    pub definition: usize,
+
+    pub direction: Direction,
+
+    /// Index of the first token of the window that was rewritten.
+    pub window_start: usize,
+
+    /// Number of tokens of the window that was rewritten.
+    pub window_size: usize,
+
+    pub bindings: Bindings,
+}
+
+impl Rewrite {
+    /// Rebuilds the rewrite that a search step did on `parent`, including the bindings.
+    pub(crate) fn from_step(definition: &Definition, parent: &Expression, step: Step) -> Self {
+        let window = &parent.tokens[step.window_start..step.window_start + step.window_size];
+
+        let bindings = definition
+            .bindings(step.direction, step.grouping, window)
+            .expect("The definition matched this window while searching");
+
+        Self {
+            definition: step.definition,
+            direction: step.direction,
+            window_start: step.window_start,
+            window_size: step.window_size,
+            bindings,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Edge {
+    parent: Expression,
+    rewrite: Rewrite,
+}
+
+/// Every rewrite that was found during an evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    root: Expression,
+
+    /// For each expression, the rewrites that produced it.
+    incoming: BTreeMap<Expression, BTreeSet<Edge>>,
+}
+
+impl Trace {
+    pub(crate) fn new(root: Expression) -> Self {
+        Self {
+            root,
+            incoming: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, parent: &Expression, child: &Expression, rewrite: Rewrite) {
+        let edge = Edge {
+            parent: parent.clone(),
+            rewrite,
+        };
+
+        match self.incoming.get_mut(child) {
+            Some(edges) => {
+                edges.insert(edge);
+            }
+            None => {
+                self.incoming.insert(child.clone(), BTreeSet::from([edge]));
+            }
+        }
+    }
+
+    /// Every recorded rewrite that produced `expression`, with the expression it was applied to.
+    pub(crate) fn rewrites_into(
+        &self,
+        expression: &Expression,
+    ) -> impl Iterator<Item = (&Expression, &Rewrite)> + '_ {
+        self.incoming
+            .get(expression)
+            .into_iter()
+            .flatten()
+            .map(|edge| (&edge.parent, &edge.rewrite))
+    }
+
+    /// The expression the evaluation started from.
+    pub fn root(&self) -> &Expression {
+        &self.root
+    }
+
+    /// Shortest sequence of rewrites that goes from the root to `target`, as
+    /// `(rewrite, result)` pairs.
+    ///
+    /// Returns `None` if `target` was never reached.
+    pub fn shortest_path(&self, target: &Expression) -> Option<Vec<(Rewrite, Expression)>> {
+        // Breadth first search backwards, from the target to the root. For each expression
+        // found, remember the step that gets one closer to the target.
+        let mut towards_target: BTreeMap<&Expression, (&Rewrite, &Expression)> = BTreeMap::new();
+        let mut queue = VecDeque::from([target]);
+
+        while let Some(expression) = queue.pop_front() {
+            if expression == &self.root {
+                let mut path = Vec::new();
+                let mut current = &self.root;
+
+                while current != target {
+                    let (rewrite, next) = towards_target[current];
+                    path.push((rewrite.clone(), next.clone()));
+                    current = next;
+                }
+
+                return Some(path);
+            }
+
+            for edge in self.incoming.get(expression).into_iter().flatten() {
+                if edge.parent == *target || towards_target.contains_key(&edge.parent) {
+                    continue;
+                }
+
+                towards_target.insert(&edge.parent, (&edge.rewrite, expression));
+                queue.push_back(&edge.parent);
+            }
+        }
+
+        None
+    }
+}
+
+/// One step of a [`Derivation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationStep {
+    pub rewrite: Rewrite,
+    pub definition: Definition,
+    pub result: Expression,
+}
+
+/// A chain of rewrites from one expression to another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Derivation {
+    pub start: Expression,
+    pub steps: Vec<DerivationStep>,
+}
+
+impl Derivation {
+    /// The expression at the end of the derivation.
+    pub fn result(&self) -> &Expression {
+        self.steps.last().map_or(&self.start, |step| &step.result)
+    }
+}
+
+impl Display for Derivation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "   {}", self.start)?;
+
+        for step in &self.steps {
+            match step.rewrite.direction {
+                Direction::Lower => writeln!(f, "=> {}  (by {})", step.result, step.definition)?,
+                Direction::Raise => writeln!(f, "<= {}  (by {})", step.result, step.definition)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Runtime {
+    /// Rebuilds the shortest derivation from the start of a traced evaluation to `target`.
+    ///
+    /// The trace has to come from an evaluation with this same runtime, since rewrites refer to
+    /// definitions by index.
+    pub fn derivation(&self, trace: &Trace, target: &Expression) -> Option<Derivation> {
+        let definitions: Vec<_> = self.definitions().collect();
+
+        let steps = trace
+            .shortest_path(target)?
+            .into_iter()
+            .map(|(rewrite, result)| DerivationStep {
+                definition: definitions[rewrite.definition].clone(),
+                rewrite,
+                result,
+            })
+            .collect();
+
+        Some(Derivation {
+            start: trace.root().clone(),
+            steps,
+        })
+    }
+}
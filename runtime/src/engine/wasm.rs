@@ -1,23 +1,31 @@
-use std::collections::BTreeSet;
+use std::{ops::ControlFlow, time::Duration};
 
 use wasm_bindgen::prelude::*;
 
 use crate::parser::parse;
 use crate::resolvers::{Chain, EmbeddedStdResolver, MapResolver, Resolver};
-use crate::{Expression, Runtime};
+use crate::{
+    EvalEvent, EvalLimits, EvalObserver, EvalOptions, Evaluation, Expression, Runtime,
+    SearchStrategy,
+};
 
 #[wasm_bindgen]
 pub struct AsyncRuntime {
-    runtime: Option<Runtime>,
+    /// The parsed program, or why there isn't one.
+    runtime: Result<Runtime, String>,
     resolver: Chain<EmbeddedStdResolver, MapResolver>,
+
+    /// The evaluation being run with [`AsyncRuntime::step`], if any.
+    evaluation: Option<Evaluation>,
 }
 
 #[wasm_bindgen]
 impl AsyncRuntime {
     pub fn new() -> AsyncRuntime {
         Self {
-            runtime: None,
+            runtime: Err("No program has been parsed yet".to_string()),
             resolver: EmbeddedStdResolver::default().chain(MapResolver::new()),
+            evaluation: None,
         }
     }
 
@@ -26,71 +34,147 @@ impl AsyncRuntime {
     }
 
     pub fn parse_with_main(&mut self, name: &str) {
-        self.runtime = parse(name, &mut self.resolver).ok();
+        self.runtime = parse(name, &mut self.resolver).map_err(|error| error.to_string());
+        self.evaluation = None;
     }
 
+    /// Runs a whole evaluation at once. Use [`AsyncRuntime::start`] and [`AsyncRuntime::step`]
+    /// instead to keep the worker responsive. Returns an error message if the evaluation can't
+    /// start.
+    #[allow(clippy::too_many_arguments)]
     pub async fn evaluations(
         &mut self,
         expression: String,
         callback: js_sys::Function,
         on_finish: js_sys::Function,
-        performance: &web_sys::Performance,
-    ) -> JsValue {
-        let expression_result = self.runtime.as_mut().unwrap().parse_expression(&expression);
-
-        let expression = match expression_result {
-            Ok(expression) => expression,
-            Err(error) => {
-                return error.to_string().into();
-            }
-        };
+        // Unused, the runtime measures the time itself
+        _performance: &web_sys::Performance,
+        max_visited: Option<usize>,
+        max_length: Option<usize>,
+        max_seconds: Option<f64>,
+        strategy: Option<String>,
+    ) -> Result<(), String> {
+        let runtime = self.runtime.as_ref().map_err(Clone::clone)?;
+
+        let (expression, options) = prepare(
+            runtime,
+            &expression,
+            max_visited,
+            max_length,
+            max_seconds,
+            strategy,
+        )?;
+
+        let outcome =
+            runtime.evaluate_with(expression, &options, &mut wasm_loop_observer(callback));
+
+        on_finish
+            .call1(&JsValue::NULL, &outcome.completion.to_string().into())
+            .unwrap();
+
+        Ok(())
+    }
 
-        let runtime = self.runtime.as_ref().unwrap();
+    /// Starts an evaluation that runs a few expressions at a time with [`AsyncRuntime::step`],
+    /// replacing the previous one. Returns an error message if the evaluation can't start.
+    pub fn start(
+        &mut self,
+        expression: String,
+        max_visited: Option<usize>,
+        max_length: Option<usize>,
+        max_seconds: Option<f64>,
+        strategy: Option<String>,
+    ) -> Result<(), String> {
+        self.evaluation = None;
+
+        let runtime = self.runtime.as_ref().map_err(Clone::clone)?;
+
+        let (expression, options) = prepare(
+            runtime,
+            &expression,
+            max_visited,
+            max_length,
+            max_seconds,
+            strategy,
+        )?;
+
+        self.evaluation = Some(runtime.start_evaluation(expression, &options));
+        Ok(())
+    }
 
-        runtime.evaluations(
-            expression,
-            &mut wasm_loop_callback(callback, performance.clone()),
-        );
+    /// Visits up to `visits` more expressions of the current evaluation, calling `callback` like
+    /// [`AsyncRuntime::evaluations`] does.
+    ///
+    /// Returns how the evaluation ended once it does, after which it is dropped.
+    pub fn step(&mut self, visits: usize, callback: js_sys::Function) -> Option<String> {
+        let evaluation = self.evaluation.as_mut()?;
+        let completion = evaluation.step(visits, &mut wasm_loop_observer(callback))?;
 
-        on_finish.call0(&JsValue::NULL).unwrap();
+        self.evaluation = None;
+        Some(completion.to_string())
+    }
 
-        JsValue::UNDEFINED
+    /// How many expressions the current evaluation has visited.
+    pub fn visited(&self) -> usize {
+        self.evaluation
+            .as_ref()
+            .map_or(0, |evaluation| evaluation.visited_count())
     }
-}
 
-fn wasm_loop_callback(
-    callback: js_sys::Function,
-    performance: web_sys::Performance,
-) -> impl FnMut(&BTreeSet<Expression>) {
-    let time_start = performance.now();
-
-    let mut smallest_expression: Option<Expression> = None;
-
-    move |evaluations: &BTreeSet<Expression>| {
-        if smallest_expression.is_none() {
-            smallest_expression = Some(evaluations.iter().next().unwrap().clone());
-            callback
-                .call2(
-                    &JsValue::NULL,
-                    &JsValue::from(smallest_expression.as_ref().unwrap().to_string()),
-                    &JsValue::from(0.0),
-                )
-                .unwrap();
-        }
+    /// Drops the current evaluation.
+    pub fn stop(&mut self) {
+        self.evaluation = None;
+    }
+}
 
-        let candidate = evaluations.iter().next().unwrap();
+/// Parses the expression and the options of an evaluation, returning any error as a message.
+fn prepare(
+    runtime: &Runtime,
+    expression: &str,
+    max_visited: Option<usize>,
+    max_length: Option<usize>,
+    max_seconds: Option<f64>,
+    strategy: Option<String>,
+) -> Result<(Expression, EvalOptions), String> {
+    let expression = runtime
+        .parse_expression(expression)
+        .map_err(|error| error.to_string())?;
+
+    let strategy = match strategy.as_deref().map(str::parse::<SearchStrategy>) {
+        Some(Ok(strategy)) => strategy,
+        Some(Err(error)) => return Err(error.to_string()),
+        None => SearchStrategy::default(),
+    };
+
+    let limits = EvalLimits {
+        max_visited,
+        max_length,
+        max_time: max_seconds.map(Duration::from_secs_f64),
+        ..EvalLimits::none()
+    };
+
+    Ok((expression, EvalOptions::new(limits, strategy)))
+}
 
-        if candidate < &smallest_expression.clone().unwrap() {
-            smallest_expression = Some(candidate.clone());
+/// Calls `callback` with every new best expression and the seconds it took to find it. The
+/// evaluation stops if the callback returns `false`.
+fn wasm_loop_observer(callback: js_sys::Function) -> impl EvalObserver {
+    move |event: EvalEvent<'_>| {
+        let EvalEvent::NewBest(candidate, elapsed) = event else {
+            return ControlFlow::Continue(());
+        };
 
-            let time_elapsed = (performance.now() - time_start) / 1000.0;
-            callback
-                .call2(
-                    &JsValue::NULL,
-                    &candidate.to_string().into(),
-                    &time_elapsed.into(),
-                )
-                .unwrap();
+        let result = callback
+            .call2(
+                &JsValue::NULL,
+                &candidate.to_string().into(),
+                &elapsed.as_secs_f64().into(),
+            )
+            .unwrap();
+
+        match result.as_bool() {
+            Some(false) => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
         }
     }
 }
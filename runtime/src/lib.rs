@@ -4,7 +4,25 @@ mod engine;
 mod matching;
 mod parser;
 
+pub use engine::completion::{CompletionError, CompletionOptions, ReductionOrder};
+pub use engine::confluence::{ConfluenceReport, CriticalPair, Joinability};
+pub use engine::cost::{CostFunction, CostModel};
+pub use engine::equality::Equivalence;
+pub use engine::eval::Evaluation;
+pub use engine::host::HostFunction;
+pub use engine::limits::{CancellationToken, Completion, EvalLimits, EvalOutcome, Limit};
+pub use engine::normal::{NormalForm, NormalForms};
+pub use engine::observer::{EvalEvent, EvalObserver, EvalStats, VisitedSoFar, WithVisited};
+pub use engine::options::{EvalOptions, Grouping, MatchMode};
+pub use engine::profile::{DefinitionProfile, EvalProfile};
+pub use engine::reduction::{Reduction, ReductionStrategy};
+pub use engine::strategy::{ParseStrategyError, SearchStrategy};
+pub use engine::termination::{GrowingDefinition, Growth, LpoProof, Precedence, TerminationReport};
+pub use engine::trace::{Bindings, Derivation, DerivationStep, Direction, Rewrite, Trace};
+pub use engine::Definition;
 pub use engine::Expression;
+pub use engine::Guard;
 pub use engine::Runtime;
 pub use engine::Structure;
+pub use engine::Token;
 pub use parser::{parse_file, resolvers};
@@ -1,10 +1,13 @@
 #[cfg(feature = "repl")]
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
 
 #[cfg(feature = "repl")]
-use clap::Parser;
+use clap::{Parser, Subcommand};
 #[cfg(feature = "repl")]
-use pink_runtime::{parse_file, Runtime, Structure};
+use pink_runtime::{
+    parse_file, CompletionOptions, EvalLimits, EvalOptions, Grouping, MatchMode, Precedence,
+    ReductionOrder, ReductionStrategy, Runtime, SearchStrategy, Structure, Token,
+};
 
 #[cfg(feature = "repl")]
 mod repl;
@@ -19,15 +22,22 @@ fn main() {
 fn main() {
     let cli = Cli::parse();
 
+    if let Some(command) = cli.command {
+        return match command {
+            Command::Termination { path } => print!("{}", parse(path).check_termination()),
+            Command::Confluence { path } => print!("{}", parse(path).check_confluence()),
+            Command::Complete {
+                path,
+                lpo,
+                precedence,
+                output,
+            } => complete(parse(path), lpo, precedence, output),
+        };
+    }
+
     // TODO: uggo
     let runtime = match cli.path {
-        Some(path) => match parse_file(path) {
-            Ok(structure) => structure,
-            Err(err) => {
-                eprintln!("Error while parsing file: {}", err);
-                std::process::exit(1);
-            }
-        },
+        Some(path) => parse(path),
 
         None => Runtime::new(BTreeMap::from([(
             "instrinsic".to_owned(),
@@ -35,7 +45,29 @@ fn main() {
         )])),
     };
 
-    match repl::run(runtime, cli.debug) {
+    let limits = EvalLimits {
+        max_visited: cli.max_visited,
+        max_length: cli.max_length,
+        max_time: cli.timeout.map(Duration::from_secs_f64),
+        ..EvalLimits::none()
+    };
+
+    let options = EvalOptions {
+        trace: cli.trace,
+        profile: cli.stats,
+        threads: cli.threads,
+        match_mode: cli.all_matches.then_some(MatchMode::All),
+        grouping: cli.tree.then_some(Grouping::Tree),
+        ..EvalOptions::new(limits, cli.strategy)
+    };
+
+    let mode = match (cli.reduce, cli.normal_forms) {
+        (Some(strategy), _) => repl::Mode::Reduce(strategy),
+        (None, true) => repl::Mode::NormalForms,
+        (None, false) => repl::Mode::Search,
+    };
+
+    match repl::run(runtime, cli.debug, options, mode) {
         Ok(()) => (),
         Err(err) => {
             eprintln!("Error while running REPL: {}", err);
@@ -44,12 +76,148 @@ fn main() {
     };
 }
 
+#[cfg(feature = "repl")]
+fn parse(path: PathBuf) -> Runtime {
+    match parse_file(path) {
+        Ok(runtime) => {
+            for definition in runtime.unregistered_host_definitions() {
+                eprintln!("Warning: `{definition}` never applies, since its host function isn't registered");
+            }
+
+            runtime
+        }
+        Err(err) => {
+            eprintln!("Error while parsing file: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "repl")]
+fn complete(runtime: Runtime, lpo: bool, precedence: Vec<String>, output: Option<PathBuf>) {
+    let order = match lpo {
+        false => ReductionOrder::ShortLex,
+        true => {
+            let tokens = precedence.into_iter().map(|name| {
+                match runtime.reserved().any(|literal| *literal == name) {
+                    true => Token::Literal(name),
+                    false => Token::Element(name),
+                }
+            });
+
+            ReductionOrder::Lpo(Precedence::from_ranking(tokens))
+        }
+    };
+
+    let options = CompletionOptions {
+        order,
+        ..CompletionOptions::default()
+    };
+
+    let pink = match runtime.complete(&options) {
+        Ok(structure) => structure.to_pink(),
+        Err(err) => {
+            eprintln!("Could not complete the definitions: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    match output {
+        Some(output) => {
+            if let Err(err) = std::fs::write(&output, pink) {
+                eprintln!("Could not write {}: {err}", output.display());
+                std::process::exit(1);
+            }
+        }
+        None => print!("{pink}"),
+    }
+}
+
 #[cfg(feature = "repl")]
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     path: Option<PathBuf>,
 
     #[clap(short, long, default_value_t = false)]
     debug: bool,
+
+    /// Order in which expressions are explored (dfs, bfs, best-first, iterative-deepening or beam:<width>)
+    #[clap(short, long, default_value_t = SearchStrategy::default())]
+    strategy: SearchStrategy,
+
+    /// Instead of searching, rewrite one path to a normal form (innermost, outermost or
+    /// parallel-outermost)
+    #[clap(short, long)]
+    reduce: Option<ReductionStrategy>,
+
+    /// Print every expression that can't be rewritten any further instead of the smallest one
+    /// (toggle with `:normal` in the REPL)
+    #[clap(long, default_value_t = false, conflicts_with = "reduce")]
+    normal_forms: bool,
+
+    /// Print how the smallest expression was derived after each evaluation
+    #[clap(short, long, default_value_t = false)]
+    trace: bool,
+
+    /// Print statistics after each evaluation, with how often every definition was tried and
+    /// applied (toggle with `:stats` in the REPL)
+    #[clap(long, default_value_t = false)]
+    stats: bool,
+
+    /// Number of threads used to expand expressions (0 uses every core)
+    #[clap(short = 'j', long, default_value_t = 1)]
+    threads: usize,
+
+    /// Apply every matching definition to each window, instead of only the first one
+    #[clap(long, default_value_t = false)]
+    all_matches: bool,
+
+    /// Only rewrite whole subterms delimited by parentheses or braces, in every structure
+    #[clap(long, default_value_t = false)]
+    tree: bool,
+
+    /// Stop evaluating after visiting this many expressions
+    #[clap(long)]
+    max_visited: Option<usize>,
+
+    /// Discard expressions with more tokens than this
+    #[clap(long)]
+    max_length: Option<usize>,
+
+    /// Stop evaluating after this many seconds
+    #[clap(long)]
+    timeout: Option<f64>,
+}
+
+#[cfg(feature = "repl")]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check whether rewriting with the definitions of a file (and its dependencies) always ends
+    Termination { path: PathBuf },
+
+    /// Check whether the order in which the definitions of a file (and its dependencies) are
+    /// applied changes the result
+    Confluence { path: PathBuf },
+
+    /// Derive an equivalent set of definitions where the order they are applied in doesn't
+    /// matter, and write it as a new file
+    Complete {
+        path: PathBuf,
+
+        /// Orient definitions with the lexicographic path ordering instead of by length
+        #[clap(long, default_value_t = false)]
+        lpo: bool,
+
+        /// Comma separated tokens for the lexicographic path ordering, greatest first
+        #[clap(long, value_delimiter = ',', requires = "lpo")]
+        precedence: Vec<String>,
+
+        /// Where to write the result, instead of printing it
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
 }
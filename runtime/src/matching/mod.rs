@@ -3,17 +3,120 @@ mod test;
 
 use std::collections::BTreeMap;
 
-use crate::engine::{PatternToken, Token};
+use crate::engine::{PatternToken, Sort, Token};
 
-type SingleBindings<'a, 'b> = BTreeMap<&'a String, &'b Token>;
-type SpreadBindings<'a, 'b> = BTreeMap<&'a String, &'b [Token]>;
+type SingleBindings<'a, 'b, T = Token> = BTreeMap<&'a String, &'b T>;
+type SpreadBindings<'a, 'b, T = Token> = BTreeMap<&'a String, &'b [T]>;
 
-type Bindings<'a, 'b> = (SingleBindings<'a, 'b>, SpreadBindings<'a, 'b>);
+pub(crate) type Bindings<'a, 'b, T = Token> =
+    (SingleBindings<'a, 'b, T>, SpreadBindings<'a, 'b, T>);
 
+/// Literals that open and close a group.
+const GROUPS: [(&str, &str); 2] = [("(", ")"), ("{", "}")];
+
+/// The literal between the items of a group.
+const SEPARATOR: &str = ",";
+
+/// A token that opens or closes a group, together with the kind of group, or that separates its
+/// items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Bracket {
+    Open(usize),
+    Close(usize),
+    Separator,
+}
+
+impl Bracket {
+    pub fn of(token: &Token) -> Option<Self> {
+        let Token::Literal(literal) = token else {
+            return None;
+        };
+
+        if literal == SEPARATOR {
+            return Some(Bracket::Separator);
+        }
+
+        GROUPS.iter().enumerate().find_map(|(kind, (open, close))| {
+            if literal == open {
+                Some(Bracket::Open(kind))
+            } else if literal == close {
+                Some(Bracket::Close(kind))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Every bracket and the separator, as tokens.
+    pub fn tokens() -> impl Iterator<Item = (Token, Bracket)> {
+        GROUPS
+            .iter()
+            .enumerate()
+            .flat_map(|(kind, (open, close))| {
+                [
+                    (Token::Literal(open.to_string()), Bracket::Open(kind)),
+                    (Token::Literal(close.to_string()), Bracket::Close(kind)),
+                ]
+            })
+            .chain([(Token::Literal(SEPARATOR.to_string()), Bracket::Separator)])
+    }
+}
+
+/// Whether `tokens` is a run of whole subterms, so every group opened in it is also closed in it
+/// and the other way around.
+pub(crate) fn is_grouped<T>(tokens: &[T], bracket: impl Fn(&T) -> Option<Bracket>) -> bool {
+    let mut open = Vec::new();
+
+    for token in tokens {
+        match bracket(token) {
+            Some(Bracket::Open(kind)) => open.push(kind),
+            Some(Bracket::Close(kind)) if open.pop() != Some(kind) => return false,
+            _ => (),
+        }
+    }
+
+    open.is_empty()
+}
+
+/// Whether `tokens` is a single item of a group, like `f ( b )` in `{ a, f ( b ) }`, so a run of
+/// whole subterms without separators outside of them.
+pub(crate) fn is_item<T>(tokens: &[T], bracket: impl Fn(&T) -> Option<Bracket>) -> bool {
+    let mut depth = 0;
+
+    for token in tokens {
+        match bracket(token) {
+            Some(Bracket::Open(_)) => depth += 1,
+            Some(Bracket::Close(_)) => depth -= 1,
+            Some(Bracket::Separator) if depth == 0 => return false,
+            _ => (),
+        }
+    }
+
+    !tokens.is_empty() && is_grouped(tokens, bracket)
+}
+
+/// Matches a pattern against an expression.
+///
+/// Variables whose sort is a set never match here, since that takes rewriting.
 pub fn get_match_bindings<'a, 'b>(
     pattern: &'a [PatternToken],
     expression: &'b [Token],
 ) -> Option<Bindings<'a, 'b>> {
+    let has_sort = |token: &Token, sort: &Sort| sort.contains(token).unwrap_or(false);
+    get_match_bindings_where(pattern, expression, &|_| true, &has_sort, &Bracket::of)
+}
+
+/// Same as [`get_match_bindings`] for tokens or interned symbols, but variables can only be bound
+/// to runs of tokens that `accepts`, and sorted variables to tokens that `has_sort`. Single
+/// variables are checked as a run of one token. Spread variables only bind runs of whole
+/// subterms, according to `bracket`.
+pub(crate) fn get_match_bindings_where<'a, 'b, T: PartialEq>(
+    pattern: &'a [PatternToken<T>],
+    expression: &'b [T],
+    accepts: &impl Fn(&[T]) -> bool,
+    has_sort: &impl Fn(&T, &Sort<T>) -> bool,
+    bracket: &impl Fn(&T) -> Option<Bracket>,
+) -> Option<Bindings<'a, 'b, T>> {
     let mut single_bindings = BTreeMap::new();
     let mut spread_bindings = BTreeMap::new();
 
@@ -22,103 +125,195 @@ pub fn get_match_bindings<'a, 'b>(
         expression,
         &mut single_bindings,
         &mut spread_bindings,
+        accepts,
+        has_sort,
+        bracket,
     )?;
 
     Some((single_bindings, spread_bindings))
 }
 
-fn match_bindings_recurse<'a, 'b>(
-    pattern: &'a [PatternToken],
-    expression: &'b [Token],
-    single_bindings: &mut SingleBindings<'a, 'b>,
-    spread_bindings: &mut SpreadBindings<'a, 'b>,
+fn match_bindings_recurse<'a, 'b, T: PartialEq>(
+    pattern: &'a [PatternToken<T>],
+    expression: &'b [T],
+    single_bindings: &mut SingleBindings<'a, 'b, T>,
+    spread_bindings: &mut SpreadBindings<'a, 'b, T>,
+    accepts: &impl Fn(&[T]) -> bool,
+    has_sort: &impl Fn(&T, &Sort<T>) -> bool,
+    bracket: &impl Fn(&T) -> Option<Bracket>,
 ) -> Option<()> {
-    let (pattern_token, token) = match (pattern.get(0), expression.get(0)) {
-        (Some(pattern_token), Some(token)) => (pattern_token, token),
-        (None, None) => return Some(()),
-        _ => return None,
+    // Optional spread variables can match the end of the expression, so it might be empty here
+    let Some(pattern_token) = pattern.first() else {
+        return expression.is_empty().then_some(());
     };
+    let token = expression.first();
 
     match pattern_token {
         PatternToken::Concrete(pattern_token) => {
-            if pattern_token == token {
+            if Some(pattern_token) == token {
                 match_bindings_recurse(
                     &pattern[1..],
                     &expression[1..],
                     single_bindings,
                     spread_bindings,
+                    accepts,
+                    has_sort,
+                    bracket,
                 )
             } else {
                 None
             }
         }
 
-        PatternToken::Variable(variable) => match single_bindings.get(variable) {
-            Some(existing_binding) => {
-                if existing_binding == &token {
+        PatternToken::Wildcard => {
+            if token.is_none() || !accepts(&expression[..1]) {
+                return None;
+            }
+
+            match_bindings_recurse(
+                &pattern[1..],
+                &expression[1..],
+                single_bindings,
+                spread_bindings,
+                accepts,
+                has_sort,
+                bracket,
+            )
+        }
+
+        PatternToken::Sorted(_, sort) if !token.is_some_and(|token| has_sort(token, sort)) => None,
+
+        PatternToken::Variable(variable) | PatternToken::Sorted(variable, _) => {
+            let token = token?;
+
+            match single_bindings.get(variable) {
+                Some(existing_binding) => {
+                    if existing_binding == &token {
+                        match_bindings_recurse(
+                            &pattern[1..],
+                            &expression[1..],
+                            single_bindings,
+                            spread_bindings,
+                            accepts,
+                            has_sort,
+                            bracket,
+                        )
+                    } else {
+                        None
+                    }
+                }
+                None => {
+                    if !accepts(&expression[..1]) {
+                        return None;
+                    }
+
+                    single_bindings.insert(variable, token);
                     match_bindings_recurse(
                         &pattern[1..],
                         &expression[1..],
                         single_bindings,
                         spread_bindings,
+                        accepts,
+                        has_sort,
+                        bracket,
                     )
-                } else {
-                    None
                 }
             }
-            None => {
-                single_bindings.insert(variable, token);
-                match_bindings_recurse(
+        }
+
+        PatternToken::SpreadVariable(variable)
+        | PatternToken::OptionalSpreadVariable(variable)
+        | PatternToken::ItemVariable(variable) => {
+            // A later occurrence has to bind the same tokens as the first one
+            if let Some(&existing_binding) = spread_bindings.get(variable) {
+                if !expression.starts_with(existing_binding) {
+                    return None;
+                }
+
+                return match_bindings_recurse(
                     &pattern[1..],
-                    &expression[1..],
+                    &expression[existing_binding.len()..],
                     single_bindings,
                     spread_bindings,
-                )
+                    accepts,
+                    has_sort,
+                    bracket,
+                );
             }
-        },
 
-        PatternToken::SpreadVariable(variable) => {
-            for i in 1..=expression.len() {
+            let min = match pattern_token {
+                PatternToken::OptionalSpreadVariable(_) => 0,
+                _ => 1,
+            };
+
+            // Single variables bound while trying a binding have to be forgotten if it fails
+            let bound = single_bindings.clone();
+
+            for i in min..=expression.len() {
                 let binding = &expression[0..i];
 
-                match spread_bindings.get(variable) {
-                    Some(existing_binding) => {
-                        if existing_binding == &binding
-                            && match_bindings_recurse(
-                                &pattern[1..],
-                                &expression[i..],
-                                single_bindings,
-                                spread_bindings,
-                            )
-                            .is_some()
-                        {
-                            return Some(());
-                        };
-                    }
-                    None => {
-                        // TODO: It is kind of ugly to remove and add a binding each time but I'm not sure it can be done in a better way.
-                        // It kind of seems this is the reason the pure recursive approach didn't work in the first place.
-                        // The fact that without this the only test that failed is the one that failed with the recursive approach,
-                        // this makes me think that this is the reason.
-                        spread_bindings.insert(variable, binding);
-
-                        if match_bindings_recurse(
-                            &pattern[1..],
-                            &expression[i..],
-                            single_bindings,
-                            spread_bindings,
-                        )
-                        .is_some()
-                        {
-                            return Some(());
-                        }
+                let shaped = match pattern_token {
+                    PatternToken::ItemVariable(_) => is_item(binding, bracket),
+                    _ => is_grouped(binding, bracket),
+                };
 
-                        spread_bindings.remove(variable);
-                    }
+                if !shaped || !accepts(binding) {
+                    continue;
+                }
+
+                // TODO: It is kind of ugly to remove and add a binding each time but I'm not sure it can be done in a better way.
+                // It kind of seems this is the reason the pure recursive approach didn't work in the first place.
+                // The fact that without this the only test that failed is the one that failed with the recursive approach,
+                // this makes me think that this is the reason.
+                spread_bindings.insert(variable, binding);
+
+                if match_bindings_recurse(
+                    &pattern[1..],
+                    &expression[i..],
+                    single_bindings,
+                    spread_bindings,
+                    accepts,
+                    has_sort,
+                    bracket,
+                )
+                .is_some()
+                {
+                    return Some(());
                 }
+
+                spread_bindings.remove(variable);
+                single_bindings.clone_from(&bound);
             }
 
             None
         }
     }
 }
+
+/// Builds a pattern with the bindings of another one.
+pub(crate) fn build<'a, 'b, T: Clone>(
+    to: &[PatternToken<T>],
+    (single_bindings, spread_bindings): Bindings<'a, 'b, T>,
+) -> Option<(Vec<T>, Bindings<'a, 'b, T>)> {
+    let mut result = Vec::new();
+
+    for token in to {
+        match token {
+            PatternToken::Concrete(token) => result.push(token.clone()),
+            PatternToken::Variable(name) | PatternToken::Sorted(name, _) => {
+                let binding = *single_bindings.get(name)?;
+                result.push(binding.clone());
+            }
+            // Nothing is bound to it
+            PatternToken::Wildcard => return None,
+            PatternToken::SpreadVariable(name)
+            | PatternToken::OptionalSpreadVariable(name)
+            | PatternToken::ItemVariable(name) => {
+                let binding = spread_bindings.get(name)?;
+                result.extend_from_slice(binding);
+            }
+        };
+    }
+
+    Some((result, (single_bindings, spread_bindings)))
+}
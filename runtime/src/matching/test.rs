@@ -2,8 +2,8 @@ use super::*;
 
 #[test]
 fn match_empty() {
-    let pattern = vec![];
-    let expression = vec![];
+    let pattern: Vec<PatternToken> = vec![];
+    let expression: Vec<Token> = vec![];
 
     let bindings = get_match_bindings(&pattern, &expression);
 
@@ -178,6 +178,36 @@ fn match_capture_spread() {
     assert_eq!(spread_bindings, expected);
 }
 
+#[test]
+fn match_optional_spread() {
+    let literal = |text: &str| Token::Literal(text.to_owned());
+
+    let pattern = vec![
+        PatternToken::Concrete(literal("token_1")),
+        PatternToken::OptionalSpreadVariable("p".to_owned()),
+        PatternToken::Concrete(literal("token_2")),
+        PatternToken::OptionalSpreadVariable("p".to_owned()),
+    ];
+
+    let expression = [literal("token_1"), literal("token_2")];
+    let (_, spread_bindings) = get_match_bindings(&pattern, &expression).unwrap();
+
+    let p = "p".to_owned();
+    let expected = BTreeMap::from([(&p, [].as_slice())]);
+    assert_eq!(spread_bindings, expected);
+
+    // Both have to be empty
+    let expression = [literal("token_1"), literal("token_2"), literal("variable")];
+    assert!(get_match_bindings(&pattern, &expression).is_none());
+
+    let pattern = vec![
+        PatternToken::Concrete(literal("token_1")),
+        PatternToken::SpreadVariable("p".to_owned()),
+        PatternToken::Concrete(literal("token_2")),
+    ];
+    assert!(get_match_bindings(&pattern, &[literal("token_1"), literal("token_2")]).is_none());
+}
+
 #[test]
 fn match_capture_fail() {
     let pattern = vec![
@@ -198,3 +228,112 @@ fn match_capture_fail() {
 
     assert!(bindings.is_none());
 }
+
+#[test]
+fn match_whole_groups() {
+    let literal = |text: &str| Token::Literal(text.to_owned());
+
+    let pattern = vec![
+        PatternToken::SpreadVariable("p".to_owned()),
+        PatternToken::Concrete(literal("b")),
+        PatternToken::Concrete(literal(")")),
+    ];
+
+    let expression = vec![literal("("), literal("a"), literal("b"), literal(")")];
+    let is_grouped = |run: &[Token]| is_grouped(run, Bracket::of);
+
+    assert!(is_grouped(&expression));
+
+    // `p` would have to bind `( a`, which spread variables never do
+    assert!(get_match_bindings(&pattern, &expression).is_none());
+
+    let pattern = vec![
+        PatternToken::Variable("x".to_owned()),
+        PatternToken::Concrete(literal("a")),
+        PatternToken::Concrete(literal("b")),
+        PatternToken::Concrete(literal(")")),
+    ];
+
+    // `x` would have to bind `(`
+    assert!(get_match_bindings(&pattern, &expression).is_some());
+    assert!(get_match_bindings_where(
+        &pattern,
+        &expression,
+        &is_grouped,
+        &|_, _| false,
+        &Bracket::of
+    )
+    .is_none());
+}
+
+#[test]
+fn match_items() {
+    let literal = |text: &str| Token::Literal(text.to_owned());
+
+    let pattern = vec![
+        PatternToken::Concrete(literal("{")),
+        PatternToken::ItemVariable("x".to_owned()),
+        PatternToken::Concrete(literal(",")),
+        PatternToken::SpreadVariable("rest".to_owned()),
+        PatternToken::Concrete(literal("}")),
+    ];
+
+    // { f ( a , b ) , c , d }
+    let expression: Vec<_> = ["{", "f", "(", "a", ",", "b", ")", ",", "c", ",", "d", "}"]
+        .into_iter()
+        .map(literal)
+        .collect();
+
+    let (_, spread_bindings) = get_match_bindings(&pattern, &expression).unwrap();
+
+    assert_eq!(spread_bindings[&"x".to_owned()], &expression[1..7]);
+    assert_eq!(spread_bindings[&"rest".to_owned()], &expression[8..11]);
+
+    assert!(is_item(&expression[1..7], Bracket::of));
+    assert!(!is_item(&expression[1..9], Bracket::of));
+    assert!(!is_item(&expression[2..4], Bracket::of));
+    assert!(!is_item(&[], Bracket::of));
+}
+
+#[test]
+fn match_sorted_variables() {
+    let pattern = vec![
+        PatternToken::Sorted("x".to_owned(), Sort::Element),
+        PatternToken::Sorted("y".to_owned(), Sort::Literal),
+    ];
+
+    let element = Token::Element("a".to_owned());
+    let literal = Token::Literal("+".to_owned());
+
+    assert!(get_match_bindings(&pattern, &[element.clone(), literal.clone()]).is_some());
+    assert!(get_match_bindings(&pattern, &[literal.clone(), element.clone()]).is_none());
+    assert!(get_match_bindings(&pattern, &[element.clone(), element.clone()]).is_none());
+
+    // Sets take rewriting
+    let pattern = vec![PatternToken::Sorted(
+        "x".to_owned(),
+        Sort::Set(Token::Literal("Naturals".to_owned())),
+    )];
+    assert!(get_match_bindings(&pattern, &[element]).is_none());
+}
+
+#[test]
+fn wildcards_do_not_bind() {
+    let pattern = vec![
+        PatternToken::Wildcard,
+        PatternToken::Variable("x".to_owned()),
+        PatternToken::Wildcard,
+    ];
+
+    let expression = vec![
+        Token::Element("a".to_owned()),
+        Token::Element("b".to_owned()),
+        Token::Element("c".to_owned()),
+    ];
+
+    let (single_bindings, _) = get_match_bindings(&pattern, &expression).unwrap();
+
+    let x = "x".to_owned();
+    assert_eq!(single_bindings, BTreeMap::from([(&x, &expression[1])]));
+    assert!(build(&pattern, (single_bindings, BTreeMap::new())).is_none());
+}
@@ -14,7 +14,10 @@ use crate::engine::{Runtime, Structure, StructureError};
 
 use self::{
     resolvers::{FileResolver, Resolver, StdResolver},
-    standalone::{definition, domain, get_domain, get_reserved, parse_use, reserve},
+    standalone::{
+        definition, domain, get_domain, get_reserved, has_numerals, parse_cost, parse_grouping,
+        parse_match, parse_numerals, parse_use, reserve,
+    },
 };
 
 /// TODO: Maybe make a type for inputs with comments and without. To further ensure safety.
@@ -56,6 +59,10 @@ fn parse_into_runtime<R: Resolver>(
     let (input, domain) = domain(&input)?;
     let (input, reserved) = reserve(input)?;
     let (input, dependencies) = parse_use(input)?;
+    let (input, match_mode) = parse_match(input)?;
+    let (input, grouping) = parse_grouping(input)?;
+    let (input, numerals) = parse_numerals(input)?;
+    let (input, weights) = parse_cost(input)?;
 
     for dependency in dependencies {
         match runtime.get(&dependency) {
@@ -94,22 +101,37 @@ fn parse_into_runtime<R: Resolver>(
 
     let full_domain = domain.iter().chain(get_domain(runtime)).collect();
     let full_reserved = reserved.iter().chain(get_reserved(runtime)).collect();
+    let full_numerals = numerals || has_numerals(runtime);
 
     let mut definitions = Vec::new();
     let mut input = input;
 
-    while let Ok((rest, mut parsed_definitions)) = definition(input, &full_domain, &full_reserved) {
+    // Anything left that isn't a definition is an error, rather than the end of the file
+    while !input.trim().is_empty() {
+        let (rest, mut parsed_definitions) =
+            definition(input, &full_domain, &full_reserved, full_numerals)?;
         input = rest;
         definitions.append(&mut parsed_definitions);
     }
 
-    let structure = match Structure::create(domain, reserved, definitions) {
+    let mut structure = match Structure::create(domain, reserved, definitions) {
         Ok(structure) => structure,
         Err(StructureError::DomainAndReservedOverlap { culprit }) => {
             return Err(ParseError::DomainAndReservedOverlap { culprit })
         }
     };
 
+    if let Some(match_mode) = match_mode {
+        structure.set_match_mode(match_mode);
+    }
+
+    if let Some(grouping) = grouping {
+        structure.set_grouping(grouping);
+    }
+
+    structure.set_numerals(numerals);
+    structure.set_weights(weights);
+
     runtime.insert(name.to_string(), Some(structure));
 
     Ok(())
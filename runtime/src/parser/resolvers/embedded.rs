@@ -27,9 +27,10 @@ macro_rules! get_program {
 #[derive(Debug, Default)]
 pub struct EmbeddedStdResolver {}
 
-const PROGRAMS: [EmbeddedProgram; 5] = [
+const PROGRAMS: [EmbeddedProgram; 6] = [
     get_program!("core"),
     get_program!("function calls"),
+    get_program!("int"),
     get_program!("peano"),
     get_program!("propositional logic"),
     get_program!("sets"),
@@ -25,6 +25,7 @@ pub struct FileResolver {
 }
 
 impl FileResolver {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
             cwd: PathBuf::from(""),
@@ -51,6 +52,7 @@ impl Resolver for FileResolver {
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 struct FileResolverError {
     path: PathBuf,
 }
@@ -14,6 +14,7 @@ pub struct MapResolver {
 
 // #[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl MapResolver {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
             data: HashMap::new(),
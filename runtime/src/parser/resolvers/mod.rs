@@ -5,6 +5,7 @@ mod std_resolver;
 mod embedded;
 
 mod chain;
+#[allow(dead_code)]
 mod function;
 mod map;
 #[cfg(test)]
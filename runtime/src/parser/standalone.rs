@@ -1,6 +1,6 @@
 //! Module for standalone parsers
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use nom::{
     bytes::complete::{tag as nom_tag, take_until as nom_take_until, take_while, take_while1},
@@ -8,13 +8,16 @@ use nom::{
 };
 
 use crate::{
-    engine::{Definition, Expression, PatternToken, Token},
-    Runtime,
+    engine::{
+        int::{numeral_len, Integer},
+        Definition, Expression, Guard, PatternToken, Sort, Token,
+    },
+    Grouping, MatchMode, Runtime,
 };
 
 use super::{ParseError, PartialRuntime};
 
-/// Crate level documentation?
+// Crate level documentation?
 
 /// Trims the start of the input
 fn trim_start(input: &str) -> &str {
@@ -70,14 +73,14 @@ fn keyword_set<'a>(
     let input = trim_start(input);
     let (input, elements) = take_until("}")(input)?;
 
-    return Ok((
+    Ok((
         input,
         elements
             .split(',')
             .map(|s| s.trim().to_owned())
             .filter(|s| !s.is_empty())
             .collect(),
-    ));
+    ))
 }
 
 pub fn domain(input: &str) -> Result<(&str, BTreeSet<String>), ParseError> {
@@ -93,6 +96,108 @@ pub fn parse_use(input: &str) -> Result<(&str, BTreeSet<String>), ParseError> {
     keyword_set(input, "use")
 }
 
+/// Parses an optional `keyword { .. }` part of the head. Only the keyword followed by a brace
+/// starts it, anything else is a definition.
+fn head_section<'a>(
+    input: &'a str,
+    keyword: &'a str,
+) -> Result<(&'a str, Option<BTreeSet<String>>), ParseError> {
+    let is_present = tag(keyword)(trim_start(input))
+        .and_then(|rest| tag("{")(trim_start(rest)))
+        .is_ok();
+
+    if !is_present {
+        return Ok((input, None));
+    }
+
+    let (rest, items) = keyword_set(input, keyword)?;
+    Ok((rest, Some(items)))
+}
+
+/// Parses the optional `match { first }` or `match { all }` part of the head.
+pub fn parse_match(input: &str) -> Result<(&str, Option<MatchMode>), ParseError> {
+    let (rest, Some(modes)) = head_section(input, "match")? else {
+        return Ok((input, None));
+    };
+    let modes: Vec<_> = modes.iter().map(String::as_str).collect();
+
+    match modes.as_slice() {
+        ["first"] => Ok((rest, Some(MatchMode::First))),
+        ["all"] => Ok((rest, Some(MatchMode::All))),
+        _ => Err(ParseError::Expected {
+            expected: "`first` or `all`".to_string(),
+            found: modes.join(", "),
+        }),
+    }
+}
+
+/// Parses the optional `grouping { flat }` or `grouping { tree }` part of the head.
+pub fn parse_grouping(input: &str) -> Result<(&str, Option<Grouping>), ParseError> {
+    let (rest, Some(groupings)) = head_section(input, "grouping")? else {
+        return Ok((input, None));
+    };
+    let groupings: Vec<_> = groupings.iter().map(String::as_str).collect();
+
+    match groupings.as_slice() {
+        ["flat"] => Ok((rest, Some(Grouping::Flat))),
+        ["tree"] => Ok((rest, Some(Grouping::Tree))),
+        _ => Err(ParseError::Expected {
+            expected: "`flat` or `tree`".to_string(),
+            found: groupings.join(", "),
+        }),
+    }
+}
+
+/// Parses the optional `numerals { integers }` part of the head.
+pub fn parse_numerals(input: &str) -> Result<(&str, bool), ParseError> {
+    let (rest, Some(numerals)) = head_section(input, "numerals")? else {
+        return Ok((input, false));
+    };
+    let numerals: Vec<_> = numerals.iter().map(String::as_str).collect();
+
+    match numerals.as_slice() {
+        ["integers"] => Ok((rest, true)),
+        _ => Err(ParseError::Expected {
+            expected: "`integers`".to_string(),
+            found: numerals.join(", "),
+        }),
+    }
+}
+
+/// The numeral at the start of the input, written the way the runtime writes it, and the rest.
+fn numeral(input: &str) -> Option<(&str, Token)> {
+    let len = numeral_len(input)?;
+    let integer: Integer = input[..len].parse().ok()?;
+
+    Some((&input[len..], integer.to_token()))
+}
+
+/// Parses the optional `cost { token = weight, ... }` part of the head.
+pub fn parse_cost(input: &str) -> Result<(&str, BTreeMap<String, u64>), ParseError> {
+    let (rest, Some(weights)) = head_section(input, "cost")? else {
+        return Ok((input, BTreeMap::new()));
+    };
+
+    let weights = weights
+        .iter()
+        .map(|weight| {
+            // The last `=` separates them, so that `=` itself can be given a weight
+            let parsed = weight.rsplit_once('=').and_then(|(token, value)| {
+                let token = token.trim();
+                let value = value.trim().parse().ok()?;
+                (!token.is_empty()).then(|| (token.to_string(), value))
+            });
+
+            parsed.ok_or_else(|| ParseError::Expected {
+                expected: "`token = weight`".to_string(),
+                found: weight.clone(),
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok((rest, weights))
+}
+
 pub fn get_reserved(runtime: &PartialRuntime) -> Vec<&String> {
     runtime
         .iter()
@@ -109,17 +214,36 @@ pub fn get_domain(runtime: &PartialRuntime) -> Vec<&String> {
         .collect()
 }
 
+pub fn has_numerals(runtime: &PartialRuntime) -> bool {
+    runtime
+        .values()
+        .any(|structure| structure.as_ref().is_some_and(|s| s.has_numerals()))
+}
+
 /// Parses the *whole* input string as an expression
-pub fn pattern(input: &str, domain: &Vec<&String>, reserved: &Vec<&String>) -> Vec<PatternToken> {
+///
+/// With `numerals`, every decimal integer is an element.
+pub fn pattern(
+    input: &str,
+    domain: &Vec<&String>,
+    reserved: &Vec<&String>,
+    numerals: bool,
+) -> Vec<PatternToken> {
     if input.is_empty() {
         return Vec::new();
     }
 
     let input = input.trim();
 
+    if let Some((rest, token)) = numeral(input).filter(|_| numerals) {
+        let mut pattern = pattern(rest, domain, reserved, numerals);
+        pattern.insert(0, PatternToken::Concrete(token));
+        return pattern;
+    }
+
     for literal in reserved.clone() {
         if let Ok(rest) = tag(literal.as_str())(input) {
-            let mut pattern = pattern(rest, domain, reserved);
+            let mut pattern = pattern(rest, domain, reserved, numerals);
             pattern.insert(
                 0,
                 PatternToken::Concrete(Token::Literal(literal.to_string())),
@@ -130,7 +254,7 @@ pub fn pattern(input: &str, domain: &Vec<&String>, reserved: &Vec<&String>) -> V
 
     for element in domain.clone() {
         if let Ok(rest) = tag(element.as_str())(input) {
-            let mut pattern = pattern(rest, domain, reserved);
+            let mut pattern = pattern(rest, domain, reserved, numerals);
             pattern.insert(
                 0,
                 PatternToken::Concrete(Token::Element(element.to_string())),
@@ -149,18 +273,63 @@ pub fn pattern(input: &str, domain: &Vec<&String>, reserved: &Vec<&String>) -> V
     // Spread variables
     // TODO: Uggo
     let result: IResult<_, _> = nom_tag("...")(rest);
-    match result {
-        Ok((rest, _)) => {
-            let mut pattern = pattern(rest, domain, reserved);
-            pattern.insert(0, PatternToken::SpreadVariable(variable.to_string()));
-            pattern
-        }
-        Err(_) => {
-            let mut pattern = pattern(rest, domain, reserved);
-            pattern.insert(0, PatternToken::Variable(variable.to_string()));
-            pattern
+    let (rest, token) = match result {
+        // Optional ones can also match nothing, and items match a single item of a group
+        Ok((rest, _)) => match (rest.strip_prefix('?'), item_annotation(rest, reserved)) {
+            (Some(rest), _) => (
+                rest,
+                PatternToken::OptionalSpreadVariable(variable.to_string()),
+            ),
+            (None, Some(rest)) => (rest, PatternToken::ItemVariable(variable.to_string())),
+            (None, None) => (rest, PatternToken::SpreadVariable(variable.to_string())),
+        },
+
+        // Variables with a sort, like `n: Naturals`, unless `:` is a token itself
+        Err(_) if rest.starts_with(':') && !reserved.iter().any(|literal| *literal == ":") => {
+            let (rest, sort) = sort(rest[1..].trim_start(), reserved);
+            (rest, PatternToken::Sorted(variable.to_string(), sort))
         }
+
+        Err(_) if variable == "_" => (rest, PatternToken::Wildcard),
+        Err(_) => (rest, PatternToken::Variable(variable.to_string())),
+    };
+
+    let mut pattern = pattern(rest, domain, reserved, numerals);
+    pattern.insert(0, token);
+    pattern
+}
+
+/// The rest of the input after `: item`, if it starts with it (and `:` is not a token itself).
+fn item_annotation<'a>(input: &'a str, reserved: &[&String]) -> Option<&'a str> {
+    if reserved.iter().any(|literal| *literal == ":") {
+        return None;
     }
+
+    let rest = input.strip_prefix(':')?.trim_start().strip_prefix("item")?;
+    let is_word_end = !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_');
+
+    is_word_end.then_some(rest)
+}
+
+/// The sort of a variable, after its `:`. Anything but `element` and `literal` is the name of a
+/// set.
+fn sort<'a>(input: &'a str, reserved: &[&String]) -> (&'a str, Sort) {
+    let result: IResult<_, _> = take_while1(|c: char| c.is_alphanumeric() || c == '_')(input);
+    let (rest, name) = result.unwrap_or_else(|_| {
+        let end = input.chars().next().map_or(0, char::len_utf8);
+        (&input[end..], &input[..end])
+    });
+
+    let sort = match name {
+        "element" => Sort::Element,
+        "literal" => Sort::Literal,
+        set if reserved.iter().any(|literal| *literal == set) => {
+            Sort::Set(Token::Literal(set.to_string()))
+        }
+        set => Sort::Set(Token::Element(set.to_string())),
+    };
+
+    (rest, sort)
 }
 
 /// Parses the *whole* input string as an expression
@@ -171,6 +340,12 @@ pub fn expression(input: &str, runtime: &Runtime) -> Result<Expression, ParseErr
 
     let input = input.trim();
 
+    if let Some((rest, token)) = numeral(input).filter(|_| runtime.has_numerals()) {
+        let mut expression = expression(rest, runtime)?;
+        expression.tokens.insert(0, token);
+        return Ok(expression);
+    }
+
     for literal in runtime.reserved() {
         if let Ok(rest) = tag(literal.as_str())(input) {
             let mut expression = expression(rest, runtime)?;
@@ -194,10 +369,111 @@ pub fn expression(input: &str, runtime: &Runtime) -> Result<Expression, ParseErr
     Err(ParseError::UknownToken(input.to_string()))
 }
 
+/// The name of the host function in a right hand side like `@add`, if it is one.
+fn host_function(rhs: &str) -> Option<&str> {
+    let name = rhs.trim().strip_prefix('@')?;
+
+    let is_name = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    is_name.then_some(name)
+}
+
+/// Splits the input at the first whitespace separated word outside of brackets that `is_separator`
+/// accepts, given its position among the words. Returns what comes before and after that word.
+fn split_at_word(input: &str, is_separator: impl Fn(usize, &str) -> bool) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let mut words = 0;
+    let mut start = None;
+
+    for (i, c) in input.char_indices().chain([(input.len(), ' ')]) {
+        if !c.is_whitespace() {
+            start = start.or(Some(i));
+            continue;
+        }
+
+        let Some(begin) = start.take() else {
+            continue;
+        };
+
+        let word = &input[begin..i];
+        if depth == 0 && is_separator(words, word) {
+            return Some((&input[..begin], &input[i..]));
+        }
+
+        words += 1;
+        for c in word.chars() {
+            match c {
+                '(' | '{' => depth += 1,
+                ')' | '}' => depth -= 1,
+                _ => (),
+            }
+        }
+    }
+
+    None
+}
+
+/// Splits a right hand side like `y where x = 0` into the right side and its guard, which starts
+/// at the first `where` outside of brackets. An `if` is always part of the right side, like in
+/// `a and if x then y else z`.
+fn split_guard(rhs: &str) -> (&str, Option<&str>) {
+    match split_at_word(rhs, |i, word| i > 0 && word == "where") {
+        Some((rhs, guard)) => (rhs, Some(guard)),
+        None => (rhs, None),
+    }
+}
+
+/// Parses a guard, which is either two sides that have to be different, like `x != y`, or a
+/// condition that has to be rewritten to `true`, like `x = 0`.
+fn guard(
+    input: &str,
+    lhs: &[PatternToken],
+    domain: &Vec<&String>,
+    reserved: &Vec<&String>,
+    numerals: bool,
+) -> Result<Guard, ParseError> {
+    let guard = match split_at_word(input, |_, word| word == "!=") {
+        Some((a, b)) => Guard::Differ(
+            pattern(a, domain, reserved, numerals),
+            pattern(b, domain, reserved, numerals),
+        ),
+        None => Guard::Normalises(pattern(input, domain, reserved, numerals)),
+    };
+
+    let variable = |token: &PatternToken| match token {
+        PatternToken::Variable(name)
+        | PatternToken::SpreadVariable(name)
+        | PatternToken::OptionalSpreadVariable(name)
+        | PatternToken::ItemVariable(name)
+        | PatternToken::Sorted(name, _) => Some(name.clone()),
+        PatternToken::Concrete(_) | PatternToken::Wildcard => None,
+    };
+
+    // Anything else would never be bound
+    for token in guard.tokens() {
+        let is_bound = match token {
+            PatternToken::Concrete(_) => true,
+            token => variable(token).is_some_and(|name| {
+                lhs.iter()
+                    .any(|bound| variable(bound) == Some(name.clone()))
+            }),
+        };
+
+        if !is_bound {
+            return Err(ParseError::Expected {
+                expected: "only variables of the left side in the guard".to_string(),
+                found: input.trim().to_string(),
+            });
+        }
+    }
+
+    Ok(guard)
+}
+
 pub fn definition<'a>(
     input: &'a str,
     domain: &Vec<&String>,
     reserved: &Vec<&String>,
+    numerals: bool,
 ) -> Result<(&'a str, Vec<Definition>), ParseError> {
     let (rest, definition) = take_until(";")(input)?;
 
@@ -227,12 +503,50 @@ pub fn definition<'a>(
 
     let lhs = if double { &lhs[..lhs.len() - 1] } else { lhs };
 
-    let lhs = pattern(lhs, domain, reserved);
-    let rhs = pattern(rhs, domain, reserved);
+    let lhs = pattern(lhs, domain, reserved, numerals);
+
+    let (rhs, guard) = match split_guard(rhs) {
+        (_, Some(guard)) if double => {
+            return Err(ParseError::Expected {
+                expected: "=> before a guard".to_string(),
+                found: format!("<=> ... where {}", guard.trim()),
+            });
+        }
+        (rhs, Some(input)) => (rhs, Some(guard(input, &lhs, domain, reserved, numerals)?)),
+        (rhs, None) => (rhs, None),
+    };
+
+    // A right hand side like `@add` is built by a host function
+    if let Some(function) = host_function(rhs) {
+        if double {
+            return Err(ParseError::Expected {
+                expected: "=> before a host function".to_string(),
+                found: format!("<=> @{function}"),
+            });
+        }
+
+        let mut definition = Definition::with_host(lhs, function);
+        definition.set_guard(guard);
+
+        return Ok((rest, vec![definition]));
+    }
+
+    let rhs = pattern(rhs, domain, reserved, numerals);
+
+    // `_` doesn't bind anything, so there is nothing to write where it ends up on the right side
+    let wildcard = |side: &[PatternToken]| side.contains(&PatternToken::Wildcard);
+    if wildcard(&rhs) || (double && wildcard(&lhs)) {
+        return Err(ParseError::Expected {
+            expected: "a variable instead of `_` on the right side".to_string(),
+            found: definition.trim().to_string(),
+        });
+    }
 
     let mut result = Vec::new();
 
-    result.push(Definition::new(lhs.to_vec(), rhs.to_vec()));
+    let mut definition = Definition::new(lhs.to_vec(), rhs.to_vec());
+    definition.set_guard(guard);
+    result.push(definition);
 
     if double {
         result.push(Definition::new(rhs, lhs));
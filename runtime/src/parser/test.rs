@@ -1,6 +1,10 @@
 use crate::engine::Definition;
+use crate::engine::Guard;
 use crate::engine::PatternToken;
+use crate::engine::Sort;
 use crate::parser::standalone::pattern;
+use crate::Grouping;
+use crate::MatchMode;
 use std::collections::BTreeSet;
 
 use crate::engine::Token;
@@ -42,7 +46,12 @@ fn pattern_test() {
 
     assert_eq!(
         vec![PatternToken::Concrete(Token::Literal("r1".to_owned()))],
-        pattern("r1", &domain.iter().collect(), &reserved.iter().collect())
+        pattern(
+            "r1",
+            &domain.iter().collect(),
+            &reserved.iter().collect(),
+            false
+        )
     );
 
     assert_eq!(
@@ -55,7 +64,52 @@ fn pattern_test() {
         pattern(
             "r1 d2 x r3",
             &domain.iter().collect(),
-            &reserved.iter().collect()
+            &reserved.iter().collect(),
+            false
+        )
+    );
+}
+
+#[test]
+fn sorted_pattern() {
+    let (_, domain) = domain("domain { d1 }").unwrap();
+    let (_, reserved) = reserve("reserve { r1, Set }").unwrap();
+
+    assert_eq!(
+        vec![
+            PatternToken::Sorted("x".to_owned(), Sort::Element),
+            PatternToken::Sorted("y".to_owned(), Sort::Literal),
+            PatternToken::Sorted("z".to_owned(), Sort::Set(Token::Literal("Set".to_owned()))),
+            PatternToken::Concrete(Token::Literal("r1".to_owned())),
+            PatternToken::Wildcard,
+            PatternToken::Variable("_w".to_owned()),
+        ],
+        pattern(
+            "x: element y:literal z: Set r1 _ _w",
+            &domain.iter().collect(),
+            &reserved.iter().collect(),
+            false
+        )
+    );
+}
+
+#[test]
+fn optional_spread_pattern() {
+    let (_, domain) = domain("domain { d1 }").unwrap();
+    let (_, reserved) = reserve("reserve { r1 }").unwrap();
+
+    assert_eq!(
+        vec![
+            PatternToken::SpreadVariable("x".to_owned()),
+            PatternToken::OptionalSpreadVariable("y".to_owned()),
+            PatternToken::Concrete(Token::Literal("r1".to_owned())),
+            PatternToken::ItemVariable("z".to_owned()),
+        ],
+        pattern(
+            "x... y...? r1 z...: item",
+            &domain.iter().collect(),
+            &reserved.iter().collect(),
+            false
         )
     );
 }
@@ -72,7 +126,12 @@ fn pattern_with_comments() {
 
     assert_eq!(
         vec![PatternToken::Concrete(Token::Literal("r1".to_owned()))],
-        pattern("r1", &domain.iter().collect(), &reserved.iter().collect())
+        pattern(
+            "r1",
+            &domain.iter().collect(),
+            &reserved.iter().collect(),
+            false
+        )
     );
 
     assert_eq!(
@@ -85,7 +144,8 @@ fn pattern_with_comments() {
         pattern(
             input.as_str(),
             &domain.iter().collect(),
-            &reserved.iter().collect()
+            &reserved.iter().collect(),
+            false
         )
     );
 }
@@ -101,19 +161,116 @@ fn simple_definition() {
         "r1 x r2",
         &domain.iter().collect(),
         &reserved.iter().collect(),
+        false,
     );
     let rhs = pattern(
         "r2 d d2",
         &domain.iter().collect(),
         &reserved.iter().collect(),
+        false,
     );
 
     let expected = Definition::new(lhs, rhs);
 
     assert_eq!(
         (" lol", vec![expected]),
-        definition(input, &domain.iter().collect(), &reserved.iter().collect()).unwrap(),
+        definition(
+            input,
+            &domain.iter().collect(),
+            &reserved.iter().collect(),
+            false
+        )
+        .unwrap(),
+    );
+}
+
+#[test]
+fn host_definition() {
+    let (_, domain) = domain("domain { d1 }").unwrap();
+    let (_, reserved) = reserve("reserve { + }").unwrap();
+    let domain = domain.iter().collect();
+    let reserved = reserved.iter().collect();
+
+    let lhs = pattern("x + d1", &domain, &reserved, false);
+    let expected = Definition::with_host(lhs, "add_one");
+
+    assert_eq!(
+        (" lol", vec![expected]),
+        definition("x + d1 => @add_one; lol", &domain, &reserved, false).unwrap(),
     );
+    assert!(definition("x + d1 <=> @add_one;", &domain, &reserved, false).is_err());
+}
+
+#[test]
+fn wildcards_stay_on_the_left() {
+    let (_, domain) = domain("domain { d1 }").unwrap();
+    let (_, reserved) = reserve("reserve { + }").unwrap();
+    let domain = domain.iter().collect();
+    let reserved = reserved.iter().collect();
+
+    assert!(definition("_ + x => x;", &domain, &reserved, false).is_ok());
+    assert!(definition("x + d1 => _;", &domain, &reserved, false).is_err());
+    assert!(definition("d1 <=> _ + d1;", &domain, &reserved, false).is_err());
+    assert!(definition("_ + d1 <=> d1;", &domain, &reserved, false).is_err());
+}
+
+#[test]
+fn guarded_definition() {
+    let (_, domain) = domain("domain { a, b, false }").unwrap();
+    let (_, reserved) = reserve("reserve { +, =, if, then, else }").unwrap();
+    let domain = domain.iter().collect();
+    let reserved = reserved.iter().collect();
+
+    let parse = |input| definition(input, &domain, &reserved, true).unwrap().1;
+
+    let mut expected = Definition::new(
+        pattern("x + y", &domain, &reserved, true),
+        pattern("y", &domain, &reserved, true),
+    );
+    expected.set_guard(Some(Guard::Normalises(pattern(
+        "x = 0", &domain, &reserved, true,
+    ))));
+    assert_eq!(parse("x + y => y where x = 0;"), [expected]);
+
+    let mut expected = Definition::new(
+        pattern("x = y", &domain, &reserved, true),
+        pattern("false", &domain, &reserved, true),
+    );
+    expected.set_guard(Some(Guard::Differ(
+        pattern("x", &domain, &reserved, true),
+        pattern("y", &domain, &reserved, true),
+    )));
+    assert_eq!(parse("x = y => false where x != y;"), [expected]);
+
+    // Conditionals stay part of the right side, wherever they are
+    for (input, rhs) in [
+        ("x + a => if x then a else b;", "if x then a else b"),
+        ("x + a => a = if x then a else b;", "a = if x then a else b"),
+    ] {
+        let parsed = parse(input);
+        assert_eq!(parsed[0].guard(), None);
+        assert_eq!(
+            parsed,
+            [Definition::new(
+                pattern("x + a", &domain, &reserved, true),
+                pattern(rhs, &domain, &reserved, true),
+            )]
+        );
+    }
+
+    assert!(definition("x + y <=> y where x = 0;", &domain, &reserved, true).is_err());
+    assert!(definition("x + y => y where z = 0;", &domain, &reserved, true).is_err());
+}
+
+#[test]
+fn bad_definitions_are_reported() {
+    let mut resolver = resolvers::MapResolver::new();
+    resolver.insert(
+        "main".to_string(),
+        "domain { a } reserve { } use { } f x => a where y = a; a => a;".to_string(),
+    );
+
+    assert!(parse("main", &mut resolver).is_err());
 }
 
 #[test]
@@ -129,18 +286,26 @@ fn multi_line_definition() {
         "r1 x r2",
         &domain.iter().collect(),
         &reserved.iter().collect(),
+        false,
     );
     let rhs = pattern(
         "r2 d d2",
         &domain.iter().collect(),
         &reserved.iter().collect(),
+        false,
     );
 
     let expected = Definition::new(lhs, rhs);
 
     assert_eq!(
         (" lol", vec![expected]),
-        definition(input, &domain.iter().collect(), &reserved.iter().collect()).unwrap(),
+        definition(
+            input,
+            &domain.iter().collect(),
+            &reserved.iter().collect(),
+            false
+        )
+        .unwrap(),
     );
 }
 
@@ -155,6 +320,7 @@ fn whole_parse_test() {
         "r1 x r2 => r2 d d2;",
         &domain.iter().collect(),
         &reserved.iter().collect(),
+        false,
     )
     .unwrap();
 
@@ -189,6 +355,7 @@ fn parse_dependencies() {
         "r4 => d4 r5;",
         &domain.iter().collect(),
         &reserved.iter().collect(),
+        false,
     )
     .unwrap();
 
@@ -247,3 +414,88 @@ fn parse_cycle() {
 
     parse_file(input_path.into()).expect_err("Should find the circular dependency");
 }
+
+#[test]
+fn match_mode_test() {
+    assert_eq!(
+        (" a => b;", Some(MatchMode::All)),
+        parse_match(" match { all } a => b;").unwrap()
+    );
+    assert_eq!(
+        ("", Some(MatchMode::First)),
+        parse_match("match { first }").unwrap()
+    );
+    assert!(parse_match("match { every }").is_err());
+}
+
+#[test]
+fn match_mode_is_optional() {
+    assert_eq!(
+        ("matched x => x;", None),
+        parse_match("matched x => x;").unwrap()
+    );
+    assert_eq!(
+        ("match x => x;", None),
+        parse_match("match x => x;").unwrap()
+    );
+}
+
+#[test]
+fn grouping_test() {
+    assert_eq!(
+        (" a => b;", Some(Grouping::Tree)),
+        parse_grouping(" grouping { tree } a => b;").unwrap()
+    );
+    assert_eq!(
+        ("grouping x => x;", None),
+        parse_grouping("grouping x => x;").unwrap()
+    );
+    assert!(parse_grouping("grouping { forest }").is_err());
+}
+
+#[test]
+fn numerals_test() {
+    assert_eq!(
+        (" a => b;", true),
+        parse_numerals(" numerals { integers } a => b;").unwrap()
+    );
+    assert_eq!(
+        ("numerals x => x;", false),
+        parse_numerals("numerals x => x;").unwrap()
+    );
+    assert!(parse_numerals("numerals { reals }").is_err());
+
+    let domain = Vec::new();
+    let (_, reserved) = reserve("reserve { + }").unwrap();
+    let reserved = reserved.iter().collect();
+
+    assert_eq!(
+        vec![
+            PatternToken::Variable("x".to_owned()),
+            PatternToken::Concrete(Token::Literal("+".to_owned())),
+            PatternToken::Concrete(Token::Element("-12".to_owned())),
+        ],
+        pattern("x + -0012", &domain, &reserved, true)
+    );
+    assert_eq!(
+        PatternToken::Variable("12".to_owned()),
+        pattern("12", &domain, &reserved, false)[0]
+    );
+}
+
+#[test]
+fn cost_test() {
+    assert_eq!(
+        (
+            " a => b;",
+            BTreeMap::from([("successor".to_string(), 3), ("=".to_string(), 0)])
+        ),
+        parse_cost(" cost { successor = 3, = = 0 } a => b;").unwrap()
+    );
+    assert_eq!(
+        ("cost x => x;", BTreeMap::new()),
+        parse_cost("cost x => x;").unwrap()
+    );
+    assert!(parse_cost("cost { successor }").is_err());
+    assert!(parse_cost("cost { successor = -1 }").is_err());
+}
@@ -1,5 +1,4 @@
-use std::collections::BTreeSet;
-use std::time::Instant;
+use std::ops::ControlFlow;
 
 use once_cell::sync::Lazy;
 
@@ -12,7 +11,10 @@ use termion::{
     style::{Bold, Reset},
 };
 
-use pink_runtime::{Expression, Runtime};
+use pink_runtime::{
+    CancellationToken, Completion, EvalEvent, EvalObserver, EvalOptions, Expression,
+    ReductionStrategy, Runtime,
+};
 
 const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
 const HISTORY_FILE: &str = ".pink-repl-history";
@@ -20,7 +22,20 @@ const HISTORY_FILE: &str = ".pink-repl-history";
 // TODO: Would be nice if this was `const`
 static PROMPT: Lazy<String> = Lazy::new(|| format!("{}{}>>{} ", Fg(Magenta), Bold, Reset));
 
-pub fn run(runtime: Runtime, debug: bool) -> Result<()> {
+/// What the REPL does with each expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Print the cheapest evaluation.
+    Search,
+
+    /// Rewrite along a single path, see [`Runtime::reduce`].
+    Reduce(ReductionStrategy),
+
+    /// Print every expression that can't be rewritten any further.
+    NormalForms,
+}
+
+pub fn run(runtime: Runtime, debug: bool, mut options: EvalOptions, mut mode: Mode) -> Result<()> {
     if debug {
         println!("Debug mode enabled");
         println!("{runtime}");
@@ -29,6 +44,15 @@ pub fn run(runtime: Runtime, debug: bool) -> Result<()> {
     print!("Welcome to {}{}pink!{}", Fg(Magenta), Bold, Reset);
     println!(" (v{})", VERSION.unwrap_or("unknown"));
 
+    // While reading a line, Ctrl-C is handled by rustyline. While evaluating, it cancels the evaluation.
+    let cancellation = CancellationToken::new();
+    let handler_cancellation = cancellation.clone();
+    if let Err(err) = ctrlc::set_handler(move || handler_cancellation.cancel()) {
+        println!("Could not set Ctrl-C handler, evaluations can't be cancelled: {err}");
+    }
+
+    options.limits.cancellation = Some(cancellation.clone());
+
     let mut rl = Editor::<(), FileHistory>::new()?;
     if rl.load_history(HISTORY_FILE).is_err() {
         println!("No previous history.");
@@ -41,6 +65,52 @@ pub fn run(runtime: Runtime, debug: bool) -> Result<()> {
             Ok(line) => {
                 rl.add_history_entry(line.as_str()).unwrap();
 
+                if line.trim() == ":stats" {
+                    options.profile = !options.profile;
+                    match options.profile {
+                        true => println!("Printing statistics after each evaluation"),
+                        false => println!("Not printing statistics anymore"),
+                    }
+                    continue;
+                }
+
+                if let Some(strategy) = line.trim().strip_prefix(":reduce") {
+                    match strategy.trim() {
+                        "" => {
+                            mode = Mode::Search;
+                            println!("Searching for the smallest evaluation");
+                        }
+                        strategy => match strategy.parse() {
+                            Ok(strategy) => {
+                                mode = Mode::Reduce(strategy);
+                                println!("Reducing {strategy} to a normal form");
+                            }
+                            Err(err) => println!("{err}"),
+                        },
+                    }
+                    continue;
+                }
+
+                if line.trim() == ":normal" {
+                    mode = match mode {
+                        Mode::NormalForms => {
+                            println!("Searching for the smallest evaluation");
+                            Mode::Search
+                        }
+                        _ => {
+                            println!("Printing every normal form");
+                            Mode::NormalForms
+                        }
+                    };
+                    continue;
+                }
+
+                if let Some(equation) = line.trim().strip_prefix(":eq") {
+                    cancellation.reset();
+                    prove_equal(&runtime, equation, &options);
+                    continue;
+                }
+
                 let expression = match runtime.parse_expression(line.as_str()) {
                     Ok(expression) => expression,
                     Err(err) => {
@@ -49,7 +119,43 @@ pub fn run(runtime: Runtime, debug: bool) -> Result<()> {
                     }
                 };
 
-                let _evaluations = runtime.evaluations(expression, &mut repl_loop_callback());
+                cancellation.reset();
+
+                match mode {
+                    Mode::Search => (),
+                    Mode::Reduce(strategy) => {
+                        reduce(&runtime, expression, strategy, &options);
+                        continue;
+                    }
+                    Mode::NormalForms => {
+                        normal_forms(&runtime, expression, &options);
+                        continue;
+                    }
+                }
+
+                let outcome =
+                    runtime.evaluate_with(expression, &options, &mut repl_loop_observer());
+
+                match outcome.completion {
+                    Completion::Exhaustive => (),
+                    Completion::CutOff(limit) => println!(
+                        "Stopped early ({limit}), best expression found: {}",
+                        outcome.best()
+                    ),
+                    Completion::Cancelled => {
+                        println!("Cancelled, best expression found: {}", outcome.best())
+                    }
+                }
+
+                if let Some(trace) = &outcome.trace {
+                    if let Some(derivation) = runtime.derivation(trace, outcome.best()) {
+                        print!("{derivation}");
+                    }
+                }
+
+                if let Some(profile) = &outcome.profile {
+                    print!("{profile}");
+                }
             }
 
             Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
@@ -66,25 +172,89 @@ pub fn run(runtime: Runtime, debug: bool) -> Result<()> {
     rl.save_history(HISTORY_FILE)
 }
 
-fn repl_loop_callback() -> impl FnMut(&BTreeSet<Expression>) {
-    let time_start = Instant::now();
+/// Handles `:eq a, b`, splitting on the first comma outside of braces and parentheses.
+fn prove_equal(runtime: &Runtime, equation: &str, options: &EvalOptions) {
+    let mut depth = 0;
+    let comma = equation.char_indices().find(|&(_, c)| {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            _ => (),
+        }
+        c == ',' && depth == 0
+    });
+
+    let Some((comma, _)) = comma else {
+        println!("Usage: :eq <expression>, <expression>");
+        return;
+    };
+
+    let parse = |input: &str| runtime.parse_expression(input.trim());
+    let (a, b) = match (parse(&equation[..comma]), parse(&equation[comma + 1..])) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(err), _) | (_, Err(err)) => {
+            println!("{err}");
+            return;
+        }
+    };
+
+    let equivalence = runtime.prove_equal(a, b, &options.limits).to_string();
+    println!("{}", equivalence.trim_end());
+}
+
+fn reduce(
+    runtime: &Runtime,
+    expression: Expression,
+    strategy: ReductionStrategy,
+    options: &EvalOptions,
+) {
+    let reduction = runtime.reduce(expression, strategy, &options.limits);
+
+    match reduction.completion {
+        Completion::Exhaustive => {
+            println!("{} ({} steps)", reduction.expression, reduction.steps)
+        }
+        Completion::CutOff(limit) => println!(
+            "Stopped early ({limit}) after {} steps: {}",
+            reduction.steps, reduction.expression
+        ),
+        Completion::Cancelled => println!(
+            "Cancelled after {} steps: {}",
+            reduction.steps, reduction.expression
+        ),
+    }
+}
 
-    let mut smallest_expression: Option<Expression> = None;
+fn normal_forms(runtime: &Runtime, expression: Expression, options: &EvalOptions) {
+    let normal_forms = runtime.normal_forms(expression, options);
+    print!("{normal_forms}");
 
-    move |evaluations: &BTreeSet<Expression>| {
-        if smallest_expression.is_none() {
-            smallest_expression = Some(evaluations.iter().next().unwrap().clone());
+    match normal_forms.completion {
+        Completion::Exhaustive => {
+            if normal_forms.forms.len() > 1 {
+                println!("The definitions are not confluent for this expression");
+            }
         }
+        Completion::CutOff(limit) => println!("Stopped early ({limit}), there might be more"),
+        Completion::Cancelled => println!("Cancelled, there might be more"),
+    }
+}
 
-        let candidate = evaluations.iter().next().unwrap();
+fn repl_loop_observer() -> impl EvalObserver {
+    // The first expression is the original one
+    let mut first = true;
 
-        if candidate < &smallest_expression.clone().unwrap() {
-            smallest_expression = Some(candidate.clone());
-            println!(
-                "Found smaller expression: {} (took {:.2}s)",
-                candidate,
-                (Instant::now() - time_start).as_secs_f32()
-            );
+    move |event: EvalEvent<'_>| {
+        if let EvalEvent::NewBest(candidate, elapsed) = event {
+            if !std::mem::take(&mut first) {
+                println!(
+                    "Found better expression: {} (took {:.2}s)",
+                    candidate,
+                    elapsed.as_secs_f32()
+                );
+            }
         }
+
+        ControlFlow::Continue(())
     }
 }
@@ -13,8 +13,10 @@ export function getWorker(): Promise<{
 	subscribe: (onResult: (result: string, timeElapsed: number) => void) => {
 		unsubscribe: () => void
 	},
+	stop: () => void,
 	close: () => void,
 	status: Writable<Status>,
+	visited: Writable<number>,
 }> {
 	return new Promise((resolve, reject) => {
 		if (!browser) {
@@ -22,6 +24,7 @@ export function getWorker(): Promise<{
 		}
 
 		let status = writable<Status>("idle")
+		let visited = writable(0)
 
 		const url = new URL("worker", import.meta.url)
 		// const worker = new CacaWorker()
@@ -36,6 +39,10 @@ export function getWorker(): Promise<{
 				onResult && onResult(event.data.result, event.data.timeElapsed)
 			}
 
+			if (event.data.type === "progress") {
+				visited.set(event.data.visited)
+			}
+
 			if (event.data.type === "error") {
 				status.set("invalid")
 			}
@@ -65,6 +72,7 @@ export function getWorker(): Promise<{
 
 			evaluate: (expression: string) => {
 				status.set("processing")
+				visited.set(0)
 				worker.postMessage({
 					type: "evaluate",
 					expression
@@ -72,6 +80,7 @@ export function getWorker(): Promise<{
 			},
 
 			status,
+			visited,
 
 			subscribe: (onResultNew: (result: string, timeElapsed: number) => void) => {
 				onResult = onResultNew
@@ -85,6 +94,10 @@ export function getWorker(): Promise<{
 				}
 			},
 
+			stop: () => {
+				worker.postMessage({ type: "stop" })
+			},
+
 			close: () => {
 				worker.terminate()
 			},
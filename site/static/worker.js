@@ -10,7 +10,33 @@ async function init_wasm() {
 
 	const runtime = AsyncRuntime.new();
 
-	let expression = "";
+	// How many expressions to visit before letting the worker handle messages again
+	const STEP_SIZE = 1000;
+
+	// Identifies the running evaluation, so that a pending step of a replaced one does nothing
+	let generation = 0;
+
+	function step(current) {
+		if (current !== generation) {
+			return;
+		}
+
+		const completion = runtime.step(STEP_SIZE, (result, timeElapsed) => {
+			self.postMessage({
+				type: "result",
+				result,
+				timeElapsed,
+			})
+		});
+
+		if (completion) {
+			self.postMessage({ type: "done", completion })
+			return;
+		}
+
+		self.postMessage({ type: "progress", visited: runtime.visited() })
+		setTimeout(() => step(current), 0);
+	}
 
 	self.postMessage({ type: "ready" })
 
@@ -20,32 +46,35 @@ async function init_wasm() {
 				runtime.send_program(event.data.name, event.data.content);
 				break;
 			case "parseWithMainAs":
+				generation++;
 				runtime.parse_with_main(event.data.name);
 				break;
 			case "evaluate":
-				expression = event.data.expression
-				const error = await runtime.evaluations(
-					expression,
-					(result, timeElapsed) => {
-						self.postMessage({
-							type: "result",
-							result,
-							timeElapsed,
-						})
-					},
-					() => {
-						self.postMessage({ type: "done" })
-					},
-					self.performance
-				);
-
-				if (error) {
+				generation++;
+
+				try {
+					runtime.start(
+						event.data.expression,
+						event.data.maxVisited,
+						event.data.maxLength,
+						event.data.maxSeconds,
+						event.data.strategy,
+					);
+				} catch (error) {
+					// The program didn't parse, or the expression or options are invalid
 					self.postMessage({
 						type: "error",
 						error,
 					})
+					break;
 				}
 
+				step(generation);
+				break;
+			case "stop":
+				generation++;
+				runtime.stop();
+				self.postMessage({ type: "done", completion: "search was cancelled" })
 				break;
 		}
 	}